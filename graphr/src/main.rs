@@ -1,10 +1,10 @@
-
-use clap::{Parser,Subcommand};
+use clap::{Parser,Subcommand,ValueEnum};
 use std::collections::BTreeMap;
-use petgraph::dot::{Dot, Config};
+use std::io::{self, Read, Write};
+use std::path::Path;
 use graphrlib::*;
 const JSONDATABASE: &str  = ".graph/graph.json";
-const CURRENTTAGS: &str  = ".graph/current_tags.json";
+const SQLITEDATABASE: &str  = ".graph/graph.sqlite";
 /// Command line interface
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -17,84 +17,178 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize the databse
-    Init,
+    Init {
+        /// Store the database in sqlite instead of json (for large databases)
+        #[clap(long = "sqlite")]
+        sqlite: bool,
+    },
 
     /// Get the database and inject that into the stdout.
     Get,
 
-    /// Get Nodes that have a given substring
+    /// Get Nodes that belong to the given template
     GetSimilar {
         name:String,
+        /// Also nodes made with earlier versions of the template
+        #[clap(long = "all-versions")]
+        all_versions: bool,
         database: Option<String>
     },
 
-    /// Add a calculation to the database.
-    NewCalculation {
-        name: String,
-        command : String,
-        },
     /// Inspect a node
-    Inspect {name: String, 
-        #[clap(default_value = "data")] 
+    Inspect {name: String,
+        #[clap(default_value = "data")]
         datafolder: String
         },
-    /// add tags to given nodes
-    AddTag {
 
-        #[clap(long = "tag", required = true)]
-        tag: Vec<String>,
+    /// Register a data node in the template
+    TemplateRegisterDnode {
+        name: String,
         /// Database in the string format
         database: Option<String>
     },
-    /// Set all the tags for the given (sub)database.
-    SetTags {
 
-        #[clap(long = "tag", required = true)]
-        tags: Vec<String>,
+    /// Register a calculation node in the template.
+    /// Inputs are marked with input(<name>) and outputs with output(<name>)
+    TemplateRegisterCnode {
+        name: String,
+        command: String,
         /// Database in the string format
         database: Option<String>
     },
-    /// Remove tags to given nodes
-    RemoveTag {
 
-        #[clap(long = "tag", required = true)]
-        tag: Vec<String>,
+    /// List the versions of a calculation template (the current one first): id, version and command
+    TemplateVersions {
+        name: String,
         /// Database in the string format
         database: Option<String>
     },
-    /// Select nodes by tag
-    SelectTag {
-        /// Tags to include
-        #[clap(long = "tag", default_values_t = Vec::<String>::new())]
-        tags:Vec<String>,
-        /// Tags to exclude
-        #[clap(long = "notag",default_values_t = Vec::<String>::new())]
-        notags:Vec<String>,
 
+    /// Set the resources a calculation template needs while running
+    TemplateSetResources {
+        name: String,
+        /// Number of job slots the calculation takes
+        #[clap(long = "cores", default_value_t = 1)]
+        cores: u32,
+        /// Memory in MB
+        #[clap(long = "memory")]
+        memory: Option<u64>,
         /// Database in the string format
         database: Option<String>
     },
+
+    /// Create an implementation of the template
+    TemplateCreateCalculation {
+        #[arg(
+            long = "leaf",
+            num_args = 2,  // Requires exactly 2 values per occurrence
+            help = "Name a root data node: <template name> <node id> (requires exactly 2 names)",
+        )]
+        leaf: Option<Vec<String>>,
+        #[arg(
+            long = "existing",
+            num_args = 2,
+            help = "Use an existing data node instead of calculating it again: <template name> <node id>",
+        )]
+        existing: Option<Vec<String>>,
+        /// Only create the calculations needed for the data template (can be repeated)
+        #[clap(long = "target")]
+        targets: Option<Vec<String>>,
+        #[arg(
+            long = "param",
+            num_args = 2,
+            help = "Value of a parameter of the calculation templates: <parameter name> <value>",
+        )]
+        param: Option<Vec<String>>,
+
+        /// Database in the string format
+        database: Option<String>
+    },
+
+    /// Create the calculations of all combinations of leaf names and parameter values.
+    /// Every --leaf and --param adds a value to the list of its template or parameter
+    TemplateCreateSweep {
+        #[arg(
+            long = "leaf",
+            num_args = 2,
+            help = "Name for a root data node: <template name> <node id>",
+        )]
+        leaf: Option<Vec<String>>,
+        #[arg(
+            long = "param",
+            num_args = 2,
+            help = "Value of a parameter: <parameter name> <value>",
+        )]
+        param: Option<Vec<String>>,
+        /// Combine the lists side by side instead of every value with every other
+        #[clap(long = "zip")]
+        zip: bool,
+        /// Write the table of the combinations and the data nodes they produced (tab separated) to the file
+        #[clap(long = "table")]
+        table: Option<String>,
+
+        /// Database in the string format
+        database: Option<String>
+    },
+
+    /// Merge the database into another database file
+    MergeInto {
+        /// Path of the database file to merge into
+        target: String,
+        /// Database in the string format
+        database: Option<String>
+    },
+
+    /// Select all nodes connected to a certain node.
     SelectSubbranch { name: String, database: Option<String>},
 
     /// Select all nodes that come to produce a certain node.
     SelectHistory {name:String, database:Option<String>},
 
     /// Select a part of the database by name
-    SelectName {        
+    SelectName {
         #[clap(long = "name", required = true)]
         names:Vec<String>,
 
         /// Database in the string format
         database: Option<String>},
 
+    /// Select nodes with a query, for example 'future("x") & template("p2") - id("^tmp")'
+    Select {query: String, database: Option<String>},
+
     /// Visualize the graph
     Show {
+        /// Group the nodes of every template
+        #[clap(long = "cluster")]
+        cluster: bool,
+        /// Number of trailing characters of the node id shown in the label (0 - none)
+        #[clap(long = "id-length", default_value_t = 6)]
+        id_length: usize,
+        /// Node to highlight (can be repeated)
+        #[clap(long = "highlight")]
+        highlight: Vec<String>,
+        /// Colour nodes that are stale, modified or missing in this data folder
+        #[clap(long = "datafolder")]
+        datafolder: Option<String>,
+        /// Output format
+        #[clap(long = "format", value_enum, default_value_t = ShowFormat::Dot)]
+        format: ShowFormat,
+        /// Show the template instead of the calculations
+        #[clap(long = "template")]
+        template: bool,
+        database: Option<String>
+    },
+    /// Write an interactive HTML page of the graph (open it in a browser)
+    View {
+        /// Folder that stores the data nodes, used to expand the commands
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
         database: Option<String>
     },
     /// Rename nodes
     Copy {
         #[arg(
-            long = "attach", 
+            long = "attach",
             num_args = 2,  // Requires exactly 2 values per occurrence
             help = "Specify a pair of names to attach (requires exactly 2 names)",
         )]
@@ -113,6 +207,153 @@ enum Commands {
         #[clap(long = "name", required = true)]
         names:Vec<String>,
     },
+    /// Move all calculations of a template to a new command (in the stored database) and print the invalidated calculations downstream.
+    /// Data of the new command keeps the data node the calculation used before, unless it is connected with --id or created with --new
+    OverwriteCalculation {
+        name: String,
+        command: String,
+        #[arg(
+            long = "id",
+            num_args = 3,
+            help = "Connect an existing data node: <calculation id> <data template> <data node id>",
+        )]
+        existing: Option<Vec<String>>,
+        #[arg(
+            long = "new",
+            num_args = 3,
+            help = "Create a data node: <calculation id> <data template> <data node id>",
+        )]
+        new: Option<Vec<String>>,
+    },
+    /// Add tags to the nodes of the given (sub)database in the stored database
+    AddTag {
+        #[clap(long = "tag", required = true)]
+        tags: Vec<String>,
+        /// Database in the string format
+        database: Option<String>
+    },
+    /// Replace the tags of the nodes of the given (sub)database in the stored database
+    SetTags {
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+        /// Database in the string format
+        database: Option<String>
+    },
+    /// Remove tags from the nodes of the given (sub)database in the stored database
+    RemoveTag {
+        #[clap(long = "tag", required = true)]
+        tags: Vec<String>,
+        /// Database in the string format
+        database: Option<String>
+    },
+    /// Set a metadata entry of the nodes of the given (sub)database in the stored database.
+    /// true/false, integers and floats are stored typed, everything else as text
+    SetMetadata {
+        key: String,
+        value: String,
+        /// Database in the string format
+        database: Option<String>
+    },
+    /// Remove a metadata entry from the nodes of the given (sub)database in the stored database
+    RemoveMetadata {
+        key: String,
+        /// Database in the string format
+        database: Option<String>
+    },
+    /// Select the nodes that have any of the tags (every node if none are given) and none of the excluded ones
+    SelectTag {
+        #[clap(long = "tag")]
+        tags: Vec<String>,
+        #[clap(long = "notag")]
+        exclude: Vec<String>,
+        /// Database in the string format
+        database: Option<String>
+    },
+
+    /// Run all calculations that have not been run successfully yet
+    Run {
+        /// Folder that stores the data nodes
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
+        /// Folder for the stdout and stderr of the calculations
+        #[clap(long = "logfolder", default_value = ".graph/logs")]
+        logfolder: String,
+        /// Continue with independent calculations after a failure
+        #[clap(long = "keep-going")]
+        keep_going: bool,
+        /// Number of job slots for independent calculations
+        #[clap(short = 'j', long = "jobs", default_value_t = 1)]
+        jobs: usize,
+        /// Total memory in MB available to the calculations
+        #[clap(long = "memory")]
+        memory: Option<u64>,
+    },
+
+    /// Re-run only the calculations needed for the target that are out of date
+    Update {
+        /// Node to bring up to date
+        target: String,
+        /// Folder that stores the data nodes
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
+        /// Folder for the stdout and stderr of the calculations
+        #[clap(long = "logfolder", default_value = ".graph/logs")]
+        logfolder: String,
+        /// Continue with independent calculations after a failure
+        #[clap(long = "keep-going")]
+        keep_going: bool,
+        /// Number of job slots for independent calculations
+        #[clap(short = 'j', long = "jobs", default_value_t = 1)]
+        jobs: usize,
+        /// Total memory in MB available to the calculations
+        #[clap(long = "memory")]
+        memory: Option<u64>,
+    },
+
+    /// Show which nodes are missing, up-to-date, modified or stale
+    Status {
+        /// Folder that stores the data nodes
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
+    },
+
+    /// Record the current content hashes of data nodes (all data nodes if none are named)
+    RegisterHashes {
+        #[clap(long = "name")]
+        names: Vec<String>,
+        /// Folder that stores the data nodes
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
+    },
+
+    /// Export the database for another workflow manager
+    Export {
+        #[clap(long = "format", value_enum, default_value = "snakemake")]
+        format: ExportFormat,
+        /// Folder that stores the data nodes
+        #[clap(long = "datafolder", default_value = "data")]
+        datafolder: String,
+        /// Conda environment file for every calculation (snakemake)
+        #[clap(long = "conda")]
+        conda: Option<String>,
+        #[arg(
+            long = "conda-env",
+            num_args = 2,
+            help = "Conda environment file of a calculation template: <template name> <file> (snakemake)",
+        )]
+        conda_env: Option<Vec<String>>,
+        /// Database in the string format
+        database: Option<String>
+    },
+
+    /// Show the journal of operations done on the database
+    Log,
+
+    /// Undo the last operation done on the database
+    Undo,
+
+    /// Rebuild the database from its journal
+    Replay,
 
     /// Find all outgoing nodes from one node and create a copy on some other node
     /// Used to quickly create calculations for new modifications
@@ -124,142 +365,477 @@ enum Commands {
 
 }
 
+/// Formats of graphr export
+#[derive(Clone, Copy, ValueEnum)]
+enum ShowFormat {
+    /// Graphviz (the options besides --template only apply here)
+    Dot,
+    /// Mermaid flowchart for Markdown documents
+    Mermaid,
+    /// GraphML for yEd or Gephi
+    Graphml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Snakemake,
+    Nextflow,
+    Make,
+    /// Workflow of the template
+    Cwl,
+    /// Job files for the cwl workflow, one per line
+    CwlJob,
+    /// W3C PROV-JSON provenance
+    ProvJson,
+    /// W3C PROV-N provenance
+    ProvN,
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Commands::Init  => {
-            let calculation_manager = CalculationManager{inputs:vec!["input_1".to_string()],
-                    outputs: vec!["output_2".to_string()],
-                    program: "python3 input_1 output_2".to_string()};
-
-            let copy_manager = CopyManager {name: "test".to_string(), origin: "another_test".to_string()};
-            
-            let mut calculation_nodes = BTreeMap::new();
-            calculation_nodes.insert("test".to_string(), CalculationNode{git_hash: "".to_string(), tags: Vec::new(), calculation: calculation_manager, copy: copy_manager});
-            let mut data_nodes = BTreeMap::new();
-            data_nodes.insert("test_data".to_string(), DataNode{save:true, tags:Vec::new(), copy: CopyManager::default()});                                                
-            let mut default_struct = JsonStorage{calculation_nodes: calculation_nodes, data_nodes: data_nodes};
-            default_struct.write(&"".to_string());
+        Commands::Init { sqlite } => {
+            if Path::new(JSONDATABASE).exists() || Path::new(SQLITEDATABASE).exists() {
+                eprintln!("Database {} already exists.", database_path());
+                std::process::exit(1);
+            }
+            let path = if *sqlite {SQLITEDATABASE} else {JSONDATABASE};
+            write_database(path, &mut Database::new());
         }
         Commands::Get  => {
-            let db = JsonStorage::new("".to_string());
+            let db = read_database(database_path());
             write_database_to_stream(&db);
         }
-        Commands::GetSimilar {name, database} => {
+        Commands::GetSimilar {name, all_versions, database} => {
             let db = get_database_input(database);
-            let name_list = db.get_similar_nodes(&name);
+            let nodes = if *all_versions {db.select_similar_versions(name.clone())} else {db.select_similar(name.clone())};
 
-            for name in name_list {
-                println!("{}",name);
+            for node in nodes {
+                match node {
+                    Node::Calculation(cnode) => println!("{}", cnode.id),
+                    Node::Data(dnode) => println!("{}", dnode.id),
+                }
             }
 
         }
-        Commands::NewCalculation {name, command} => {
-            let mut db =  JsonStorage::default();
-            db.add_calculation(&name, &command);
-            write_database_to_stream(&db);
-        }  
         Commands::Inspect {name, datafolder} => {
-            let mut db = JsonStorage::new("".to_string());
-            db.inspect(&name, &datafolder);
+            // Only the node and what it refers to is read
+            let db = check(check(open_storage(database_path())).neighbourhood(name)).unwrap_or_default();
+            inspect(&db, name, datafolder);
         }
-        Commands::AddTag { tag, database } => {
+        Commands::TemplateRegisterDnode { name, database } => {
             let mut db = get_database_input(database);
-            db.add_tags( &tag).expect("Faile to add tags");
+            db.template_register_dnode(name.clone());
             write_database_to_stream(&db);
         }
-        Commands::SetTags { tags, database } => {
+        Commands::TemplateRegisterCnode { name, command, database } => {
             let mut db = get_database_input(database);
-            db.set_tags( &tags).expect("Failed to set tags");
+            check(db.template_register_cnode(name.clone(), command.clone()));
             write_database_to_stream(&db);
-
         }
-        Commands::RemoveTag {tag, database } => {
+        Commands::TemplateVersions { name, database } => {
+            let db = get_database_input(database);
+            for cnode in db.template_versions(name.clone()) {
+                println!("{}\t{}\t{}", cnode.id, cnode.version(), cnode.marked_command());
+            }
+        }
+        Commands::TemplateSetResources { name, cores, memory, database } => {
             let mut db = get_database_input(database);
-            db.remove_tags(&tag).expect("Faile to add tags");
+            check(db.template_set_resources(name.clone(), *cores, *memory));
             write_database_to_stream(&db);
         }
-        Commands::SelectTag { tags, notags, database } => {
-            
+        Commands::TemplateCreateCalculation { leaf, existing, targets, param, database } => {
+            let db = get_database_input(database);
+            let leafs: BTreeMap<String, String> = parse_pairs(leaf).into_iter().collect();
+            let existing = existing.is_some().then(|| parse_pairs(existing).into_iter().collect());
+            // Typed by the declaration of the parameter, so that str values stay as they were written
+            let params = parse_pairs(param).into_iter().map(|(name, value)| (name, MetadataValue::Text(value))).collect();
+            let new_db = check(db.template_create_calculation(leafs, existing, targets.clone(), Some(params)));
+            write_database_to_stream(&new_db);
+        }
+        Commands::TemplateCreateSweep { leaf, param, zip, table, database } => {
+            let db = get_database_input(database);
+            let mut leafs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for (template, name) in parse_pairs(leaf) {
+                leafs.entry(template).or_default().push(name);
+            }
+            let mut params: BTreeMap<String, Vec<MetadataValue>> = BTreeMap::new();
+            for (name, value) in parse_pairs(param) {
+                params.entry(name).or_default().push(MetadataValue::Text(value));
+            }
+            let (new_db, combinations) = check(db.template_create_sweep(leafs, Some(params), *zip));
+            if let Some(path) = table {
+                check(std::fs::write(path, sweep_table(&combinations)).map_err(GraphrError::from));
+            }
+            write_database_to_stream(&new_db);
+        }
+        Commands::MergeInto { target, database } => {
+            let mut db_std = get_database_input(database);
+            check(check(open_storage(target)).merge(&mut db_std));
+
+            write_database_to_stream(&db_std);
+        }
+        Commands::SelectSubbranch { name, database } => {
             let db = get_database_input(database);
-            let new_db = db.filter_by_tags(tags, notags);
+            let new_db = check(db.select_subbranch(name.clone()));
             write_database_to_stream(&new_db);
         }
-        Commands::SelectSubbranch { name , database} => {
+        Commands::SelectName { names, database } => {
             let db = get_database_input(database);
-            let graph = db.select_disconected_branch(name);
-            let new_db = db.digraph_to_database(&graph);
+            let new_db = db.select_by_name(names.clone());
             write_database_to_stream(&new_db);
         }
         Commands::SelectHistory { name, database } => {
             let db = get_database_input(database);
-            let graph = db.select_node_history(name);
-            let new_db = db.digraph_to_database(&graph);
+            let new_db = check(db.select_history(name.clone()));
             write_database_to_stream(&new_db);
 
         }
-        Commands::Show { database } => {
-            
+        Commands::Select { query, database } => {
+            let db = get_database_input(database);
+            let new_db = check(db.select(query.clone()));
+            write_database_to_stream(&new_db);
+        }
+        Commands::Show { format, cluster, id_length, highlight, datafolder, template, database } => {
+
             // handle the cases when the input is passed directly and when it could by piped.
             let db = get_database_input(database);
-            let (graph, _graph_nodes) = db.generate_digraph();
-            println!("{}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
+            let shown = match (format, template) {
+                (ShowFormat::Dot, true) => db.template_as_dot(Some(highlight.clone())),
+                (ShowFormat::Dot, false) => db.as_dot(*cluster, *id_length, Some(highlight.clone()), datafolder.clone()),
+                (ShowFormat::Mermaid, true) => db.template_to_mermaid(),
+                (ShowFormat::Mermaid, false) => db.to_mermaid(),
+                (ShowFormat::Graphml, true) => db.template_to_graphml(),
+                (ShowFormat::Graphml, false) => db.to_graphml(),
+            };
+            print!("{}", check(shown));
 
         }
+        Commands::View { datafolder, database } => {
+            let db = get_database_input(database);
+            print!("{}", check(db.to_html(datafolder.clone())));
+        }
         Commands::Copy {attach, database} => {
 
-            let attach_parsed = match attach {
-                Some(value) => {
-                    // value is presumably a Vec<String> or slice of Strings
-                    // chunks(2) iterates over pairs of elements
-                    value.chunks(2)
-                        .filter_map(|chunk| {
-                            if chunk.len() == 2 {
-                                // Create a tuple of cloned strings
-                                Some((chunk[0].clone(), chunk[1].clone()))
-                            } else {
-                                // Ignore incomplete chunks
-                                None
-                            }
-                        })
-                        .collect::<Vec<(String, String)>>()
-                }
-                None => Vec::new(),
-            };
-
             let db = get_database_input(database);
-            let copied_db = db.copy(attach_parsed);
+            let copied_db = db.copy(parse_pairs(attach));
             write_database_to_stream(&copied_db);
 
         }
         Commands::Add {database} =>{
-            let mut db = JsonStorage::new("".to_string());
-            let db_std = get_database_input(database);
- 
+            let mut db_std = get_database_input(database);
+
             // combine
-            db.add(&db_std);
-            db.write("");
+            check(check(open_storage(database_path())).merge(&mut db_std));
 
             write_database_to_stream(&db_std);
 
         }
         Commands::Delete { names } => {
-            let mut db = JsonStorage::new("".to_string());
-            db.delete(names);
-            db.write("");
+            let mut db = read_database(database_path());
+            db.delete(names.clone());
+            write_database(database_path(), &mut db);
         }
-        Commands::SelectName { names, database } => {
+        Commands::OverwriteCalculation { name, command, existing, new } => {
+            let mut db = read_database(database_path());
+            // Calculations that are not corrected keep their data nodes
+            let mut corrections: Corrections = db.select_similar_versions(name.clone()).into_iter()
+                .filter_map(|node| match node {
+                    Node::Calculation(cnode) => Some((cnode.id, BTreeMap::new())),
+                    Node::Data(_) => None,
+                })
+                .collect();
+            for (kind, values) in [("id", existing), ("name", new)] {
+                for value in values.iter().flat_map(|v| v.chunks(3)) {
+                    let correction = (kind.to_string(), value[2].clone());
+                    corrections.entry(value[0].clone()).or_default().insert(value[1].clone(), correction);
+                }
+            }
+            for id in check(db.overwrite_calculation(name.clone(), command.clone(), corrections)) {
+                println!("{}", id);
+            }
+            write_database(database_path(), &mut db);
+        }
+        Commands::AddTag { tags, database } => {
+            let names = node_names(database);
+            let mut db = read_database(database_path());
+            check(db.add_tags(tags.clone(), Some(names)));
+            write_database(database_path(), &mut db);
+        }
+        Commands::SetTags { tags, database } => {
+            let names = node_names(database);
+            let mut db = read_database(database_path());
+            check(db.set_tags(tags.clone(), Some(names)));
+            write_database(database_path(), &mut db);
+        }
+        Commands::RemoveTag { tags, database } => {
+            let names = node_names(database);
+            let mut db = read_database(database_path());
+            check(db.remove_tags(tags.clone(), Some(names)));
+            write_database(database_path(), &mut db);
+        }
+        Commands::SetMetadata { key, value, database } => {
+            let names = node_names(database);
+            let mut db = read_database(database_path());
+            check(db.set_metadata(key.clone(), MetadataValue::parse(value), Some(names)));
+            write_database(database_path(), &mut db);
+        }
+        Commands::RemoveMetadata { key, database } => {
+            let names = node_names(database);
+            let mut db = read_database(database_path());
+            check(db.remove_metadata(key.clone(), Some(names)));
+            write_database(database_path(), &mut db);
+        }
+        Commands::SelectTag { tags, exclude, database } => {
             let db = get_database_input(database);
-            let copied_db = db.select_by_name(names);
-            write_database_to_stream(&copied_db);
+            write_database_to_stream(&db.filter_by_tags(tags.clone(), exclude.clone()));
+        }
+        Commands::Run { datafolder, logfolder, keep_going, jobs, memory } => {
+            let mut db = read_database(database_path());
+            let options = RunOptions {
+                data_folder: datafolder.clone(),
+                log_folder: logfolder.clone(),
+                policy: if *keep_going {FailurePolicy::Continue} else {FailurePolicy::Stop},
+                jobs: *jobs,
+                memory: *memory,
+                progress: true,
+            };
+
+            let summary = db.run_calculations(&options);
+            // Store the run records even if something went wrong on the way
+            write_database(database_path(), &mut db);
+            report(&check(summary), logfolder);
+        }
+        Commands::Update { target, datafolder, logfolder, keep_going, jobs, memory } => {
+            let mut db = read_database(database_path());
+            let options = RunOptions {
+                data_folder: datafolder.clone(),
+                log_folder: logfolder.clone(),
+                policy: if *keep_going {FailurePolicy::Continue} else {FailurePolicy::Stop},
+                jobs: *jobs,
+                memory: *memory,
+                progress: true,
+            };
+
+            let summary = db.update_target(target, &options);
+            write_database(database_path(), &mut db);
+            report(&check(summary), logfolder);
+        }
+        Commands::Status { datafolder } => {
+            let db = read_database(database_path());
+            for (id, status) in check(db.status(datafolder.clone())) {
+                println!("{:<10} {}", status.to_string(), id);
+            }
+        }
+        Commands::RegisterHashes { names, datafolder } => {
+            let mut db = read_database(database_path());
+            let names = if names.is_empty() {None} else {Some(names.clone())};
+            check(db.register_hashes(names, datafolder.clone()));
+            write_database(database_path(), &mut db);
+        }
+        Commands::Export { format, datafolder, conda, conda_env, database } => {
+            let db = get_database_input(database);
+            let exported = match format {
+                ExportFormat::Snakemake => {
+                    let options = SnakemakeOptions {
+                        root_folder: datafolder.clone(),
+                        conda: conda.clone(),
+                        conda_envs: parse_pairs(conda_env).into_iter().collect(),
+                    };
+                    check(db.snakefile(&options))
+                }
+                ExportFormat::Nextflow => check(db.nextflow_script(datafolder)),
+                ExportFormat::Make => check(db.makefile(datafolder)),
+                ExportFormat::Cwl => check(db.template_to_cwl()) + "\n",
+                ExportFormat::CwlJob => {
+                    let jobs = check(db.cwl_jobs(datafolder));
+                    jobs.iter().map(|job| format!("{}\n", job)).collect()
+                }
+                ExportFormat::ProvJson => check(db.to_prov_json()) + "\n",
+                ExportFormat::ProvN => check(db.prov_n()),
+            };
+            print!("{}", exported);
+        }
+        Commands::Log => {
+            let entries = check(read_journal(&journal_path(database_path())));
+            let undone = undone_entries(&entries);
+            for (i, entry) in entries.iter().enumerate() {
+                let mark = if undone.contains(&i) {" (undone)"} else {""};
+                println!("{:>4} {} {:<10} {}{}", i, iso_timestamp(entry.timestamp), entry.user, entry.operation, mark);
+            }
+        }
+        Commands::Undo => {
+            check(undo_last(database_path()));
+        }
+        Commands::Replay => {
+            check(replay(database_path()));
         }
         Commands::SelectFuture { name, database } => {
 
             let db = get_database_input(database);
-            let graph = db.select_node_future(name);
-            let new_db = db.digraph_to_database(&graph);
+            let new_db = check(db.select_future(name.clone()));
             write_database_to_stream(&new_db);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Convert a flat list of values into pairs
+/// (used by arguments that take exactly 2 values per occurrence).
+fn parse_pairs(values: &Option<Vec<String>>) -> Vec<(String, String)> {
+    match values {
+        Some(value) => {
+            // chunks(2) iterates over pairs of elements
+            value.chunks(2)
+                .filter_map(|chunk| {
+                    if chunk.len() == 2 {
+                        Some((chunk[0].clone(), chunk[1].clone()))
+                    } else {
+                        // Ignore incomplete chunks
+                        None
+                    }
+                })
+                .collect::<Vec<(String, String)>>()
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Print information about a node
+fn inspect(db: &Database, name: &str, datafolder: &str) {
+    match db.get(name.to_string()) {
+        Some(Node::Calculation(cnode)) => {
+            println!("Calculation node: {}", cnode.id);
+            println!("Template: {}", cnode.template);
+            println!("Inputs: {:?}", cnode.incoming);
+            println!("Outputs: {:?}", cnode.outcoming);
+            if !cnode.modified.is_empty() {
+                println!("Modifies: {:?}", cnode.modified);
+            }
+            if let Some(template) = db.get_template_cnode(&cnode.template) {
+                println!("graphr template-register-cnode {} \"{}\"", template.id, template.marked_command());
+            }
+            println!("{}", check(db.get_command(cnode.id.clone(), datafolder.to_string())));
+        }
+        Some(Node::Data(dnode)) => {
+            println!("Data node: {}", dnode.id);
+            println!("Template: {}", dnode.template);
+            if let Some(base) = &dnode.revision_of {
+                println!("Revision {} of {}", dnode.revision, base);
+            }
+            println!("Location: {}", Path::new(datafolder).join(dnode.location()).display());
+        }
+        None => {
+            eprintln!("Node {} not found in the database.", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Print the outcome of a run. Exits with an error if any calculation failed.
+fn report(summary: &RunSummary, logfolder: &str) {
+    for id in &summary.failed {
+        eprintln!("Failed: {} (see {}/{}.stderr)", id, logfolder, id);
+    }
+    println!("Succeeded: {}, failed: {}, skipped: {}", summary.succeeded.len(), summary.failed.len(), summary.skipped.len());
+    if !summary.failed.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Table of a sweep: the leafs, the parameters and the produced data of every combination as columns
+fn sweep_table(combinations: &[Combination]) -> String {
+    let Some(first) = combinations.first() else { return String::new() };
+    let header: Vec<String> = first.leafs.keys().cloned()
+        .chain(first.params.keys().map(|name| format!("param({})", name)))
+        .chain(first.outputs.keys().cloned())
+        .collect();
+    let mut result = format!("{}\n", header.join("\t"));
+    for combination in combinations {
+        let row: Vec<String> = combination.leafs.values().cloned()
+            .chain(combination.params.values().map(|value| value.to_string()))
+            .chain(combination.outputs.values().cloned())
+            .collect();
+        result.push_str(&format!("{}\n", row.join("\t")));
+    }
+    result
+}
+
+/// Unwrap the result or report the error and exit.
+fn check<T>(result: graphrlib::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Location of the database. Sqlite is used if it was chosen when initializing.
+fn database_path() -> &'static str {
+    if Path::new(SQLITEDATABASE).exists() {SQLITEDATABASE} else {JSONDATABASE}
+}
+
+/// Read the database from a file.
+fn read_database(path: &str) -> Database {
+    check(check(open_storage(path)).load())
+}
+
+/// Replace the database stored in a file.
+fn write_database(path: &str, db: &mut Database) {
+    check(check(open_storage(path)).save(db));
+}
+
+/// Positions of the journal entries that were undone later
+fn undone_entries(entries: &[JournalEntry]) -> Vec<usize> {
+    let mut effective = Vec::new();
+    let mut undone = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.operation {
+            Operation::Undo => undone.extend(effective.pop()),
+            _ => effective.push(i),
+        }
+    }
+    undone
+}
+
+/// handles whether the database comes from stdin or as the last argument named 'database'.
+fn get_database_input(database: &Option<String>) -> Database {
+
+    let database_json_string = match database {
+        Some(data) => {data.clone()}
+        None => {
+            let mut buffer = String::new();
+            check(io::stdin().read_to_string(&mut buffer).map_err(GraphrError::from));
+            buffer
+        }
+    };
+    check(serde_json::from_str(&database_json_string).map_err(GraphrError::from))
+}
+
+/// Ids of the nodes of the database passed on the command line.
+/// Tags and metadata are changed on the stored database, merging a changed copy back could only add them.
+fn node_names(database: &Option<String>) -> Vec<String> {
+    get_database_input(database).to_nodes().into_iter().map(|node| match node {
+        Node::Calculation(cnode) => cnode.id,
+        Node::Data(dnode) => dnode.id,
+    }).collect()
+}
+
+fn write_database_to_stream(database: &Database) {
+
+    let write_string = serde_json::to_string(database).expect("Failed to seriazile the database for printing.");
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+
+    // Try writing to stdout
+    if let Err(e) = writeln!(handle, "{}", write_string) {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            // Exit gracefully if the pipe is closed early
+            std::process::exit(0);
+        } else {
+            eprintln!("Failed to write to stdout: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+}
@@ -0,0 +1,132 @@
+/*
+Errors returned by graphrlib.
+Every variant is mapped to its own python exception, so that a failure in the library
+raises an exception in python instead of taking the whole interpreter down.
+*/
+use std::fmt;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::PyErr;
+
+
+#[derive(Debug)]
+pub enum GraphrError {
+    /// Root data nodes of the template that were not given a name.
+    MissingLeaf(Vec<String>),
+    /// A template node with the same id, but a different definition already exists.
+    TemplateConflict(String),
+    /// A node refers to a node that does not exist.
+    DanglingReference { node: String, reference: String },
+    /// Node with the given id was not found.
+    UnknownNode(String),
+    /// Nodes depend on each other in a loop.
+    Cycle(String),
+    /// Failed to parse a command or an expression.
+    ParseError(String),
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    /// The journal can not be used for the requested operation.
+    Journal(String),
+    /// Corrections of overwrite_calculation that do not fit the calculations.
+    InvalidCorrection(String),
+    /// Existing data nodes that can not take the place of data templates in a new calculation.
+    InvalidExisting(String),
+    /// Parameter values that do not fit the parameters of the template.
+    InvalidParam(String),
+    /// Lists of a sweep that can not be combined.
+    InvalidSweep(String),
+}
+
+pub type Result<T> = std::result::Result<T, GraphrError>;
+
+
+impl fmt::Display for GraphrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphrError::MissingLeaf(names) => write!(f, "Need to provide names for the root nodes: {}", names.join(", ")),
+            GraphrError::TemplateConflict(id) => write!(f, "Template mismatch for '{}': existing and new templates differ", id),
+            GraphrError::DanglingReference { node, reference } => write!(f, "Node '{}' refers to '{}', which does not exist", node, reference),
+            GraphrError::UnknownNode(id) => write!(f, "Node '{}' not found", id),
+            GraphrError::Cycle(id) => write!(f, "Nodes form a cycle through '{}'", id),
+            GraphrError::ParseError(message) => write!(f, "Parse error: {}", message),
+            GraphrError::Io(e) => write!(f, "IO error: {}", e),
+            GraphrError::Serde(e) => write!(f, "Serialization error: {}", e),
+            GraphrError::Sqlite(e) => write!(f, "Storage error: {}", e),
+            GraphrError::Journal(message) => write!(f, "Journal error: {}", message),
+            GraphrError::InvalidCorrection(message) => write!(f, "Invalid correction: {}", message),
+            GraphrError::InvalidExisting(message) => write!(f, "Invalid existing data: {}", message),
+            GraphrError::InvalidParam(message) => write!(f, "Invalid parameter: {}", message),
+            GraphrError::InvalidSweep(message) => write!(f, "Invalid sweep: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GraphrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GraphrError::Io(e) => Some(e),
+            GraphrError::Serde(e) => Some(e),
+            GraphrError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for GraphrError {
+    fn from(e: std::io::Error) -> Self {
+        GraphrError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for GraphrError {
+    fn from(e: serde_json::Error) -> Self {
+        GraphrError::Serde(e)
+    }
+}
+
+impl From<rusqlite::Error> for GraphrError {
+    fn from(e: rusqlite::Error) -> Self {
+        GraphrError::Sqlite(e)
+    }
+}
+
+
+// Python exceptions. All of them derive from GraphrException
+create_exception!(graphrlib, GraphrException, PyException);
+create_exception!(graphrlib, MissingLeafError, GraphrException);
+create_exception!(graphrlib, TemplateConflictError, GraphrException);
+create_exception!(graphrlib, DanglingReferenceError, GraphrException);
+create_exception!(graphrlib, UnknownNodeError, GraphrException);
+create_exception!(graphrlib, CycleError, GraphrException);
+create_exception!(graphrlib, ParseError, GraphrException);
+create_exception!(graphrlib, GraphrIOError, GraphrException);
+create_exception!(graphrlib, SerdeError, GraphrException);
+create_exception!(graphrlib, StorageError, GraphrException);
+create_exception!(graphrlib, JournalError, GraphrException);
+create_exception!(graphrlib, InvalidCorrectionError, GraphrException);
+create_exception!(graphrlib, InvalidExistingError, GraphrException);
+create_exception!(graphrlib, InvalidParamError, GraphrException);
+create_exception!(graphrlib, InvalidSweepError, GraphrException);
+
+impl From<GraphrError> for PyErr {
+    fn from(e: GraphrError) -> Self {
+        let message = e.to_string();
+        match e {
+            GraphrError::MissingLeaf(_) => MissingLeafError::new_err(message),
+            GraphrError::TemplateConflict(_) => TemplateConflictError::new_err(message),
+            GraphrError::DanglingReference { .. } => DanglingReferenceError::new_err(message),
+            GraphrError::UnknownNode(_) => UnknownNodeError::new_err(message),
+            GraphrError::Cycle(_) => CycleError::new_err(message),
+            GraphrError::ParseError(_) => ParseError::new_err(message),
+            GraphrError::Io(_) => GraphrIOError::new_err(message),
+            GraphrError::Serde(_) => SerdeError::new_err(message),
+            GraphrError::Sqlite(_) => StorageError::new_err(message),
+            GraphrError::Journal(_) => JournalError::new_err(message),
+            GraphrError::InvalidCorrection(_) => InvalidCorrectionError::new_err(message),
+            GraphrError::InvalidExisting(_) => InvalidExistingError::new_err(message),
+            GraphrError::InvalidParam(_) => InvalidParamError::new_err(message),
+            GraphrError::InvalidSweep(_) => InvalidSweepError::new_err(message),
+        }
+    }
+}
@@ -0,0 +1,251 @@
+/*
+Common Workflow Language (CWL v1.2) export.
+The template becomes a packed CWL document: a CommandLineTool for every calculation template and
+a Workflow (#main) that connects them. Root data templates are the inputs of the workflow.
+A database is exported as job files that bind the inputs of the workflow to the data in the root folder,
+one job for every set of root data the calculations of the database were created from.
+Commands run with sh -c; outputs are written into the working directory under the name of their slot.
+Data that is modified in place is staged as a writable copy.
+Parameters of calculation templates are inputs of the workflow as well, the jobs bind the values of the calculations.
+Text parameters reach the command as environment variables, so that the shell does not split them.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use serde_json::{json, Map, Value};
+use crate::export::{fill_slots, identifier, Slots};
+use crate::{Database, DatabaseTemplate, GraphrError, IdC, IdCTemplate, IdD, IdDTemplate, ParamKind, Result};
+
+const CWL_VERSION: &str = "v1.2";
+
+
+impl DatabaseTemplate {
+
+    /// Packed CWL document with a tool per calculation template and the workflow #main
+    pub fn cwl_document(&self) -> Result<Value> {
+        let order = self.calculation_order()?;
+
+        let mut taken: BTreeSet<String> = BTreeSet::from(["main".to_string()]);
+        let mut unique = |name: &str| {
+            let mut candidate = identifier(name);
+            while taken.contains(&candidate) {
+                candidate.push('_');
+            }
+            taken.insert(candidate.clone());
+            candidate
+        };
+
+        // Data that is not produced by any calculation comes from the inputs of the workflow.
+        // Every data template has its sources (revisions) with the calculation template that made them.
+        let produced: BTreeSet<&IdDTemplate> = self.cnodes.values().flat_map(|c| &c.outcoming).collect();
+        let mut sources: BTreeMap<&IdDTemplate, Vec<(Option<&IdCTemplate>, String)>> = BTreeMap::new();
+        let mut inputs = Map::new();
+        for d_id in self.cnodes.values().flat_map(|c| c.incoming.iter().chain(&c.modified)) {
+            if !produced.contains(d_id) && !sources.contains_key(d_id) {
+                let name = unique(d_id);
+                inputs.insert(name.clone(), json!({"type": ["File", "Directory"], "label": d_id}));
+                sources.insert(d_id, vec![(None, name)]);
+            }
+        }
+
+        let mut graph_entries = Vec::new();
+        let mut steps = Map::new();
+        // Same order and revisions as create_calculation: modifications chain onto the last revision,
+        // readers get the revision of the last modification they are downstream of
+        for (template, upstream) in order {
+            let slots = Slots::new(template);
+            let name = unique(&template.id);
+            graph_entries.push(tool(&name, template, &slots));
+
+            let mut step_inputs = Map::new();
+            let readers = slots.incoming.iter().zip(&template.incoming).map(|slot| (slot, false));
+            let modifiers = slots.modified.iter().zip(&template.modified).map(|slot| (slot, true));
+            for ((slot, d_id), modify) in readers.chain(modifiers) {
+                let source = sources.get(d_id)
+                    .and_then(|s| s.iter().rev().find(|(made_by, _)| modify || made_by.is_none_or(|c_id| upstream.contains(c_id))))
+                    .ok_or_else(|| GraphrError::DanglingReference { node: template.id.clone(), reference: d_id.clone() })?;
+                step_inputs.insert(slot.clone(), json!(source.1));
+            }
+            for (slot, d_id) in slots.outcoming.iter().zip(&template.outcoming).chain(slots.modified.iter().zip(&template.modified)) {
+                sources.entry(d_id).or_default().push((Some(&template.id), format!("{}/{}", name, slot)));
+            }
+            // Parameters are inputs of the workflow, bound by the job of every calculation
+            for param in &template.params {
+                let input = unique(&format!("{}_{}", name, param.name));
+                let mut definition = json!({"type": param_type(param.kind), "label": param_label(&template.id, &param.name)});
+                if let Some(default) = &param.default {
+                    definition["default"] = json!(default);
+                }
+                inputs.insert(input.clone(), definition);
+                step_inputs.insert(param_input(&param.name), json!(input));
+            }
+            let step_outputs: Vec<&String> = slots.outcoming.iter().chain(&slots.modified).collect();
+            steps.insert(name.clone(), json!({"run": format!("#{}", name), "in": step_inputs, "out": step_outputs}));
+        }
+
+        // Final state of all produced data
+        let mut outputs = Map::new();
+        for (d_id, source) in sources.iter().filter_map(|(d_id, s)| Some((d_id, &s.last()?.1))) {
+            if source.contains('/') {
+                outputs.insert(unique(d_id), json!({"type": ["File", "Directory"], "label": d_id, "outputSource": source}));
+            }
+        }
+
+        graph_entries.push(json!({
+            "class": "Workflow",
+            "id": "main",
+            "inputs": inputs,
+            "outputs": outputs,
+            "steps": steps,
+        }));
+        Ok(json!({"cwlVersion": CWL_VERSION, "$graph": graph_entries}))
+    }
+}
+
+
+impl Database {
+
+    /// Job files for the CWL workflow of the template (see DatabaseTemplate::cwl_document).
+    /// Every calculation that nothing depends on gets the root data of its history bound; calculations with the same root data share a job.
+    pub fn cwl_jobs(&self, root_folder: &str) -> Result<Vec<Value>> {
+        let document = self.template.cwl_document()?;
+        let inputs = document["$graph"].as_array()
+            .and_then(|g| g.iter().find(|entry| entry["id"] == "main"))
+            .and_then(|workflow| workflow["inputs"].as_object())
+            .ok_or_else(|| GraphrError::UnknownNode("main".to_string()))?;
+        // Workflow input names by data template
+        let input_names: BTreeMap<&str, &str> = inputs.iter()
+            .filter_map(|(name, input)| Some((input["label"].as_str()?, name.as_str())))
+            .collect();
+
+        let consumed: BTreeSet<&IdD> = self.cnodes.values().flat_map(|c| c.inputs()).collect();
+        let terminal: Vec<&IdC> = self.cnodes.values()
+            .filter(|c| !c.outputs().any(|d_id| consumed.contains(d_id)))
+            .map(|c| &c.id)
+            .collect();
+
+        let mut jobs: Vec<Value> = Vec::new();
+        for c_id in terminal {
+            let history = self.select_node_history(c_id.clone())?;
+            let mut job = Map::new();
+            for id in history.node_weights() {
+                if let Some(cnode) = self.cnodes.get(id)
+                    && let Some(template) = self.template.cnodes.get(&cnode.template) {
+                    for (param, value) in template.param_values(&cnode.params)? {
+                        if let Some(name) = input_names.get(param_label(&template.id, &param).as_str()) {
+                            job.insert(name.to_string(), json!(value));
+                        }
+                    }
+                    continue
+                }
+                let Some(dnode) = self.dnodes.get(id) else { continue };
+                if let Some(name) = input_names.get(dnode.template.as_str())
+                    && !self.cnodes.values().any(|c| c.produces(id)) {
+                    let class = if self.is_directory(id, root_folder) { "Directory" } else { "File" };
+                    job.insert(name.to_string(), json!({"class": class, "path": format!("{}/{}", root_folder, self.location(id))}));
+                }
+            }
+            let job = Value::Object(job);
+            if !jobs.contains(&job) {
+                jobs.push(job);
+            }
+        }
+        Ok(jobs)
+    }
+}
+
+
+/// CommandLineTool of a calculation template
+fn tool(name: &str, template: &crate::CNodeTemplate, slots: &Slots) -> Value {
+    // $( and ${ start parameter references in CWL
+    let escaped = template.command.replace("$(", "\\$(").replace("${", "\\${");
+    let command = fill_slots(&escaped, template, |kind, n| match kind {
+        'i' => format!("$(inputs.{}.path)", slots.incoming[n]),
+        'o' => slots.outcoming[n].clone(),
+        _ => format!("$(inputs.{}.basename)", slots.modified[n]),
+    });
+    // Text is passed in the environment, so that the shell takes it as a single word
+    let command = template.fill_params(&command, |_, param| match param.kind {
+        ParamKind::Str => format!("\"${}\"", param_input(&param.name)),
+        _ => format!("$(inputs.{})", param_input(&param.name)),
+    });
+
+    let mut inputs = Map::new();
+    for slot in slots.incoming.iter().chain(&slots.modified) {
+        inputs.insert(slot.clone(), json!({"type": ["File", "Directory"]}));
+    }
+    for param in &template.params {
+        inputs.insert(param_input(&param.name), json!({"type": param_type(param.kind)}));
+    }
+    let mut outputs = Map::new();
+    for slot in &slots.outcoming {
+        outputs.insert(slot.clone(), json!({"type": ["File", "Directory"], "outputBinding": {"glob": slot}}));
+    }
+    for slot in &slots.modified {
+        outputs.insert(slot.clone(), json!({"type": ["File", "Directory"], "outputBinding": {"glob": format!("$(inputs.{}.basename)", slot)}}));
+    }
+
+    let mut requirements = Vec::new();
+    if !slots.modified.is_empty() {
+        let listing: Vec<Value> = slots.modified.iter().map(|slot| json!({"entry": format!("$(inputs.{})", slot), "writable": true})).collect();
+        requirements.push(json!({"class": "InitialWorkDirRequirement", "listing": listing}));
+    }
+    let environment: Map<String, Value> = template.params.iter()
+        .filter(|param| param.kind == ParamKind::Str)
+        .map(|param| (param_input(&param.name), json!(format!("$(inputs.{})", param_input(&param.name)))))
+        .collect();
+    if !environment.is_empty() {
+        requirements.push(json!({"class": "EnvVarRequirement", "envDef": environment}));
+    }
+    let mut resources = json!({"class": "ResourceRequirement", "coresMin": template.resources.cores});
+    if let Some(memory) = template.resources.memory {
+        resources["ramMin"] = json!(memory);
+    }
+    requirements.push(resources);
+
+    json!({
+        "class": "CommandLineTool",
+        "id": name,
+        "label": template.id,
+        "baseCommand": ["sh", "-c"],
+        "arguments": [command],
+        "inputs": inputs,
+        "outputs": outputs,
+        "requirements": requirements,
+    })
+}
+
+/// Input of the tool for a parameter, prefixed so that it does not clash with the slots
+fn param_input(name: &str) -> String {
+    format!("param_{}", identifier(name))
+}
+
+/// Label of the workflow input of a parameter, used to find it again for the jobs
+fn param_label(template_id: &str, name: &str) -> String {
+    format!("param({}) of {}", name, template_id)
+}
+
+fn param_type(kind: ParamKind) -> &'static str {
+    match kind {
+        ParamKind::Bool => "boolean",
+        ParamKind::Int => "long",
+        ParamKind::Float => "double",
+        ParamKind::Str => "string",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::example;
+
+    fn golden(content: &str) -> Value {
+        serde_json::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn golden_document_and_jobs() {
+        let db = example();
+        assert_eq!(db.template.cwl_document().unwrap(), golden(include_str!("golden/workflow.cwl")));
+        assert_eq!(json!(db.cwl_jobs("data").unwrap()), golden(include_str!("golden/jobs.json")));
+    }
+}
@@ -0,0 +1,128 @@
+/*
+Nodes and edges of a database or a template as they are drawn by the graph formats (DOT, Mermaid, GraphML).
+*/
+use crate::{Database, DatabaseTemplate, GraphrError, Result};
+
+
+pub(crate) struct DiagramNode {
+    pub id: String,
+    pub template: String,
+    pub calculation: bool,
+    /// Command of the calculation template (with the input/output/modify markers)
+    pub command: Option<String>,
+    /// Location of the data in the data folder (database only)
+    pub location: Option<String>,
+}
+
+impl DiagramNode {
+    pub fn kind(&self) -> &'static str {
+        if self.calculation { "calculation" } else { "data" }
+    }
+}
+
+pub(crate) struct DiagramEdge {
+    pub from: String,
+    pub to: String,
+    /// Slot of the command the edge fills: $i_N, $o_N or $m_N
+    pub slot: String,
+    /// The edge belongs to a modification in place
+    pub modify: bool,
+    /// Data flows both ways (modification in a template, where the data before and after is the same node)
+    pub both: bool,
+}
+
+/// Calculations first, then data, both sorted by id
+pub(crate) struct Diagram {
+    pub nodes: Vec<DiagramNode>,
+    pub edges: Vec<DiagramEdge>,
+}
+
+impl Diagram {
+    fn edge(&mut self, from: &str, to: &str, slot: String, modify: bool, both: bool) {
+        self.edges.push(DiagramEdge { from: from.to_string(), to: to.to_string(), slot, modify, both });
+    }
+}
+
+
+impl DatabaseTemplate {
+
+    pub(crate) fn diagram(&self) -> Result<Diagram> {
+        let mut diagram = Diagram { nodes: Vec::new(), edges: Vec::new() };
+        for (id, cnode) in &self.cnodes {
+            let command = Some(cnode.marked_command());
+            diagram.nodes.push(DiagramNode { id: id.clone(), template: id.clone(), calculation: true, command, location: None });
+        }
+        for id in self.dnodes.keys() {
+            diagram.nodes.push(DiagramNode { id: id.clone(), template: id.clone(), calculation: false, command: None, location: None });
+        }
+
+        for (id, cnode) in &self.cnodes {
+            let check = |d_id: &String| {
+                if self.dnodes.contains_key(d_id) { Ok(()) } else { Err(GraphrError::DanglingReference { node: id.clone(), reference: d_id.clone() }) }
+            };
+            for (n, d_id) in cnode.incoming.iter().enumerate() {
+                check(d_id)?;
+                diagram.edge(d_id, id, format!("$i_{}", n), false, false);
+            }
+            for (n, d_id) in cnode.outcoming.iter().enumerate() {
+                check(d_id)?;
+                diagram.edge(id, d_id, format!("$o_{}", n), false, false);
+            }
+            // The template has a single node for the data before and after the modification
+            for (n, d_id) in cnode.modified.iter().enumerate() {
+                check(d_id)?;
+                diagram.edge(d_id, id, format!("$m_{}", n), true, true);
+            }
+        }
+        Ok(diagram)
+    }
+}
+
+
+impl Database {
+
+    pub(crate) fn diagram(&self) -> Result<Diagram> {
+        let mut diagram = Diagram { nodes: Vec::new(), edges: Vec::new() };
+        for (id, cnode) in &self.cnodes {
+            let command = self.get_template_cnode(&cnode.template).map(|t| t.marked_command());
+            diagram.nodes.push(DiagramNode { id: id.clone(), template: cnode.template.clone(), calculation: true, command, location: None });
+        }
+        for (id, dnode) in &self.dnodes {
+            let location = Some(dnode.location().clone());
+            diagram.nodes.push(DiagramNode { id: id.clone(), template: dnode.template.clone(), calculation: false, command: None, location });
+        }
+
+        for (id, cnode) in &self.cnodes {
+            let check = |d_id: &String| {
+                if self.dnodes.contains_key(d_id) { Ok(()) } else { Err(GraphrError::DanglingReference { node: id.clone(), reference: d_id.clone() }) }
+            };
+            for (n, d_id) in cnode.incoming.iter().enumerate() {
+                check(d_id)?;
+                diagram.edge(d_id, id, format!("$i_{}", n), false, false);
+            }
+            for (n, d_id) in cnode.outcoming.iter().enumerate() {
+                check(d_id)?;
+                diagram.edge(id, d_id, format!("$o_{}", n), false, false);
+            }
+            for (n, (from_id, to_id)) in cnode.modified.iter().enumerate() {
+                check(from_id)?;
+                check(to_id)?;
+                diagram.edge(from_id, id, format!("$m_{}", n), true, false);
+                diagram.edge(id, to_id, format!("$m_{}", n), true, false);
+            }
+        }
+        Ok(diagram)
+    }
+}
+
+/// Template name and the end of the id (ids are timestamps, the last digits tell them apart)
+pub(crate) fn short_label(template: &str, id: &str, id_length: usize) -> String {
+    if id_length == 0 || id == template {
+        return template.to_string()
+    }
+    let start = id.char_indices().rev().nth(id_length - 1).map(|(i, _)| i);
+    match start {
+        Some(i) if i > 0 => format!("{}\n…{}", template, &id[i..]),
+        _ => format!("{}\n{}", template, id),
+    }
+}
@@ -0,0 +1,126 @@
+/*
+Graphviz DOT rendering of a database and of its template.
+Calculations are drawn as boxes and data as ellipses. Every edge is labelled with the slot of the command
+($i_N, $o_N, $m_N) it fills; modifications in place are dashed.
+Nodes of a database are labelled with their template and a short id, and can be grouped into a cluster per template.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use crate::export::diagram::{short_label, Diagram};
+use crate::export::quote;
+use crate::{Database, DatabaseTemplate, NodeStatus, Result};
+
+/// Shape and fill colour
+const CALCULATION_STYLE: (&str, &str) = ("shape=box, style=\"rounded,filled\"", "#cfe2f3");
+const DATA_STYLE: (&str, &str) = ("shape=ellipse, style=filled", "#fff2cc");
+const HIGHLIGHT_STYLE: &str = "penwidth=3, color=\"#cc0000\"";
+
+
+/// Settings of the DOT rendering
+#[derive(Clone, Debug)]
+pub struct DotOptions {
+    /// Group the nodes of every template into a cluster (database only)
+    pub cluster: bool,
+    /// Number of trailing characters of the id shown in the label. 0 shows no id.
+    pub id_length: usize,
+    /// Nodes drawn with a thick red border
+    pub highlight: BTreeSet<String>,
+    /// Data folder to compare the database against. Nodes that are not up to date get the colour of their status.
+    pub data_folder: Option<String>,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        DotOptions { cluster: false, id_length: 6, highlight: BTreeSet::new(), data_folder: None }
+    }
+}
+
+
+impl DatabaseTemplate {
+
+    /// Render the template in DOT format. Only highlight of the options applies.
+    pub fn dot(&self, options: &DotOptions) -> Result<String> {
+        let options = DotOptions { cluster: false, data_folder: None, ..options.clone() };
+        Ok(render(&self.diagram()?, &BTreeMap::new(), &options))
+    }
+}
+
+
+impl Database {
+
+    /// Render the database in DOT format
+    pub fn dot(&self, options: &DotOptions) -> Result<String> {
+        let status = match &options.data_folder {
+            Some(data_folder) => self.node_status(data_folder)?,
+            None => BTreeMap::new(),
+        };
+        Ok(render(&self.diagram()?, &status, options))
+    }
+}
+
+
+fn render(diagram: &Diagram, status: &BTreeMap<String, NodeStatus>, options: &DotOptions) -> String {
+    // Node statements by cluster label (None - outside of any cluster)
+    let mut clusters: BTreeMap<Option<&String>, Vec<String>> = BTreeMap::new();
+    for node in &diagram.nodes {
+        let label = short_label(&node.template, &node.id, options.id_length);
+        let kind_style = if node.calculation { CALCULATION_STYLE } else { DATA_STYLE };
+        let statement = format!("{} [label={}, {}]", quote(&node.id), quote(&label), style(kind_style, &node.id, status.get(&node.id), options));
+        let cluster = if options.cluster { Some(&node.template) } else { None };
+        clusters.entry(cluster).or_default().push(statement);
+    }
+
+    let mut result = String::from("digraph {\n    node [fontname=\"Helvetica\"]\n    edge [fontname=\"Helvetica\", fontsize=10]\n");
+    for (n, (cluster, statements)) in clusters.into_iter().enumerate() {
+        let indent = match cluster {
+            Some(label) => {
+                result.push_str(&format!("    subgraph cluster_{} {{\n        label={}\n        style=\"rounded,dashed\"\n", n, quote(label)));
+                "        "
+            }
+            None => "    ",
+        };
+        for statement in statements {
+            result.push_str(&format!("{}{}\n", indent, statement));
+        }
+        if cluster.is_some() {
+            result.push_str("    }\n");
+        }
+    }
+    for edge in &diagram.edges {
+        let style = match (edge.modify, edge.both) {
+            (true, true) => ", dir=both, style=dashed",
+            (true, false) => ", style=dashed",
+            _ => "",
+        };
+        result.push_str(&format!("    {} -> {} [label={}{}]\n", quote(&edge.from), quote(&edge.to), quote(&edge.slot), style));
+    }
+    result.push_str("}\n");
+    result
+}
+
+fn style((shape, fill): (&str, &str), id: &str, status: Option<&NodeStatus>, options: &DotOptions) -> String {
+    let mut result = match status {
+        Some(NodeStatus::Stale) => format!("{}, fillcolor=\"#f4cccc\"", shape),
+        Some(NodeStatus::Modified) => format!("{}, fillcolor=\"#fce5cd\"", shape),
+        Some(NodeStatus::Missing) => format!("{}, fillcolor=\"#eeeeee\", fontcolor=\"#666666\"", shape),
+        _ => format!("{}, fillcolor=\"{}\"", shape, fill),
+    };
+    if options.highlight.contains(id) {
+        result.push_str(", ");
+        result.push_str(HIGHLIGHT_STYLE);
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_dot() {
+        let db = example();
+        assert_eq!(db.dot(&DotOptions::default()).unwrap(), include_str!("golden/database.dot"));
+        assert_eq!(db.template.dot(&DotOptions::default()).unwrap(), include_str!("golden/template.dot"));
+    }
+}
@@ -0,0 +1,86 @@
+// Generated by graphr
+nextflow.enable.dsl = 2
+
+params.root = 'data'
+
+def gather(calculations, data, count) {
+    def result = calculations.map { row -> [row, []] }
+    for (int i = 0; i < count; i++) {
+        def index = i
+        result = result
+            .map { row, files -> [row[1][index], row, files] }
+            .combine(data, by: 0)
+            .map { id, row, files, file -> [row, files + [file]] }
+    }
+    return result.map { row, files -> [row[0]] + files + row.drop(2) }
+}
+
+process F {
+    tag "${calculation}"
+    publishDir params.root, mode: 'copy'
+
+    input:
+    tuple val(calculation), path(x), val(y), val(param_pattern)
+
+    output:
+    tuple val(y), path("${y}"), emit: y
+
+    script:
+    """
+    grep ${param_pattern} ${x} > ${y}
+    """
+}
+
+process S {
+    tag "${calculation}"
+    publishDir params.root, mode: 'copy'
+
+    input:
+    tuple val(calculation), path(y), val(z)
+
+    output:
+    tuple val(z), path("${z}"), emit: z
+
+    script:
+    """
+    wc -l ${y} > ${z}
+    """
+}
+
+process M {
+    tag "${calculation}"
+    publishDir params.root, mode: 'copy'
+    stageInMode 'copy'
+
+    input:
+    tuple val(calculation), path(y), val(y_revision)
+
+    output:
+    tuple val(y_revision), path("${y}", includeInputs: true), emit: y
+
+    script:
+    """
+    echo checked >> ${y}
+    """
+}
+
+workflow {
+    data = Channel.of(
+        ['x0', file("${params.root}/x0")],
+    )
+
+    F(gather(Channel.of(
+        ['cF', ['x0'], 'y0', '\'it\'\\\'\'s here\''],
+    ), data, 1))
+    data = data.mix(F.out.y)
+
+    S(gather(Channel.of(
+        ['cS', ['y0'], 'z0'],
+    ), data, 1))
+    data = data.mix(S.out.z)
+
+    M(gather(Channel.of(
+        ['cM', ['y0'], 'y1'],
+    ), data, 1))
+    data = data.mix(M.out.y)
+}
@@ -0,0 +1,100 @@
+/*
+GraphML export, for graph editors such as yEd and Gephi.
+Every node carries its kind (calculation or data), its template, a short label, the command of calculations
+and the location of data; every edge carries the slot of the command it fills.
+*/
+use crate::export::diagram::{short_label, Diagram};
+use crate::{Database, DatabaseTemplate, Result};
+
+const ID_LENGTH: usize = 6;
+
+/// key id, element, attribute name
+const KEYS: [(&str, &str, &str); 7] = [
+    ("label", "node", "label"),
+    ("kind", "node", "kind"),
+    ("template", "node", "template"),
+    ("command", "node", "command"),
+    ("location", "node", "location"),
+    ("slot", "edge", "slot"),
+    ("modify", "edge", "modify"),
+];
+
+
+impl DatabaseTemplate {
+
+    /// GraphML document of the template
+    pub fn graphml(&self) -> Result<String> {
+        Ok(render(&self.diagram()?))
+    }
+}
+
+
+impl Database {
+
+    /// GraphML document of the database
+    pub fn graphml(&self) -> Result<String> {
+        Ok(render(&self.diagram()?))
+    }
+}
+
+
+fn render(diagram: &Diagram) -> String {
+    let mut result = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    result.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\" \
+                     xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+                     xsi:schemaLocation=\"http://graphml.graphdrawing.org/xmlns http://graphml.graphdrawing.org/xmlns/1.0/graphml.xsd\">\n");
+    for (id, element, name) in KEYS {
+        let kind = if id == "modify" { "boolean" } else { "string" };
+        result.push_str(&format!("  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n", id, element, name, kind));
+    }
+    result.push_str("  <graph id=\"graphr\" edgedefault=\"directed\">\n");
+
+    for node in &diagram.nodes {
+        result.push_str(&format!("    <node id=\"{}\">\n", escape(&node.id)));
+        let label = short_label(&node.template, &node.id, ID_LENGTH).replace('\n', " ");
+        let mut values = vec![("label", label), ("kind", node.kind().to_string()), ("template", node.template.clone())];
+        values.extend(node.command.iter().map(|command| ("command", command.clone())));
+        values.extend(node.location.iter().map(|location| ("location", location.clone())));
+        for (key, value) in values {
+            result.push_str(&format!("      <data key=\"{}\">{}</data>\n", key, escape(&value)));
+        }
+        result.push_str("    </node>\n");
+    }
+
+    // A modification in the template goes both ways, which is an edge in each direction
+    for edge in &diagram.edges {
+        let mut pairs = vec![(&edge.from, &edge.to)];
+        if edge.both {
+            pairs.push((&edge.to, &edge.from));
+        }
+        for (source, target) in pairs {
+            result.push_str(&format!("    <edge source=\"{}\" target=\"{}\">\n", escape(source), escape(target)));
+            result.push_str(&format!("      <data key=\"slot\">{}</data>\n", escape(&edge.slot)));
+            result.push_str(&format!("      <data key=\"modify\">{}</data>\n", edge.modify));
+            result.push_str("    </edge>\n");
+        }
+    }
+    result.push_str("  </graph>\n</graphml>\n");
+    result
+}
+
+/// Escape text for XML content and attribute values
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_graphml() {
+        let db = example();
+        assert_eq!(db.graphml().unwrap(), include_str!("golden/database.graphml"));
+        assert_eq!(db.template.graphml().unwrap(), include_str!("golden/template.graphml"));
+    }
+}
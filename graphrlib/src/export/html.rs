@@ -0,0 +1,369 @@
+/*
+Self-contained HTML viewer of a database.
+The graph is laid out here (layers by longest path from the roots, ordered within a layer to reduce crossings)
+and embedded as JSON next to a small SVG renderer, so the file works offline and without any library.
+The viewer can pan and zoom, search by id or template, and shows the details of a clicked node together with
+its history and future, selected the same way as select_node_history and select_node_future.
+*/
+use std::collections::{BTreeMap, VecDeque};
+use serde_json::{json, Value};
+use crate::export::diagram::{short_label, Diagram};
+use crate::{iso_timestamp, Database, GraphrError, Result};
+
+const ID_LENGTH: usize = 6;
+const LAYER_GAP: f64 = 220.0;
+const ROW_GAP: f64 = 60.0;
+/// Sweeps of the barycenter ordering
+const SWEEPS: usize = 4;
+
+
+impl Database {
+
+    /// Interactive HTML page of the database. Commands are expanded with the root folder.
+    pub fn html(&self, root_folder: &str) -> Result<String> {
+        let diagram = self.diagram()?;
+        let positions = layout(&diagram)?;
+
+        let mut nodes = Vec::new();
+        for (node, (x, y)) in diagram.nodes.iter().zip(positions) {
+            let mut details = vec![json!(["template", node.template])];
+            let mut record = json!({
+                "id": node.id,
+                "calculation": node.calculation,
+                "template": node.template,
+                "label": short_label(&node.template, &node.id, ID_LENGTH).replace('\n', " "),
+                "x": x,
+                "y": y,
+            });
+
+            if let Some(cnode) = self.cnodes.get(&node.id) {
+                details.push(json!(["command", self.get_command(node.id.clone(), root_folder.to_string())?]));
+                match &cnode.run {
+                    Some(run) => {
+                        let status = run.exit_status.map(|s| s.to_string()).unwrap_or_else(|| "killed".to_string());
+                        details.push(json!(["exit status", status]));
+                        details.push(json!(["run", format!("{} - {}", iso_timestamp(run.start), iso_timestamp(run.end))]));
+                        if let Some(user) = &run.user {
+                            details.push(json!(["user", user]));
+                        }
+                    }
+                    None => details.push(json!(["run", "never"])),
+                }
+                record["inputs"] = json!(cnode.inputs().collect::<Vec<_>>());
+                record["outputs"] = json!(cnode.outputs().collect::<Vec<_>>());
+            } else if let Some(dnode) = self.dnodes.get(&node.id) {
+                details.push(json!(["location", format!("{}/{}", root_folder, dnode.location())]));
+                if dnode.revision > 0 {
+                    details.push(json!(["revision", dnode.revision]));
+                }
+                if let Some(hash) = &dnode.hash {
+                    details.push(json!(["hash", hash]));
+                }
+            }
+            record["details"] = Value::Array(details);
+            nodes.push(record);
+        }
+
+        let edges: Vec<Value> = diagram.edges.iter()
+            .map(|e| json!({"from": e.from, "to": e.to, "slot": e.slot, "modify": e.modify}))
+            .collect();
+        let graph = serde_json::to_string(&json!({"nodes": nodes, "edges": edges}))?;
+        // The JSON sits inside a script element, which must not be closed by the data
+        Ok(PAGE.replace("__GRAPH__", &graph.replace("</", "<\\/")))
+    }
+}
+
+
+/// Position of every node of the diagram, left to right in the direction of the data flow
+fn layout(diagram: &Diagram) -> Result<Vec<(f64, f64)>> {
+    let index: BTreeMap<&String, usize> = diagram.nodes.iter().enumerate().map(|(i, n)| (&n.id, i)).collect();
+    let count = diagram.nodes.len();
+    let mut successors = vec![Vec::new(); count];
+    let mut predecessors = vec![Vec::new(); count];
+    for edge in &diagram.edges {
+        let (from, to) = (index[&edge.from], index[&edge.to]);
+        successors[from].push(to);
+        predecessors[to].push(from);
+    }
+
+    // Longest path from the roots, in topological order
+    let mut missing: Vec<usize> = predecessors.iter().map(|p| p.len()).collect();
+    let mut queue: VecDeque<usize> = (0..count).filter(|&i| missing[i] == 0).collect();
+    let mut layer = vec![0; count];
+    while let Some(node) = queue.pop_front() {
+        for &next in &successors[node] {
+            layer[next] = layer[next].max(layer[node] + 1);
+            missing[next] -= 1;
+            if missing[next] == 0 {
+                queue.push_back(next);
+            }
+        }
+    }
+    // Nodes that were not reached wait for each other
+    if let Some(node) = (0..count).find(|&i| missing[i] > 0) {
+        return Err(GraphrError::Cycle(diagram.nodes[node].id.clone()))
+    }
+
+    let layers = layer.iter().max().map_or(0, |l| l + 1);
+    let mut rows: Vec<Vec<usize>> = vec![Vec::new(); layers];
+    for node in 0..count {
+        rows[layer[node]].push(node);
+    }
+
+    // Order every layer by the mean position of the neighbours in the previous (or next) layer
+    let mut position = vec![0.0; count];
+    update_positions(&rows, &mut position);
+    for sweep in 0..SWEEPS {
+        let downward = sweep % 2 == 0;
+        let order: Vec<usize> = if downward { (1..layers).collect() } else { (0..layers.saturating_sub(1)).rev().collect() };
+        for l in order {
+            let neighbours = if downward { &predecessors } else { &successors };
+            let mut keyed: Vec<(f64, usize)> = rows[l].iter().map(|&node| {
+                let adjacent = &neighbours[node];
+                let key = if adjacent.is_empty() {
+                    position[node]
+                } else {
+                    adjacent.iter().map(|&n| position[n]).sum::<f64>() / adjacent.len() as f64
+                };
+                (key, node)
+            }).collect();
+            keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            rows[l] = keyed.into_iter().map(|(_, node)| node).collect();
+            update_positions(&rows, &mut position);
+        }
+    }
+
+    // Layers are centred on the tallest one
+    let tallest = rows.iter().map(|r| r.len()).max().unwrap_or(0) as f64;
+    let mut result = vec![(0.0, 0.0); count];
+    for (l, row) in rows.iter().enumerate() {
+        let offset = (tallest - row.len() as f64) / 2.0;
+        for (i, &node) in row.iter().enumerate() {
+            result[node] = (l as f64 * LAYER_GAP, (i as f64 + offset) * ROW_GAP);
+        }
+    }
+    Ok(result)
+}
+
+fn update_positions(rows: &[Vec<usize>], position: &mut [f64]) {
+    for row in rows {
+        for (i, &node) in row.iter().enumerate() {
+            position[node] = i as f64;
+        }
+    }
+}
+
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>graphr</title>
+<style>
+  html, body { margin: 0; height: 100%; font-family: Helvetica, Arial, sans-serif; font-size: 13px; }
+  #bar { position: fixed; top: 0; left: 0; right: 0; height: 40px; display: flex; align-items: center; gap: 12px;
+         padding: 0 12px; background: #f5f5f5; border-bottom: 1px solid #ccc; z-index: 1; }
+  #search { width: 280px; padding: 4px 6px; }
+  #canvas { position: fixed; top: 41px; left: 0; width: 100%; height: calc(100% - 41px); cursor: grab; }
+  #canvas.dragging { cursor: grabbing; }
+  #panel { position: fixed; top: 52px; right: 12px; width: 380px; max-height: 80%; overflow: auto; background: white;
+           border: 1px solid #ccc; border-radius: 4px; padding: 8px 12px; display: none; }
+  #panel h3 { margin: 4px 0 8px; word-break: break-all; }
+  #panel dt { font-weight: bold; margin-top: 6px; }
+  #panel dd { margin: 2px 0 0; white-space: pre-wrap; word-break: break-all; font-family: monospace; }
+  .legend span { display: inline-block; width: 12px; height: 12px; margin: 0 4px 0 10px; vertical-align: middle; }
+  .node { cursor: pointer; }
+  .node rect { stroke: #6d9eeb; fill: #cfe2f3; }
+  .node ellipse { stroke: #f1c232; fill: #fff2cc; }
+  .node text { font-size: 11px; pointer-events: none; }
+  .edge path { fill: none; stroke: #888; }
+  .edge.modify path { stroke-dasharray: 5 3; }
+  .edge text { font-size: 9px; fill: #666; }
+  .dim { opacity: 0.15; }
+  .match rect, .match ellipse { stroke: #000; stroke-width: 3; }
+  .history rect, .history ellipse { stroke: #38761d; stroke-width: 3; }
+  .future rect, .future ellipse { stroke: #b45f06; stroke-width: 3; }
+  .selected rect, .selected ellipse { stroke: #cc0000; stroke-width: 4; }
+</style>
+</head>
+<body>
+<div id="bar">
+  <input id="search" type="search" placeholder="Search by id or template (enter to jump)">
+  <span id="count"></span>
+  <span class="legend"><span style="background:#38761d"></span>history<span style="background:#b45f06"></span>future<span style="background:#cc0000"></span>selected</span>
+</div>
+<svg id="canvas"><g id="view"><g id="edges"></g><g id="nodes"></g></g></svg>
+<div id="panel"></div>
+<script type="application/json" id="graph">__GRAPH__</script>
+<script>
+"use strict";
+const graph = JSON.parse(document.getElementById("graph").textContent);
+const SVG = "http://www.w3.org/2000/svg";
+const WIDTH = 150, HEIGHT = 32;
+const nodes = new Map(graph.nodes.map(n => [n.id, n]));
+const successors = new Map(graph.nodes.map(n => [n.id, []]));
+const predecessors = new Map(graph.nodes.map(n => [n.id, []]));
+for (const e of graph.edges) {
+  successors.get(e.from).push(e.to);
+  predecessors.get(e.to).push(e.from);
+}
+
+function element(name, attributes, parent) {
+  const result = document.createElementNS(SVG, name);
+  for (const [key, value] of Object.entries(attributes)) result.setAttribute(key, value);
+  parent.appendChild(result);
+  return result;
+}
+
+const edgeElements = graph.edges.map(e => {
+  const from = nodes.get(e.from), to = nodes.get(e.to);
+  const group = element("g", {class: e.modify ? "edge modify" : "edge"}, document.getElementById("edges"));
+  const x1 = from.x + WIDTH / 2, y1 = from.y, x2 = to.x - WIDTH / 2, y2 = to.y, middle = (x1 + x2) / 2;
+  element("path", {d: `M${x1},${y1} C${middle},${y1} ${middle},${y2} ${x2},${y2}`, "marker-end": "url(#arrow)"}, group);
+  element("text", {x: middle, y: (y1 + y2) / 2 - 3, "text-anchor": "middle"}, group).textContent = e.slot;
+  return [e, group];
+});
+const marker = element("marker", {id: "arrow", viewBox: "0 0 10 10", refX: 10, refY: 5, markerWidth: 6, markerHeight: 6, orient: "auto"},
+                       element("defs", {}, document.getElementById("canvas")));
+element("path", {d: "M0,0 L10,5 L0,10 z", fill: "#888"}, marker);
+
+const nodeElements = new Map(graph.nodes.map(n => {
+  const group = element("g", {class: "node", transform: `translate(${n.x},${n.y})`}, document.getElementById("nodes"));
+  if (n.calculation) {
+    element("rect", {x: -WIDTH / 2, y: -HEIGHT / 2, width: WIDTH, height: HEIGHT, rx: 8}, group);
+  } else {
+    element("ellipse", {rx: WIDTH / 2, ry: HEIGHT / 2}, group);
+  }
+  element("text", {"text-anchor": "middle", dy: "0.35em"}, group).textContent = n.label;
+  element("title", {}, group).textContent = n.id;
+  group.addEventListener("click", event => { event.stopPropagation(); select(n.id); });
+  return [n.id, group];
+}));
+
+// Same selection as select_node_history / select_node_future: every calculation with a path to (or from)
+// the node, together with all inputs and outputs of those calculations
+function reachable(id, neighbours) {
+  const seen = new Set([id]), queue = [id];
+  while (queue.length) {
+    for (const next of neighbours.get(queue.pop())) {
+      if (!seen.has(next)) { seen.add(next); queue.push(next); }
+    }
+  }
+  return seen;
+}
+function selection(id, neighbours) {
+  const result = new Set();
+  for (const n of reachable(id, neighbours)) {
+    const node = nodes.get(n);
+    if (!node.calculation) continue;
+    result.add(n);
+    node.inputs.forEach(i => result.add(i));
+    node.outputs.forEach(o => result.add(o));
+  }
+  return result;
+}
+
+function mark(classes) {
+  const highlighted = id => classes.has(id);
+  for (const [id, group] of nodeElements) {
+    const base = "node" + (classes.has(id) ? " " + classes.get(id) : "");
+    group.setAttribute("class", classes.size && !highlighted(id) ? base + " dim" : base);
+  }
+  for (const [e, group] of edgeElements) {
+    const base = e.modify ? "edge modify" : "edge";
+    group.setAttribute("class", classes.size && !(highlighted(e.from) && highlighted(e.to)) ? base + " dim" : base);
+  }
+}
+
+const panel = document.getElementById("panel");
+function select(id) {
+  const node = nodes.get(id);
+  const classes = new Map();
+  selection(id, successors).forEach(n => classes.set(n, "future"));
+  selection(id, predecessors).forEach(n => classes.set(n, "history"));
+  classes.set(id, "selected");
+  mark(classes);
+
+  panel.replaceChildren();
+  const title = document.createElement("h3");
+  title.textContent = (node.calculation ? "Calculation " : "Data ") + id;
+  const list = document.createElement("dl");
+  for (const [key, value] of node.details) {
+    const term = document.createElement("dt"), description = document.createElement("dd");
+    term.textContent = key;
+    description.textContent = value;
+    list.append(term, description);
+  }
+  panel.append(title, list);
+  panel.style.display = "block";
+}
+
+// Pan and zoom
+const canvas = document.getElementById("canvas"), view = document.getElementById("view");
+let scale = 1, dx = 60 + WIDTH / 2, dy = 40 + HEIGHT;
+function transform() { view.setAttribute("transform", `translate(${dx},${dy}) scale(${scale})`); }
+transform();
+canvas.addEventListener("wheel", event => {
+  event.preventDefault();
+  const factor = Math.exp(-event.deltaY * 0.001);
+  const box = canvas.getBoundingClientRect(), x = event.clientX - box.left, y = event.clientY - box.top;
+  dx = x - (x - dx) * factor;
+  dy = y - (y - dy) * factor;
+  scale *= factor;
+  transform();
+}, {passive: false});
+let drag = null;
+canvas.addEventListener("mousedown", event => { drag = {x: event.clientX - dx, y: event.clientY - dy, moved: false}; canvas.classList.add("dragging"); });
+window.addEventListener("mousemove", event => {
+  if (!drag) return;
+  dx = event.clientX - drag.x;
+  dy = event.clientY - drag.y;
+  drag.moved = true;
+  transform();
+});
+window.addEventListener("mouseup", () => { canvas.classList.remove("dragging"); });
+canvas.addEventListener("click", () => {
+  if (drag && drag.moved) { drag = null; return; }
+  drag = null;
+  mark(new Map());
+  panel.style.display = "none";
+});
+
+// Search
+const search = document.getElementById("search"), count = document.getElementById("count");
+let matches = [];
+search.addEventListener("input", () => {
+  const query = search.value.trim().toLowerCase();
+  matches = query ? graph.nodes.filter(n => n.id.toLowerCase().includes(query) || n.template.toLowerCase().includes(query)) : [];
+  mark(new Map(matches.map(n => [n.id, "match"])));
+  count.textContent = query ? `${matches.length} found` : "";
+});
+search.addEventListener("keydown", event => {
+  if (event.key !== "Enter" || !matches.length) return;
+  const box = canvas.getBoundingClientRect();
+  const node = matches.shift();
+  matches.push(node);
+  dx = box.width / 2 - node.x * scale;
+  dy = box.height / 2 - node.y * scale;
+  transform();
+});
+</script>
+</body>
+</html>
+"##;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::example;
+
+    /// The graph data of the page, the rest of it is the same for every database
+    #[test]
+    fn golden_graph() {
+        let html = example().html("data").unwrap();
+        let graph = html.split("<script type=\"application/json\" id=\"graph\">").nth(1).unwrap()
+            .split("</script>").next().unwrap();
+        assert_eq!(serde_json::from_str::<Value>(graph).unwrap(), serde_json::from_str::<Value>(include_str!("golden/html.json")).unwrap());
+    }
+}
@@ -0,0 +1,134 @@
+/*
+GNU make export.
+Every calculation is a rule whose targets are its outputs (grouped with &: when there are several,
+which needs GNU make 4.3) and whose prerequisites are its inputs. A calculation that modifies data has the
+calculations that read the data before it as order-only prerequisites. Recipes are the commands of
+get_command with the root folder replaced by $(ROOT).
+Data modified in place is represented by marker files in .graphr/revisions, as make can not have the same
+file as prerequisite and target of a rule.
+*/
+use std::collections::BTreeMap;
+use crate::export::identifier;
+use crate::{CNode, Database, IdCTemplate, IdD, Result};
+
+const MARKERS: &str = ".graphr/revisions";
+/// Markers of calculations that produce nothing
+const CALCULATIONS: &str = ".graphr/calculations";
+const ROOT: &str = "\u{1}";
+
+
+impl Database {
+
+    /// Generate a Makefile that produces all data of the database
+    pub fn makefile(&self, root_folder: &str) -> Result<String> {
+        let mut result = String::from("# Generated by graphr\n");
+        result.push_str(&format!("ROOT ?= {}\n", escape(root_folder)));
+        result.push_str("SHELL := /bin/sh\n.ONESHELL:\n\n");
+
+        let mut by_template: BTreeMap<&IdCTemplate, Vec<String>> = BTreeMap::new();
+        let mut rules = String::new();
+
+        for cnode in self.cnodes.values() {
+            let targets = self.make_targets(cnode);
+            let mut prerequisites: Vec<String> = Vec::new();
+            for path in cnode.inputs().map(|d_id| self.make_path(d_id)) {
+                if !prerequisites.contains(&path) {
+                    prerequisites.push(path);
+                }
+            }
+            let mut readers: Vec<String> = Vec::new();
+            for target in self.readers_before(cnode).flat_map(|reader| self.make_targets(reader)) {
+                if !readers.contains(&target) {
+                    readers.push(target);
+                }
+            }
+
+            // Commands run in a shell started by make, so $ has to be escaped
+            let command = escape(&self.get_command(cnode.id.clone(), ROOT.to_string())?).replace(ROOT, "$(ROOT)");
+            let separator = if targets.len() > 1 { " &:" } else { ":" };
+
+            rules.push_str(&format!("# {} ({})\n", cnode.template, cnode.id));
+            rules.push_str(&rule_line(&targets, separator, &prerequisites, &readers));
+            for line in command.lines() {
+                rules.push_str(&format!("\t{}\n", line));
+            }
+            let markers: Vec<&str> = targets.iter().filter(|t| !t.starts_with("$(ROOT)")).map(|t| t.as_str()).collect();
+            if !markers.is_empty() {
+                rules.push_str(&format!("\t@mkdir -p {} {} && touch {}\n", MARKERS, CALCULATIONS, markers.join(" ")));
+            }
+            rules.push('\n');
+
+            by_template.entry(&cnode.template).or_default().extend(targets);
+        }
+
+        // Aggregate targets: everything, and everything of a template
+        let mut names: Vec<String> = vec!["all".to_string()];
+        let mut aggregates = String::new();
+        for (template_id, targets) in &by_template {
+            let mut name = identifier(template_id);
+            while names.contains(&name) {
+                name.push('_');
+            }
+            aggregates.push_str(&rule_line(&[name.clone()], ":", targets, &[]));
+            names.push(name);
+        }
+        let everything: Vec<String> = by_template.values().flatten().cloned().collect();
+
+        result.push_str(&format!(".PHONY: {}\n\n", names.join(" ")));
+        result.push_str(&rule_line(&["all".to_string()], ":", &everything, &[]));
+        result.push('\n');
+        result.push_str(&aggregates);
+        if !aggregates.is_empty() {
+            result.push('\n');
+        }
+        result.push_str(&rules);
+        Ok(result)
+    }
+
+    /// Files the calculation produces. Calculations without any get a marker of their own.
+    fn make_targets(&self, cnode: &CNode) -> Vec<String> {
+        let mut targets: Vec<String> = cnode.outcoming.iter().map(|d_id| self.make_path(d_id)).collect();
+        targets.extend(cnode.modified.iter().map(|(_, to_id)| format!("{}/{}", MARKERS, escape(to_id))));
+        if targets.is_empty() {
+            targets.push(format!("{}/{}", CALCULATIONS, escape(&cnode.id)));
+        }
+        targets
+    }
+
+    /// Path of the data node in the Makefile. Revisions are represented by their marker.
+    fn make_path(&self, d_id: &IdD) -> String {
+        if self.is_revision(d_id) {
+            format!("{}/{}", MARKERS, escape(d_id))
+        } else {
+            format!("$(ROOT)/{}", escape(self.location(d_id)))
+        }
+    }
+}
+
+fn rule_line(targets: &[String], separator: &str, prerequisites: &[String], order_only: &[String]) -> String {
+    let mut line = format!("{}{}", targets.join(" "), separator);
+    if !prerequisites.is_empty() {
+        line.push_str(&format!(" {}", prerequisites.join(" ")));
+    }
+    if !order_only.is_empty() {
+        line.push_str(&format!(" | {}", order_only.join(" ")));
+    }
+    line.push('\n');
+    line
+}
+
+/// Escape $ for make
+fn escape(value: &str) -> String {
+    value.replace('$', "$$")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_makefile() {
+        assert_eq!(example().makefile("data").unwrap(), include_str!("golden/Makefile"));
+    }
+}
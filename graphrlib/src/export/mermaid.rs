@@ -0,0 +1,83 @@
+/*
+Mermaid flowchart export, for diagrams in Markdown documents.
+Calculations are rounded boxes and data are cylinders; the kind is also the class of the node, so it can be restyled.
+Mermaid nodes have no attributes, so the command of a calculation is written as a comment above it.
+*/
+use std::collections::BTreeMap;
+use crate::export::diagram::{short_label, Diagram};
+use crate::{Database, DatabaseTemplate, Result};
+
+const ID_LENGTH: usize = 6;
+
+
+impl DatabaseTemplate {
+
+    /// Mermaid flowchart of the template
+    pub fn mermaid(&self) -> Result<String> {
+        Ok(render(&self.diagram()?))
+    }
+}
+
+
+impl Database {
+
+    /// Mermaid flowchart of the database
+    pub fn mermaid(&self) -> Result<String> {
+        Ok(render(&self.diagram()?))
+    }
+}
+
+
+fn render(diagram: &Diagram) -> String {
+    let mut result = String::from("flowchart TD\n");
+    result.push_str("    classDef calculation fill:#cfe2f3,stroke:#6d9eeb\n");
+    result.push_str("    classDef data fill:#fff2cc,stroke:#f1c232\n");
+
+    // Mermaid ids can not contain arbitrary characters, nodes are numbered instead
+    let mut names: BTreeMap<&String, String> = BTreeMap::new();
+    for (n, node) in diagram.nodes.iter().enumerate() {
+        let name = format!("n{}", n);
+        let label = text(&short_label(&node.template, &node.id, ID_LENGTH));
+        if let Some(command) = &node.command {
+            result.push_str(&format!("    %% {}: {}\n", node.id, command.replace('\n', " ")));
+        }
+        if node.calculation {
+            result.push_str(&format!("    {}(\"{}\"):::calculation\n", name, label));
+        } else {
+            result.push_str(&format!("    {}[(\"{}\")]:::data\n", name, label));
+        }
+        names.insert(&node.id, name);
+    }
+
+    for edge in &diagram.edges {
+        let arrow = match (edge.modify, edge.both) {
+            (true, true) => "<-.->",
+            (true, false) => "-.->",
+            _ => "-->",
+        };
+        result.push_str(&format!("    {} {}|\"{}\"| {}\n", names[&edge.from], arrow, text(&edge.slot), names[&edge.to]));
+    }
+    result
+}
+
+/// Text of a quoted label. Characters mermaid would interpret are written as entity codes.
+fn text(value: &str) -> String {
+    value.replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br>")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_mermaid() {
+        let db = example();
+        assert_eq!(db.mermaid().unwrap(), include_str!("golden/database.mmd"));
+        assert_eq!(db.template.mermaid().unwrap(), include_str!("golden/template.mmd"));
+    }
+}
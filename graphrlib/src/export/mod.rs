@@ -0,0 +1,134 @@
+/*
+Export of a database to the formats of other workflow managers, provenance and graph drawing tools.
+Every format lives in its own file; the helpers here are shared between them.
+*/
+use std::path::Path;
+use crate::{CNode, CNodeTemplate, Database, IdD};
+
+mod cwl;
+mod diagram;
+mod dot;
+mod graphml;
+mod html;
+mod makefile;
+mod mermaid;
+mod nextflow;
+mod prov;
+mod snakemake;
+
+pub use dot::DotOptions;
+pub use snakemake::SnakemakeOptions;
+
+
+/// Names of the input, output and modify slots of a calculation template.
+/// Slots are named after their data template; repeated names get a number appended.
+pub(crate) struct Slots {
+    pub incoming: Vec<String>,
+    pub outcoming: Vec<String>,
+    pub modified: Vec<String>,
+}
+
+impl Slots {
+    pub fn new(template: &CNodeTemplate) -> Self {
+        let mut taken: Vec<String> = Vec::new();
+        let mut name = |d_template: &String| {
+            let base = identifier(d_template);
+            let mut candidate = base.clone();
+            let mut n = 1;
+            while taken.contains(&candidate) {
+                n += 1;
+                candidate = format!("{}_{}", base, n);
+            }
+            taken.push(candidate.clone());
+            candidate
+        };
+
+        let incoming = template.incoming.iter().map(&mut name).collect();
+        let outcoming = template.outcoming.iter().map(&mut name).collect();
+        let modified = template.modified.iter().map(&mut name).collect();
+        Slots { incoming, outcoming, modified }
+    }
+}
+
+/// Turn a template name into an identifier (letters, digits and underscores, not starting with a digit)
+pub(crate) fn identifier(name: &str) -> String {
+    let mut result: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if result.is_empty() || result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// Double quoted string literal with all special characters escaped (valid in json and python)
+pub(crate) fn quote(value: &str) -> String {
+    serde_json::to_string(value).expect("Strings can always be serialized")
+}
+
+/// Replace the $i_N, $o_N and $m_N slots of a template command.
+/// Replaced in reverse order so that $i_1 does not clobber $i_10.
+pub(crate) fn fill_slots(command: &str, template: &CNodeTemplate, slot: impl Fn(char, usize) -> String) -> String {
+    let mut result = command.to_string();
+    for (kind, count) in [('i', template.incoming.len()), ('o', template.outcoming.len()), ('m', template.modified.len())] {
+        for n in (0..count).rev() {
+            result = result.replace(&format!("${}_{}", kind, n), &slot(kind, n));
+        }
+    }
+    result
+}
+
+impl Database {
+
+    /// Place of the data node in the root folder. Revisions share the location of the data they were made from.
+    pub(crate) fn location<'a>(&'a self, d_id: &'a IdD) -> &'a IdD {
+        self.dnodes.get(d_id).map(|d| d.location()).unwrap_or(d_id)
+    }
+
+    /// Calculations that read the data the calculation modifies, before it is modified. They have to run first.
+    pub(crate) fn readers_before<'a>(&'a self, cnode: &'a CNode) -> impl Iterator<Item = &'a CNode> + 'a {
+        self.cnodes.values().filter(move |other| other.id != cnode.id
+            && cnode.modified.iter().any(|(from, _)| other.incoming.contains(from)))
+    }
+
+    pub(crate) fn is_revision(&self, d_id: &IdD) -> bool {
+        self.dnodes.get(d_id).is_some_and(|d| d.revision > 0)
+    }
+
+    /// Whether the data node is stored as a directory in the root folder.
+    /// Data that does not exist yet is assumed to be a file.
+    pub(crate) fn is_directory(&self, d_id: &IdD, root_folder: &str) -> bool {
+        self.dnodes.get(d_id).is_some_and(|d| Path::new(root_folder).join(d.location()).is_dir())
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+    use crate::{Database, MetadataValue};
+
+    /// F filters x into y with a parameter that needs quoting, M marks y in place and S counts the lines of y.
+    /// The generated ids are replaced by c<template> and <template><revision>, so that the output is always the same.
+    pub(crate) fn example() -> Database {
+        let mut db = Database::new();
+        for name in ["x", "y", "z"] {
+            db.template_register_dnode(name.to_string());
+        }
+        db.template_register_cnode("F".to_string(), "grep param(pattern) input(x) > output(y)".to_string()).unwrap();
+        db.template_register_cnode("M".to_string(), "echo checked >> modify(y)".to_string()).unwrap();
+        db.template_register_cnode("S".to_string(), "wc -l input(y) > output(z)".to_string()).unwrap();
+        let params = BTreeMap::from([("pattern".to_string(), MetadataValue::Text("it's here".to_string()))]);
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), "x0".to_string())]), None, None, Some(params)).unwrap()
+            .merge_into(&mut db).unwrap();
+
+        let mut json = serde_json::to_string(&db).unwrap();
+        for cnode in db.cnodes.values() {
+            json = json.replace(&format!("\"{}\"", cnode.id), &format!("\"c{}\"", cnode.template));
+        }
+        for dnode in db.dnodes.values().filter(|d| d.id != "x0") {
+            json = json.replace(&format!("\"{}\"", dnode.id), &format!("\"{}{}\"", dnode.template, dnode.revision));
+        }
+        serde_json::from_str(&json).unwrap()
+    }
+}
@@ -0,0 +1,202 @@
+/*
+Nextflow (DSL2) export.
+Every calculation template becomes a process and the calculations of the template are the rows of
+the channel the process is invoked with. All data travels through a single channel of [data node id, file]
+pairs: root inputs are read from the root folder and every process adds what it produces.
+Each calculation picks its inputs from that channel by id, so the order of the calculations is kept.
+Outputs are published to the root folder under their location.
+Values of the parameters of a calculation travel in its row, next to the ids and locations of its data.
+*/
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use petgraph::algo::toposort;
+use petgraph::graph::DiGraph;
+use crate::export::{fill_slots, identifier, Slots};
+use crate::params::shell_word;
+use crate::{CNode, CNodeTemplate, Database, GraphrError, IdCTemplate, IdD, Result};
+
+/// Names that can not be used for processes or variables
+const RESERVED: [&str; 17] = ["as", "calculation", "data", "def", "for", "gather", "if", "in", "input", "output",
+                              "params", "path", "process", "script", "tuple", "val", "workflow"];
+
+
+impl Database {
+
+    /// Generate a Nextflow DSL2 script that produces all data of the database
+    pub fn nextflow_script(&self, root_folder: &str) -> Result<String> {
+        let mut by_template: BTreeMap<&IdCTemplate, Vec<&CNode>> = BTreeMap::new();
+        for cnode in self.cnodes.values() {
+            by_template.entry(&cnode.template).or_default().push(cnode);
+        }
+
+        let mut result = String::from("// Generated by graphr\nnextflow.enable.dsl = 2\n\n");
+        result.push_str(&format!("params.root = {}\n\n", groovy_string(root_folder)));
+        result.push_str(GATHER);
+
+        let mut workflow = String::from("workflow {\n");
+        let producers: HashMap<&IdD, &CNode> = self.cnodes.values()
+            .flat_map(|c| c.outputs().map(move |d_id| (d_id, c)))
+            .collect();
+
+        // Data that is not produced by any calculation comes from the root folder
+        let roots: BTreeSet<&IdD> = self.cnodes.values()
+            .flat_map(|c| c.inputs())
+            .filter(|d_id| !producers.contains_key(d_id))
+            .collect();
+        let rows: Vec<String> = roots.iter()
+            .map(|d_id| format!("        [{}, file(\"${{params.root}}/{}\")],\n", groovy_string(d_id), groovy_escape(self.location(d_id))))
+            .collect();
+        workflow.push_str(&format!("    data = Channel.of(\n{}    )\n", rows.concat()));
+
+        for template_id in self.template_order(&by_template, &producers)? {
+            let cnodes = &by_template[template_id];
+            let template = self.get_template_cnode(template_id).ok_or_else(|| {
+                GraphrError::DanglingReference { node: cnodes[0].id.clone(), reference: template_id.to_string() }
+            })?;
+            let slots = Slots::new(template);
+            let name = process_name(template_id);
+            result.push_str(&process(&name, template, &slots));
+
+            // [calculation id, [input ids], output locations..., ids of the new revisions..., parameter values...]
+            let rows: Vec<String> = cnodes.iter().map(|c| {
+                let inputs: Vec<String> = c.inputs().map(|d_id| groovy_string(d_id)).collect();
+                let mut row = vec![groovy_string(&c.id), format!("[{}]", inputs.join(", "))];
+                row.extend(c.outcoming.iter().map(|d_id| groovy_string(self.location(d_id))));
+                row.extend(c.modified.iter().map(|(_, to_id)| groovy_string(to_id)));
+                let values = template.param_values(&c.params)?;
+                row.extend(template.params.iter().map(|param| groovy_string(&shell_word(&values[&param.name]))));
+                Ok(format!("        [{}],\n", row.join(", ")))
+            }).collect::<Result<_>>()?;
+            let emits: Vec<String> = slots.outcoming.iter().chain(&slots.modified).map(|slot| format!("{}.out.{}", name, variable(slot))).collect();
+
+            let count = template.incoming.len() + template.modified.len();
+            workflow.push_str(&format!("\n    {}(gather(Channel.of(\n{}    ), data, {}))\n", name, rows.concat(), count));
+            if !emits.is_empty() {
+                workflow.push_str(&format!("    data = data.mix({})\n", emits.join(", ")));
+            }
+        }
+        workflow.push_str("}\n");
+        result.push_str(&workflow);
+        Ok(result)
+    }
+
+    /// Processes can only be invoked once, so the templates have to be in the order in which their calculations depend on each other.
+    fn template_order<'a>(&self, by_template: &BTreeMap<&'a IdCTemplate, Vec<&CNode>>, producers: &HashMap<&IdD, &CNode>) -> Result<Vec<&'a IdCTemplate>> {
+        let mut graph = DiGraph::<&IdCTemplate, ()>::new();
+        let indices: BTreeMap<&IdCTemplate, _> = by_template.keys().map(|t| (*t, graph.add_node(*t))).collect();
+
+        for (template_id, cnodes) in by_template {
+            for d_id in cnodes.iter().flat_map(|c| c.inputs()) {
+                if let Some(producer) = producers.get(d_id) {
+                    if producer.template == **template_id {
+                        // The template would have to run on its own results
+                        return Err(GraphrError::Cycle(template_id.to_string()))
+                    }
+                    graph.update_edge(indices[&producer.template], indices[template_id], ());
+                }
+            }
+        }
+
+        let order = toposort(&graph, None).map_err(|cycle| GraphrError::Cycle(graph[cycle.node_id()].to_string()))?;
+        Ok(order.into_iter().map(|i| graph[i]).collect())
+    }
+}
+
+/// Pairs every calculation with the files of its inputs (looked up by data node id)
+const GATHER: &str = r#"def gather(calculations, data, count) {
+    def result = calculations.map { row -> [row, []] }
+    for (int i = 0; i < count; i++) {
+        def index = i
+        result = result
+            .map { row, files -> [row[1][index], row, files] }
+            .combine(data, by: 0)
+            .map { id, row, files, file -> [row, files + [file]] }
+    }
+    return result.map { row, files -> [row[0]] + files + row.drop(2) }
+}
+
+"#;
+
+/// Process of a calculation template
+fn process(name: &str, template: &CNodeTemplate, slots: &Slots) -> String {
+    let mut result = format!("process {} {{\n    tag \"${{calculation}}\"\n", name);
+    result.push_str("    publishDir params.root, mode: 'copy'\n");
+    if !template.modified.is_empty() {
+        // Work on a copy, the published result replaces the original
+        result.push_str("    stageInMode 'copy'\n");
+    }
+    if template.resources.cores > 1 {
+        result.push_str(&format!("    cpus {}\n", template.resources.cores));
+    }
+    if let Some(memory) = template.resources.memory {
+        result.push_str(&format!("    memory '{} MB'\n", memory));
+    }
+
+    let mut inputs = vec!["val(calculation)".to_string()];
+    inputs.extend(slots.incoming.iter().chain(&slots.modified).map(|slot| format!("path({})", variable(slot))));
+    inputs.extend(slots.outcoming.iter().map(|slot| format!("val({})", variable(slot))));
+    inputs.extend(slots.modified.iter().map(|slot| format!("val({}_revision)", variable(slot))));
+    inputs.extend(template.params.iter().map(|param| format!("val({})", param_variable(&param.name))));
+    result.push_str(&format!("\n    input:\n    tuple {}\n", inputs.join(", ")));
+
+    if !slots.outcoming.is_empty() || !slots.modified.is_empty() {
+        result.push_str("\n    output:\n");
+        for slot in &slots.outcoming {
+            let slot = variable(slot);
+            result.push_str(&format!("    tuple val({}), path(\"${{{}}}\"), emit: {}\n", slot, slot, slot));
+        }
+        for slot in &slots.modified {
+            let slot = variable(slot);
+            result.push_str(&format!("    tuple val({}_revision), path(\"${{{}}}\", includeInputs: true), emit: {}\n", slot, slot, slot));
+        }
+    }
+
+    // Slots are marked first, so that escaping the command does not touch them
+    let marked = fill_slots(&template.command, template, |kind, n| format!("\u{1}{}{}\u{1}", kind, n));
+    let marked = template.fill_params(&marked, |n, _| format!("\u{1}p{}\u{1}", n));
+    let mut command = groovy_escape(&marked);
+    for (kind, names) in [('i', &slots.incoming), ('o', &slots.outcoming), ('m', &slots.modified)] {
+        for (n, slot) in names.iter().enumerate() {
+            command = command.replace(&format!("\u{1}{}{}\u{1}", kind, n), &format!("${{{}}}", variable(slot)));
+        }
+    }
+    for (n, param) in template.params.iter().enumerate() {
+        command = command.replace(&format!("\u{1}p{}\u{1}", n), &format!("${{{}}}", param_variable(&param.name)));
+    }
+    result.push_str(&format!("\n    script:\n    \"\"\"\n    {}\n    \"\"\"\n}}\n\n", command));
+    result
+}
+
+fn process_name(template_id: &str) -> String {
+    variable(&identifier(template_id))
+}
+
+/// Variables of parameters are prefixed, so that they do not clash with the slots
+fn param_variable(name: &str) -> String {
+    format!("param_{}", identifier(name))
+}
+
+/// Reserved names get an underscore appended
+fn variable(slot: &str) -> String {
+    if RESERVED.contains(&slot) { format!("{}_", slot) } else { slot.to_string() }
+}
+
+/// Escape text for a double quoted (or triple quoted) groovy string, so that nothing is interpolated
+fn groovy_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('$', "\\$").replace('"', "\\\"")
+}
+
+/// Single quoted groovy string (no interpolation)
+fn groovy_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_script() {
+        assert_eq!(example().nextflow_script("data").unwrap(), include_str!("golden/main.nf"));
+    }
+}
@@ -0,0 +1,268 @@
+/*
+W3C PROV export (PROV-JSON and PROV-N).
+Data nodes are entities, calculations are activities that used their inputs and generated their outputs,
+and calculation templates are plans the activities were associated with. Where a calculation has been run,
+its start and end time and the user that ran it (as agent) are added.
+Revisions made by modifying data in place are derived from the data they were made from (prov:Revision).
+*/
+use std::collections::BTreeSet;
+use serde_json::{json, Map, Value};
+use crate::{iso_timestamp, Database, Result};
+
+const NAMESPACES: [(&str, &str); 4] = [
+    ("graphr", "urn:graphr:"),
+    ("node", "urn:graphr:node:"),
+    ("template", "urn:graphr:template:"),
+    ("user", "urn:graphr:user:"),
+];
+
+
+/// Value of a PROV attribute
+enum Attribute {
+    Text(String),
+    Number(i64),
+    /// Qualified name, for example prov:Plan
+    Name(String),
+}
+
+type Attributes = Vec<(&'static str, Attribute)>;
+
+/// Everything of the database in PROV terms. Identifiers are qualified names.
+#[derive(Default)]
+struct ProvDocument {
+    entities: Vec<(String, Attributes)>,
+    /// id, start time, end time
+    activities: Vec<(String, Option<u64>, Option<u64>, Attributes)>,
+    agents: Vec<(String, Attributes)>,
+    /// activity, entity, time, role
+    used: Vec<(String, String, Option<u64>, String)>,
+    /// entity, activity, time, role
+    generated: Vec<(String, String, Option<u64>, String)>,
+    /// activity, agent, plan
+    associated: Vec<(String, Option<String>, String)>,
+    /// generated entity, used entity, activity
+    revisions: Vec<(String, String, String)>,
+}
+
+
+impl Database {
+
+    /// Provenance of the database as a PROV-JSON document
+    pub fn prov_json(&self) -> Result<Value> {
+        let document = self.prov_document();
+        let mut result = Map::new();
+        result.insert("prefix".to_string(), NAMESPACES.iter().map(|(p, uri)| (p.to_string(), json!(uri))).collect());
+
+        let mut section = |name: &str, records: Map<String, Value>| {
+            if !records.is_empty() {
+                result.insert(name.to_string(), Value::Object(records));
+            }
+        };
+
+        section("entity", document.entities.iter().map(|(id, attributes)| (id.clone(), json_attributes(Map::new(), attributes))).collect());
+        section("activity", document.activities.iter().map(|(id, start, end, attributes)| {
+            let mut record = Map::new();
+            if let Some(start) = start {
+                record.insert("prov:startTime".to_string(), json!(iso_timestamp(*start)));
+            }
+            if let Some(end) = end {
+                record.insert("prov:endTime".to_string(), json!(iso_timestamp(*end)));
+            }
+            (id.clone(), json_attributes(record, attributes))
+        }).collect());
+        section("agent", document.agents.iter().map(|(id, attributes)| (id.clone(), json_attributes(Map::new(), attributes))).collect());
+
+        // Relations are identified by blank nodes
+        section("used", document.used.iter().enumerate().map(|(n, (activity, entity, time, role))| {
+            let mut record = json!({"prov:activity": activity, "prov:entity": entity, "prov:role": role});
+            if let Some(time) = time {
+                record["prov:time"] = json!(iso_timestamp(*time));
+            }
+            (format!("_:u{}", n), record)
+        }).collect());
+        section("wasGeneratedBy", document.generated.iter().enumerate().map(|(n, (entity, activity, time, role))| {
+            let mut record = json!({"prov:entity": entity, "prov:activity": activity, "prov:role": role});
+            if let Some(time) = time {
+                record["prov:time"] = json!(iso_timestamp(*time));
+            }
+            (format!("_:g{}", n), record)
+        }).collect());
+        section("wasAssociatedWith", document.associated.iter().enumerate().map(|(n, (activity, agent, plan))| {
+            let mut record = json!({"prov:activity": activity, "prov:plan": plan});
+            if let Some(agent) = agent {
+                record["prov:agent"] = json!(agent);
+            }
+            (format!("_:a{}", n), record)
+        }).collect());
+        section("wasDerivedFrom", document.revisions.iter().enumerate().map(|(n, (generated, used, activity))| {
+            let record = json!({
+                "prov:generatedEntity": generated,
+                "prov:usedEntity": used,
+                "prov:activity": activity,
+                "prov:type": {"$": "prov:Revision", "type": "prov:QUALIFIED_NAME"},
+            });
+            (format!("_:d{}", n), record)
+        }).collect());
+
+        Ok(Value::Object(result))
+    }
+
+    /// Provenance of the database in PROV-N notation
+    pub fn prov_n(&self) -> Result<String> {
+        let document = self.prov_document();
+        let time = |t: &Option<u64>| t.map(iso_timestamp).unwrap_or_else(|| "-".to_string());
+
+        let mut lines = vec!["document".to_string()];
+        lines.extend(NAMESPACES.iter().map(|(prefix, uri)| format!("  prefix {} <{}>", prefix, uri)));
+        for (id, attributes) in &document.entities {
+            lines.push(format!("  entity({}{})", id, n_attributes(attributes)));
+        }
+        for (id, start, end, attributes) in &document.activities {
+            lines.push(format!("  activity({}, {}, {}{})", id, time(start), time(end), n_attributes(attributes)));
+        }
+        for (id, attributes) in &document.agents {
+            lines.push(format!("  agent({}{})", id, n_attributes(attributes)));
+        }
+        for (activity, entity, t, role) in &document.used {
+            lines.push(format!("  used({}, {}, {}, [prov:role={}])", activity, entity, time(t), n_string(role)));
+        }
+        for (entity, activity, t, role) in &document.generated {
+            lines.push(format!("  wasGeneratedBy({}, {}, {}, [prov:role={}])", entity, activity, time(t), n_string(role)));
+        }
+        for (activity, agent, plan) in &document.associated {
+            lines.push(format!("  wasAssociatedWith({}, {}, {})", activity, agent.as_deref().unwrap_or("-"), plan));
+        }
+        for (generated, used, activity) in &document.revisions {
+            lines.push(format!("  wasDerivedFrom({}, {}, {}, -, -, [prov:type='prov:Revision'])", generated, used, activity));
+        }
+        lines.push("endDocument\n".to_string());
+        Ok(lines.join("\n"))
+    }
+
+    fn prov_document(&self) -> ProvDocument {
+        let mut document = ProvDocument::default();
+
+        for dnode in self.dnodes.values() {
+            let mut attributes: Attributes = vec![
+                ("prov:type", Attribute::Name("graphr:Data".to_string())),
+                ("graphr:template", Attribute::Text(dnode.template.clone())),
+                ("graphr:location", Attribute::Text(dnode.location().clone())),
+            ];
+            if let Some(hash) = &dnode.hash {
+                attributes.push(("graphr:hash", Attribute::Text(hash.clone())));
+            }
+            if dnode.revision > 0 {
+                attributes.push(("graphr:revision", Attribute::Number(dnode.revision as i64)));
+            }
+            document.entities.push((qualified("node", &dnode.id), attributes));
+        }
+
+        let templates: BTreeSet<&String> = self.cnodes.values().map(|c| &c.template).collect();
+        for template_id in templates {
+            let mut attributes: Attributes = vec![("prov:type", Attribute::Name("prov:Plan".to_string()))];
+            if let Some(template) = self.get_template_cnode(template_id) {
+                attributes.push(("graphr:command", Attribute::Text(template.marked_command())));
+            }
+            document.entities.push((qualified("template", template_id), attributes));
+        }
+
+        let mut users = BTreeSet::new();
+        for cnode in self.cnodes.values() {
+            let id = qualified("node", &cnode.id);
+            let run = cnode.run.as_ref();
+            let mut attributes: Attributes = vec![
+                ("prov:type", Attribute::Name("graphr:Calculation".to_string())),
+                ("graphr:template", Attribute::Text(cnode.template.clone())),
+            ];
+            if let Some(status) = run.and_then(|r| r.exit_status) {
+                attributes.push(("graphr:exitStatus", Attribute::Number(status as i64)));
+            }
+            document.activities.push((id.clone(), run.map(|r| r.start), run.map(|r| r.end), attributes));
+
+            // Data only exists after a successful run
+            let start = run.map(|r| r.start);
+            let end = run.filter(|r| r.success()).map(|r| r.end);
+            for (n, d_id) in cnode.incoming.iter().enumerate() {
+                document.used.push((id.clone(), qualified("node", d_id), start, format!("i_{}", n)));
+            }
+            for (n, d_id) in cnode.outcoming.iter().enumerate() {
+                document.generated.push((qualified("node", d_id), id.clone(), end, format!("o_{}", n)));
+            }
+            for (n, (from_id, to_id)) in cnode.modified.iter().enumerate() {
+                document.used.push((id.clone(), qualified("node", from_id), start, format!("m_{}", n)));
+                document.generated.push((qualified("node", to_id), id.clone(), end, format!("m_{}", n)));
+                document.revisions.push((qualified("node", to_id), qualified("node", from_id), id.clone()));
+            }
+
+            let agent = run.and_then(|r| r.user.as_ref()).map(|user| qualified("user", user));
+            if let Some(user) = run.and_then(|r| r.user.as_ref()) {
+                users.insert(user);
+            }
+            document.associated.push((id, agent, qualified("template", &cnode.template)));
+        }
+
+        for user in users {
+            document.agents.push((qualified("user", user), vec![("prov:type", Attribute::Name("prov:Person".to_string()))]));
+        }
+        document
+    }
+}
+
+/// Qualified name. Characters that are not allowed in the local part are percent encoded.
+fn qualified(prefix: &str, local: &str) -> String {
+    let mut result = format!("{}:", prefix);
+    for byte in local.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' || byte == b'.' {
+            result.push(byte as char);
+        } else {
+            result.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    result
+}
+
+fn json_attributes(mut record: Map<String, Value>, attributes: &Attributes) -> Value {
+    for (key, value) in attributes {
+        let value = match value {
+            Attribute::Text(text) => json!(text),
+            Attribute::Number(number) => json!(number),
+            Attribute::Name(name) => json!({"$": name, "type": "prov:QUALIFIED_NAME"}),
+        };
+        record.insert(key.to_string(), value);
+    }
+    Value::Object(record)
+}
+
+fn n_attributes(attributes: &Attributes) -> String {
+    if attributes.is_empty() {
+        return String::new()
+    }
+    let values: Vec<String> = attributes.iter().map(|(key, value)| {
+        let value = match value {
+            Attribute::Text(text) => n_string(text),
+            Attribute::Number(number) => number.to_string(),
+            Attribute::Name(name) => format!("'{}'", name),
+        };
+        format!("{}={}", key, value)
+    }).collect();
+    format!(", [{}]", values.join(", "))
+}
+
+/// String literal of PROV-N
+fn n_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_prov() {
+        let db = example();
+        assert_eq!(db.prov_json().unwrap(), serde_json::from_str::<Value>(include_str!("golden/prov.json")).unwrap());
+        assert_eq!(db.prov_n().unwrap(), include_str!("golden/prov.provn"));
+    }
+}
@@ -0,0 +1,257 @@
+/*
+Snakemake export.
+Calculations of the same template become a single rule with a wildcard for the output, as long as
+snakemake can express them that way: a single output and no data modified in place. All other
+calculations get a rule of their own (<template>_<n>). Parameters are written into the commands, calculations
+with different values of the parameters get rules of their own too.
+A snakemake rule can not have the same file as input and output, so every revision of data that is
+modified in place is represented by an empty marker file in .graphr/revisions. The calculations that read the
+data before it is modified are inputs of the modifying rule as well, marked ancient so that they only order the rules.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use crate::export::{fill_slots, identifier, quote, Slots};
+use crate::{CNode, CNodeTemplate, Database, GraphrError, IdCTemplate, IdD, Result};
+
+const MARKERS: &str = ".graphr/revisions";
+
+
+/// Settings of the generated Snakefile
+#[derive(Clone, Debug)]
+pub struct SnakemakeOptions {
+    /// Folder of the data nodes. Can be changed when running with `--config root=<folder>`
+    pub root_folder: String,
+    /// Conda environment file used by every rule
+    pub conda: Option<String>,
+    /// Conda environment files of single calculation templates (take precedence over conda)
+    pub conda_envs: BTreeMap<IdCTemplate, String>,
+}
+
+impl Default for SnakemakeOptions {
+    fn default() -> Self {
+        SnakemakeOptions { root_folder: "data".to_string(), conda: None, conda_envs: BTreeMap::new() }
+    }
+}
+
+
+impl Database {
+
+    /// Generate a Snakefile that produces all data of the database
+    pub fn snakefile(&self, options: &SnakemakeOptions) -> Result<String> {
+        let mut by_template: BTreeMap<&IdCTemplate, Vec<&CNode>> = BTreeMap::new();
+        for cnode in self.cnodes.values() {
+            by_template.entry(&cnode.template).or_default().push(cnode);
+        }
+
+        let mut result = String::from("# Generated by graphr\n");
+        result.push_str(&format!("ROOT = config.get(\"root\", {})\n\n", quote(&options.root_folder)));
+
+        // Inputs of the wildcard rules, looked up by the location of the output
+        let mut inputs = String::new();
+        let mut rules = String::new();
+        let mut rule_names: BTreeSet<String> = BTreeSet::from(["all".to_string()]);
+
+        for (template_id, cnodes) in &by_template {
+            let template = self.get_template_cnode(template_id).ok_or_else(|| {
+                GraphrError::DanglingReference { node: cnodes[0].id.clone(), reference: template_id.to_string() }
+            })?;
+            let slots = Slots::new(template);
+
+            // Parameter values are written into the command, so a shared rule needs the same values
+            let wildcard = template.outcoming.len() == 1 && template.modified.is_empty()
+                && cnodes.iter().all(|c| c.incoming.iter().all(|d_id| !self.is_revision(d_id)))
+                && cnodes.iter().all(|c| c.params == cnodes[0].params);
+
+            if wildcard {
+                let name = unique_name(&mut rule_names, identifier(template_id));
+                if !template.incoming.is_empty() {
+                    inputs.push_str(&format!("    {}: {{\n", quote(template_id)));
+                    for cnode in cnodes {
+                        let bindings: Vec<String> = slots.incoming.iter().zip(&cnode.incoming)
+                            .map(|(slot, d_id)| format!("{}: {}", quote(slot), self.data_path(d_id)))
+                            .collect();
+                        inputs.push_str(&format!("        {}: {{{}}},\n", quote(self.location(&cnode.outcoming[0])), bindings.join(", ")));
+                    }
+                    inputs.push_str("    },\n");
+                }
+                rules.push_str(&self.wildcard_rule(&name, &template.with_values(cnodes[0])?, &slots, cnodes, options));
+            } else {
+                for cnode in cnodes {
+                    let name = unique_name(&mut rule_names, format!("{}_1", identifier(template_id)));
+                    rules.push_str(&self.calculation_rule(&name, &template.with_values(cnode)?, &slots, cnode, options));
+                }
+            }
+        }
+
+        if !inputs.is_empty() {
+            result.push_str(&format!("INPUTS = {{\n{}}}\n\n", inputs));
+        }
+
+        // Everything the calculations produce
+        let mut targets: Vec<String> = Vec::new();
+        for cnode in self.cnodes.values() {
+            let produced = cnode.outcoming.iter().map(|d_id| self.data_path(d_id))
+                .chain(cnode.modified.iter().map(|(_, to_id)| quote(&marker(to_id))));
+            for target in produced {
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+        result.push_str("rule all:\n    input:\n");
+        if targets.is_empty() {
+            result.push_str("        [],\n");
+        }
+        for target in targets {
+            result.push_str(&format!("        {},\n", target));
+        }
+        result.push('\n');
+        result.push_str(&rules);
+        Ok(result)
+    }
+
+    /// Rule for all calculations of a template, with the output location as wildcard
+    fn wildcard_rule(&self, name: &str, template: &CNodeTemplate, slots: &Slots, cnodes: &[&CNode], options: &SnakemakeOptions) -> String {
+        let output = &slots.outcoming[0];
+        let mut rule = format!("rule {}:\n", name);
+
+        if !slots.incoming.is_empty() {
+            rule.push_str("    input:\n");
+            for slot in &slots.incoming {
+                rule.push_str(&format!("        {}=lambda wildcards: INPUTS[{}][wildcards.{}][{}],\n",
+                                       slot, quote(&template.id), output, quote(slot)));
+            }
+        }
+
+        let path = format!("ROOT + {}", quote(&format!("/{{{}}}", output)));
+        let directory = cnodes.iter().any(|c| self.is_directory(&c.outcoming[0], &options.root_folder));
+        rule.push_str(&format!("    output:\n        {}={},\n", output, if directory { format!("directory({})", path) } else { path }));
+
+        let locations: Vec<String> = cnodes.iter().map(|c| regex::escape(self.location(&c.outcoming[0]))).collect();
+        rule.push_str(&format!("    wildcard_constraints:\n        {}={},\n", output, quote(&locations.join("|"))));
+
+        let command = fill_slots(&escape_braces(&template.command), template, |kind, n| match kind {
+            'i' => format!("{{input.{}}}", slots.incoming[n]),
+            _ => format!("{{output.{}}}", slots.outcoming[n]),
+        });
+        rule.push_str(&settings(template, options));
+        rule.push_str(&format!("    shell:\n        {}\n\n", quote(&command)));
+        rule
+    }
+
+    /// Rule for a single calculation with all paths written out
+    fn calculation_rule(&self, name: &str, template: &CNodeTemplate, slots: &Slots, cnode: &CNode, options: &SnakemakeOptions) -> String {
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+        // Actual locations of data that is represented by a marker
+        let mut params = Vec::new();
+
+        for (slot, d_id) in slots.incoming.iter().zip(&cnode.incoming) {
+            if self.is_revision(d_id) {
+                input.push(format!("{}={}", slot, quote(&marker(d_id))));
+                params.push(format!("{}={}", slot, self.data_path(d_id)));
+            } else {
+                input.push(format!("{}={}", slot, self.data_path(d_id)));
+            }
+        }
+        for (slot, d_id) in slots.outcoming.iter().zip(&cnode.outcoming) {
+            let path = self.data_path(d_id);
+            output.push(format!("{}={}", slot, if self.is_directory(d_id, &options.root_folder) { format!("directory({})", path) } else { path }));
+        }
+        for (slot, (from_id, to_id)) in slots.modified.iter().zip(&cnode.modified) {
+            let previous = if self.is_revision(from_id) { quote(&marker(from_id)) } else { self.data_path(from_id) };
+            input.push(format!("{}={}", slot, previous));
+            output.push(format!("{}=touch({})", slot, quote(&marker(to_id))));
+            params.push(format!("{}={}", slot, self.data_path(from_id)));
+        }
+        let mut readers: Vec<String> = Vec::new();
+        for reader in self.readers_before(cnode) {
+            let produced = reader.outcoming.iter().map(|d_id| self.data_path(d_id))
+                .chain(reader.modified.iter().map(|(_, to_id)| quote(&marker(to_id))));
+            for path in produced {
+                let path = format!("ancient({})", path);
+                if !readers.contains(&path) {
+                    readers.push(path);
+                }
+            }
+        }
+        if !readers.is_empty() {
+            input.push(format!("_readers=[{}]", readers.join(", ")));
+        }
+
+        let mut rule = format!("rule {}:\n", name);
+        for (section, items) in [("input", &input), ("output", &output), ("params", &params)] {
+            if !items.is_empty() {
+                rule.push_str(&format!("    {}:\n", section));
+                for item in items {
+                    rule.push_str(&format!("        {},\n", item));
+                }
+            }
+        }
+
+        let command = fill_slots(&escape_braces(&template.command), template, |kind, n| match kind {
+            'i' if self.is_revision(&cnode.incoming[n]) => format!("{{params.{}}}", slots.incoming[n]),
+            'i' => format!("{{input.{}}}", slots.incoming[n]),
+            'o' => format!("{{output.{}}}", slots.outcoming[n]),
+            _ => format!("{{params.{}}}", slots.modified[n]),
+        });
+        rule.push_str(&settings(template, options));
+        rule.push_str(&format!("    shell:\n        {}\n\n", quote(&command)));
+        rule
+    }
+
+    /// Python expression for the path of the data in the root folder
+    fn data_path(&self, d_id: &IdD) -> String {
+        format!("ROOT + {}", quote(&format!("/{}", self.location(d_id))))
+    }
+}
+
+/// Threads, resources and conda environment of a rule
+fn settings(template: &CNodeTemplate, options: &SnakemakeOptions) -> String {
+    let mut result = String::new();
+    if template.resources.cores > 1 {
+        result.push_str(&format!("    threads: {}\n", template.resources.cores));
+    }
+    if let Some(memory) = template.resources.memory {
+        result.push_str(&format!("    resources:\n        mem_mb={}\n", memory));
+    }
+    if let Some(conda) = options.conda_envs.get(&template.id).or(options.conda.as_ref()) {
+        result.push_str(&format!("    conda:\n        {}\n", quote(conda)));
+    }
+    result
+}
+
+/// Shell commands are format strings in snakemake
+fn escape_braces(command: &str) -> String {
+    command.replace('{', "{{").replace('}', "}}")
+}
+
+fn marker(d_id: &IdD) -> String {
+    format!("{}/{}", MARKERS, d_id)
+}
+
+/// Rule names have to be unique. Numbered names (<template>_1) count up, others get a number appended.
+fn unique_name(taken: &mut BTreeSet<String>, name: String) -> String {
+    let (base, mut n) = match name.rsplit_once('_').and_then(|(base, n)| Some((base.to_string(), n.parse::<usize>().ok()?))) {
+        Some((base, n)) => (base, n),
+        None => (name.clone(), 1),
+    };
+    let mut candidate = name;
+    while taken.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}_{}", base, n);
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::example;
+
+    #[test]
+    fn golden_snakefile() {
+        assert_eq!(example().snakefile(&SnakemakeOptions::default()).unwrap(), include_str!("golden/Snakefile"));
+    }
+}
@@ -0,0 +1,285 @@
+/*
+Append-only journal of the operations that changed a database.
+Mutating methods of Database record an entry in memory; the storage appends the entries
+to <database path>.journal (one json object per line) when the database is written.
+Replaying the journal from an empty database rebuilds the database. Undo is an entry of its own,
+which cancels the last operation that was not undone yet, so the journal is never rewritten.
+*/
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::overwrite::Revisions;
+use crate::run::{current_user, now_nanos};
+use crate::{open_storage, Corrections, Database, GraphrError, IdC, IdD, MetadataValue, Result, RunRecord};
+
+
+/// A change of the database, with everything needed to apply it again
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "operation", content = "arguments", rename_all = "snake_case")]
+pub enum Operation {
+    /// State of a database that existed before it had a journal
+    Snapshot { database: Database },
+    TemplateRegisterDnode { name: String },
+    TemplateRegisterCnode { name: String, command: String },
+    TemplateSetResources { name: String, cores: u32, memory: Option<u64> },
+    RegisterDnode { template: String, name: String },
+    MergeInto { database: Database },
+    CheckAgainstAndRegister { database: Database },
+    RegisterPipeline { database: Database },
+    Delete { names: Vec<String> },
+    /// Outcome of runs and recorded hashes. None clears the value.
+    Record { runs: BTreeMap<IdC, Option<RunRecord>>, hashes: BTreeMap<IdD, Option<String>> },
+    Insert { database: Database },
+    AddTags { names: Vec<String>, tags: Vec<String> },
+    SetTags { names: Vec<String>, tags: Vec<String> },
+    RemoveTags { names: Vec<String>, tags: Vec<String> },
+    SetMetadata { names: Vec<String>, key: String, value: MetadataValue },
+    RemoveMetadata { names: Vec<String>, key: String },
+    /// Revisions created for modified data are kept, so that the same ids are used again
+    OverwriteCalculation { name: String, command: String, corrections: Corrections, revisions: Revisions },
+    Undo,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    /// Nanoseconds since the unix epoch
+    pub timestamp: u64,
+    pub user: String,
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+impl JournalEntry {
+    pub fn new(operation: Operation) -> Self {
+        JournalEntry { timestamp: now_nanos(), user: current_user(), operation }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |db: &Database| format!("{} templates, {} calculations, {} data nodes",
+            db.template.cnodes.len() + db.template.dnodes.len(), db.cnodes.len(), db.dnodes.len());
+        match self {
+            Operation::Snapshot { database } => write!(f, "snapshot ({})", size(database)),
+            Operation::TemplateRegisterDnode { name } => write!(f, "template_register_dnode {}", name),
+            Operation::TemplateRegisterCnode { name, command } => write!(f, "template_register_cnode {} \"{}\"", name, command),
+            Operation::TemplateSetResources { name, cores, memory } => write!(f, "template_set_resources {} cores={} memory={:?}", name, cores, memory),
+            Operation::RegisterDnode { template, name } => write!(f, "register_dnode {} {}", template, name),
+            Operation::MergeInto { database } => write!(f, "merge_into ({})", size(database)),
+            Operation::CheckAgainstAndRegister { database } => write!(f, "check_against_and_register ({})", size(database)),
+            Operation::RegisterPipeline { database } => write!(f, "register_pipeline ({})", size(database)),
+            Operation::Delete { names } => write!(f, "delete {}", names.join(", ")),
+            Operation::Record { runs, hashes } => write!(f, "record {} runs, {} hashes", runs.len(), hashes.len()),
+            Operation::Insert { database } => write!(f, "insert ({})", size(database)),
+            Operation::AddTags { names, tags } => write!(f, "add_tags {} on {} nodes", tags.join(", "), names.len()),
+            Operation::SetTags { names, tags } => write!(f, "set_tags {} on {} nodes", tags.join(", "), names.len()),
+            Operation::RemoveTags { names, tags } => write!(f, "remove_tags {} on {} nodes", tags.join(", "), names.len()),
+            Operation::SetMetadata { names, key, value } => write!(f, "set_metadata {}={} on {} nodes", key, value, names.len()),
+            Operation::RemoveMetadata { names, key } => write!(f, "remove_metadata {} on {} nodes", key, names.len()),
+            Operation::OverwriteCalculation { name, command, corrections, .. } => {
+                write!(f, "overwrite_calculation {} \"{}\" on {} calculations", name, command, corrections.len())
+            }
+            Operation::Undo => write!(f, "undo"),
+        }
+    }
+}
+
+
+/// Journal that belongs to the database stored at the path
+pub fn journal_path(path: &str) -> String {
+    format!("{}.journal", path)
+}
+
+/// Read all entries of a journal. A missing journal is empty.
+pub fn read_journal(path: &str) -> Result<Vec<JournalEntry>> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new())
+    }
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Append entries to the journal.
+/// A database that was stored before the journal existed is put into the journal as a snapshot first.
+pub(crate) fn append_journal(path: &str, stored: &Database, entries: &[JournalEntry]) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(())
+    }
+
+    let mut lines = Vec::new();
+    let is_empty = stored.cnodes.is_empty() && stored.dnodes.is_empty()
+        && stored.template.cnodes.is_empty() && stored.template.dnodes.is_empty();
+    if !Path::new(path).exists() && !is_empty {
+        let mut snapshot = stored.clone();
+        snapshot.journal.clear();
+        lines.push(serde_json::to_string(&JournalEntry::new(Operation::Snapshot { database: snapshot }))?);
+    }
+    for entry in entries {
+        lines.push(serde_json::to_string(entry)?);
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", lines.join("\n")).as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Operations that are still in effect (undone operations removed)
+pub fn effective_operations(entries: &[JournalEntry]) -> Vec<&JournalEntry> {
+    let mut effective = Vec::new();
+    for entry in entries {
+        match entry.operation {
+            Operation::Undo => { effective.pop(); }
+            _ => effective.push(entry),
+        }
+    }
+    effective
+}
+
+
+impl Operation {
+    /// Apply the operation to the database again
+    fn apply(&self, db: &mut Database) -> Result<()> {
+        match self {
+            Operation::Snapshot { database } => {
+                *db = database.clone();
+            }
+            Operation::TemplateRegisterDnode { name } => {
+                db.template_register_dnode(name.clone());
+            }
+            Operation::TemplateRegisterCnode { name, command } => {
+                db.template_register_cnode(name.clone(), command.clone())?;
+            }
+            Operation::TemplateSetResources { name, cores, memory } => {
+                db.template_set_resources(name.clone(), *cores, *memory)?;
+            }
+            Operation::RegisterDnode { template, name } => {
+                db.register_dnode(template.clone(), Some(name.clone()))?;
+            }
+            Operation::MergeInto { database } => {
+                database.clone().merge_into(db)?;
+            }
+            Operation::CheckAgainstAndRegister { database } => {
+                database.clone().check_against_and_register(db)?;
+            }
+            Operation::RegisterPipeline { database } => {
+                db.register_pipeline(database.clone())?;
+            }
+            Operation::Delete { names } => {
+                db.delete(names.clone());
+            }
+            Operation::Record { runs, hashes } => {
+                db.apply_record(runs, hashes);
+            }
+            Operation::Insert { database } => {
+                db.insert_nodes(database);
+            }
+            Operation::AddTags { names, tags } => {
+                db.add_tags(tags.clone(), Some(names.clone()))?;
+            }
+            Operation::SetTags { names, tags } => {
+                db.set_tags(tags.clone(), Some(names.clone()))?;
+            }
+            Operation::RemoveTags { names, tags } => {
+                db.remove_tags(tags.clone(), Some(names.clone()))?;
+            }
+            Operation::SetMetadata { names, key, value } => {
+                db.set_metadata(key.clone(), value.clone(), Some(names.clone()))?;
+            }
+            Operation::RemoveMetadata { names, key } => {
+                db.remove_metadata(key.clone(), Some(names.clone()))?;
+            }
+            Operation::OverwriteCalculation { name, command, corrections, revisions } => {
+                db.apply_overwrite(name, command, corrections, revisions)?;
+            }
+            Operation::Undo => {}
+        }
+        Ok(())
+    }
+}
+
+
+impl Database {
+
+    /// Keep an operation in the journal until the database is written
+    pub(crate) fn record(&mut self, operation: Operation) {
+        self.journal.push(JournalEntry::new(operation));
+    }
+
+    pub(crate) fn apply_record(&mut self, runs: &BTreeMap<IdC, Option<RunRecord>>, hashes: &BTreeMap<IdD, Option<String>>) {
+        for (id, run) in runs {
+            if let Some(cnode) = self.cnodes.get_mut(id) {
+                cnode.run = run.clone();
+            }
+        }
+        for (id, hash) in hashes {
+            if let Some(dnode) = self.dnodes.get_mut(id) {
+                dnode.hash = hash.clone();
+            }
+        }
+        self.record(Operation::Record { runs: runs.clone(), hashes: hashes.clone() });
+    }
+
+    /// Apply the operations done on another copy of the database. The entries go into the journal as they are.
+    /// Undo can not be applied, it cancels whatever was done last and that changed in the meantime.
+    pub(crate) fn apply_journal(&mut self, entries: &[JournalEntry]) -> Result<()> {
+        let length = self.journal.len();
+        for entry in entries {
+            if matches!(entry.operation, Operation::Undo | Operation::Snapshot { .. }) {
+                return Err(GraphrError::Journal(format!("Can not {}, the database was changed in the meantime. Try again.", entry.operation)))
+            }
+            entry.operation.apply(self)?;
+        }
+        self.journal.truncate(length);
+        self.journal.extend(entries.iter().cloned());
+        Ok(())
+    }
+
+    /// Rebuild a database by applying the operations of the journal to an empty database
+    pub fn replay_journal(entries: &[JournalEntry]) -> Result<Database> {
+        let mut db = Database::new();
+        for entry in effective_operations(entries) {
+            entry.operation.apply(&mut db)?;
+        }
+        db.journal.clear();
+        Ok(db)
+    }
+}
+
+/// Undo the last operation on the database stored at the path.
+/// The database is rebuilt from the journal without that operation and stored again.
+pub fn undo_last(path: &str) -> Result<Database> {
+    let mut storage = open_storage(path)?;
+    let current = storage.load()?;
+
+    let mut entries = read_journal(&journal_path(path))?;
+    // The snapshot is where the journal starts, there is nothing before it to go back to
+    if effective_operations(&entries).last().is_none_or(|entry| matches!(entry.operation, Operation::Snapshot { .. })) {
+        return Err(GraphrError::Journal("Nothing to undo".to_string()))
+    }
+    let undo = JournalEntry::new(Operation::Undo);
+    entries.push(undo.clone());
+
+    let mut db = Database::replay_journal(&entries)?;
+    db.loaded_version = current.loaded_version;
+    db.journal = vec![undo];
+    storage.save(&mut db)?;
+    Ok(db)
+}
+
+/// Rebuild the database stored at the path from its journal and store it again.
+pub fn replay(path: &str) -> Result<Database> {
+    let mut storage = open_storage(path)?;
+    let current = storage.load()?;
+
+    let mut db = Database::replay_journal(&read_journal(&journal_path(path))?)?;
+    db.loaded_version = current.loaded_version;
+    storage.save(&mut db)?;
+    Ok(db)
+}
@@ -5,23 +5,44 @@ DNode - Only contains abstract calculations. real data are only described by loc
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use pyo3::prelude::*;
-use pyo3::wrap_pyfunction;
 use pyo3::types::PyType;
 use serde::{Serialize, Deserialize};
 use regex::Regex;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::fmt;
 use std::collections::{HashSet, VecDeque};
 use std::cmp::Ordering;
-use std::path::{Path, PathBuf};
-use serde_json::{Value, Map};
+use std::path::Path;
 use std::io::Write;
-use petgraph::graph::{NodeIndex, DiGraph, UnGraph};
+use petgraph::graph::{NodeIndex, DiGraph};
 use petgraph::Direction;
-use petgraph::dot::{Dot, Config};
-use petgraph::algo::has_path_connecting;
-use petgraph::visit::Topo;
-use petgraph::visit::Walker;
+use petgraph::algo::{has_path_connecting, toposort};
+
+mod error;
+mod export;
+mod journal;
+mod overwrite;
+mod params;
+mod query;
+mod run;
+mod status;
+mod storage;
+mod subtemplate;
+mod sweep;
+mod tags;
+mod versions;
+pub use error::{GraphrError, Result};
+pub use export::{DotOptions, SnakemakeOptions};
+pub use journal::{journal_path, read_journal, replay, undo_last, JournalEntry, Operation};
+pub use overwrite::Corrections;
+pub use params::{Param, ParamKind, ParamValues};
+pub use query::Query;
+pub use run::{iso_timestamp, FailurePolicy, Resources, RunOptions, RunRecord, RunSummary};
+pub use status::NodeStatus;
+pub use storage::{open_storage, JsonStorage, SqliteStorage, Storage};
+pub use sweep::Combination;
+pub use tags::{Metadata, MetadataValue};
+use tags::Annotated;
+use params::shell_word;
 
 
 
@@ -30,9 +51,13 @@ type IdCTemplate = String;
 type IdDTemplate = String;
 type IdC = String;
 type IdD = String;
-type IdNode = String; // Id of a general node (could be IdC or IdD)
 type IdNodeTemplate = String;
-type IdTemplate = String;
+
+/// Command with the markers replaced, inputs, outputs, modified data, parameters
+type ParsedCommand = (String, Vec<IdDTemplate>, Vec<IdDTemplate>, Vec<IdDTemplate>, Vec<Param>);
+
+/// Weight of the graph edges that belong to a modification in place
+const MODIFY_EDGE: &str = "modify";
 
 
 
@@ -46,17 +71,41 @@ pub struct DNodeTemplate {
 
 
 #[pyclass]
-#[derive(Serialize, Deserialize, Default,Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Default,Clone, Debug)]
 pub struct CNodeTemplate {
     pub id: IdCTemplate,
     pub command: String,
     pub incoming: Vec<IdDTemplate>,
-    pub outcoming: Vec<IdDTemplate>
+    pub outcoming: Vec<IdDTemplate>,
+    /// Data nodes that are modified in place
+    #[serde(default)]
+    pub modified: Vec<IdDTemplate>,
+    /// Hints for the scheduler
+    #[serde(default)]
+    pub resources: Resources,
+    /// Parameters of the command, given a value for every calculation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+}
+
+/// Resources are only hints for the scheduler and do not change what is calculated,
+/// therefore they are not part of the comparison.
+impl PartialEq for CNodeTemplate {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+            && self.command == other.command
+            && self.incoming == other.incoming
+            && self.outcoming == other.outcoming
+            && self.modified == other.modified
+            && self.params == other.params
+    }
 }
 
+impl Eq for CNodeTemplate {}
+
 #[pyclass]
 #[derive(Clone)]
-enum NodeTemplate {
+pub enum NodeTemplate {
     Calculation(CNodeTemplate),
     Data(DNodeTemplate),
 }
@@ -65,15 +114,18 @@ enum NodeTemplate {
 
 /// Describes abstract calculations.
 #[pyclass]
-#[derive(Clone,serde::Serialize,Deserialize,Debug)]
+#[derive(Clone,serde::Serialize,Deserialize,Debug,Default)]
 pub struct DatabaseTemplate {
     cnodes: BTreeMap<IdCTemplate, CNodeTemplate>, // Store all calculation nodes
     dnodes: BTreeMap<IdDTemplate, DNodeTemplate>, // Store all data nodes
+    /// Earlier versions of calculation templates, stored as name@version (see versions.rs)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    versions: BTreeMap<IdCTemplate, CNodeTemplate>,
 }
 
 /// Describes implementations and actual calculations
 #[pyclass]
-#[derive(Clone,serde::Serialize,Deserialize)]
+#[derive(Clone,serde::Serialize,Deserialize,Default)]
 pub struct Database {
 
     template: DatabaseTemplate,
@@ -81,6 +133,14 @@ pub struct Database {
     cnodes: BTreeMap<IdC, CNode>, // Store all calculation nodes
     dnodes: BTreeMap<IdD, DNode>, // Store all data nodes
 
+    /// Version of the stored database this one was read from.
+    /// Used to notice that somebody else wrote the file in the meantime.
+    #[serde(skip)]
+    loaded_version: Option<String>,
+
+    /// Operations done since the database was read. Appended to the journal when written.
+    #[serde(skip)]
+    journal: Vec<JournalEntry>,
 }
 
 
@@ -92,9 +152,27 @@ pub struct DNode {
     #[pyo3(get)]
     pub id: IdD,
     #[pyo3(get)]
-    pub template: IdDTemplate
-
-
+    pub template: IdDTemplate,
+    /// A data node modified in place gets a new revision for every modification.
+    /// All revisions share the location of the first one (revision_of).
+    #[pyo3(get)]
+    #[serde(default)]
+    pub revision_of: Option<IdD>,
+    #[pyo3(get)]
+    #[serde(default)]
+    pub revision: u32,
+    /// Content hash of the data, recorded when the node is produced or registered
+    #[pyo3(get)]
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Free-form labels, for example the experiment the node belongs to
+    #[pyo3(get)]
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Typed key/value information about the node
+    #[pyo3(get)]
+    #[serde(default)]
+    pub metadata: Metadata,
 }
 
 /// Describes an abstract calculation node
@@ -108,16 +186,59 @@ pub struct CNode{
     #[pyo3(get)]
     pub incoming: Vec<IdD>,
     #[pyo3(get)]
-    pub outcoming: Vec<IdD>
+    pub outcoming: Vec<IdD>,
+    /// Data nodes modified in place: (revision that is read, revision that is written)
+    #[pyo3(get)]
+    #[serde(default)]
+    pub modified: Vec<(IdD, IdD)>,
+    /// Values of the parameters of the template
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: ParamValues,
+    /// Outcome of the last execution
+    #[pyo3(get)]
+    #[serde(default)]
+    pub run: Option<RunRecord>,
+    /// Free-form labels, for example the experiment the node belongs to
+    #[pyo3(get)]
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Typed key/value information about the node
+    #[pyo3(get)]
+    #[serde(default)]
+    pub metadata: Metadata,
 }
 
 #[pyclass]
 #[derive(Clone)]
-enum Node {
+pub enum Node {
     Calculation(CNode),
     Data(DNode),
 }
 
+impl DNode {
+    /// Id under which the data is stored (shared by all revisions)
+    pub fn location(&self) -> &IdD {
+        self.revision_of.as_ref().unwrap_or(&self.id)
+    }
+}
+
+impl CNode {
+    /// All data nodes the calculation reads (including revisions it modifies)
+    pub fn inputs(&self) -> impl Iterator<Item = &IdD> {
+        self.incoming.iter().chain(self.modified.iter().map(|(from, _)| from))
+    }
+
+    /// All data nodes the calculation produces (including new revisions)
+    pub fn outputs(&self) -> impl Iterator<Item = &IdD> {
+        self.outcoming.iter().chain(self.modified.iter().map(|(_, to)| to))
+    }
+
+    pub fn produces(&self, d_id: &IdD) -> bool {
+        self.outputs().any(|o| o == d_id)
+    }
+}
+
 
 #[pymethods]
 impl Node {
@@ -125,12 +246,11 @@ impl Node {
     /// Generate an id for a calculation node.
     #[staticmethod]
     fn generate_id() -> IdC{
-        let now = SystemTime::now()
+        SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Failed to get current system time.")
         .as_nanos()
-        .to_string();
-        now
+        .to_string()
     }
 
 
@@ -147,7 +267,8 @@ impl Node {
     fn outcoming(&self) -> Vec<String> {
         match self {
             Node::Calculation(a) => a.outcoming.clone(),
-            Node::Data(a) => panic!("Data nodes do not have outcoming or incoming data assosiated with it. Provide Calculation node.")
+            // Data nodes do not have outcoming or incoming data assosiated with it.
+            Node::Data(_) => Vec::new()
         }
     }
 
@@ -179,10 +300,8 @@ impl DNodeTemplate {
 
     /// Format the string so that it could be interpreted by calculation creation 
     /// algorithm
-    fn __format__(&self, spec: &str) -> PyResult<String> {
-        let formatted = match spec {
-            _ => format!("{}", self.id),
-        };
+    fn __format__(&self, _spec: &str) -> PyResult<String> {
+        let formatted = self.id.to_string();
         Ok(formatted)
     }
 }
@@ -191,7 +310,25 @@ impl DNodeTemplate {
 #[pymethods]
 impl CNodeTemplate {
     fn __str__(&self) -> PyResult<String> {
-        Ok(format!("DNodeTemplate(id={};\ninput={:?},\noutput={:?})", self.id,self.incoming,self.outcoming ))
+        Ok(format!("DNodeTemplate(id={};\ninput={:?},\noutput={:?},\nmodify={:?})", self.id,self.incoming,self.outcoming,self.modified ))
+    }
+
+    /// Name shared by all versions of the template
+    #[getter(name)]
+    fn get_name(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// Content hash of the command, identifies the version of the template
+    #[getter(version)]
+    fn get_version(&self) -> String {
+        self.version()
+    }
+
+    /// Parameters of the command
+    #[getter(params)]
+    fn get_params(&self) -> Vec<Param> {
+        self.params.clone()
     }
 
 }
@@ -201,15 +338,20 @@ impl CNodeTemplate {
 impl CNodeTemplate {
 
     /// Parses a command to the desirable format
-    /// command has inputs marked with input(<filename>) and output with output(<filename>)
-    fn parse_command(command: String) -> (String, Vec<IdDTemplate>, Vec<IdDTemplate>) {
+    /// command has inputs marked with input(<filename>), output with output(<filename>),
+    /// data modified in place with modify(<filename>) and parameters with param(<name>) (see params.rs)
+    fn parse_command(command: String) -> Result<ParsedCommand> {
         
-        let re = Regex::new(r"(input|output)\(([^)]+)\)").expect("Failed to compile input regex.");
+        let re = Regex::new(r"(input|output|modify|param)\(([^)]+)\)").expect("Failed to compile input regex.");
 
         let mut inputs = Vec::new();
         let mut outputs = Vec::new();
+        let mut modified = Vec::new();
         let mut input_counter = 0;
         let mut output_counter = 0;
+        let mut modify_counter = 0;
+        let mut params: Vec<Param> = Vec::new();
+        let mut error = None;
         // Replace input(<filename>) with $i, where i is an integer enumerating all input
 
         let output = re.replace_all(&command, |caps: &regex::Captures| {
@@ -230,12 +372,78 @@ impl CNodeTemplate {
                     replacement
 
                 },
+                "modify" => {
+                    modified.push(value.to_string());
+                    let replacement = format!("$m_{}", modify_counter);
+                    modify_counter +=1;
+                    replacement
+                },
+                "param" => {
+                    // A parameter can be used several times, it is declared once
+                    let param = match Param::parse(value) {
+                        Ok(param) => param,
+                        Err(e) => {
+                            error.get_or_insert(e);
+                            return String::new()
+                        }
+                    };
+                    let index = match params.iter().position(|p| p.name == param.name) {
+                        Some(index) => {
+                            if value.trim() != param.name && params[index] != param {
+                                error.get_or_insert(GraphrError::ParseError(format!("Parameter '{}' is declared differently", param.name)));
+                            }
+                            index
+                        }
+                        None => {
+                            params.push(param);
+                            params.len() - 1
+                        }
+                    };
+                    format!("$p_{}", index)
+                },
                 _ => {panic!("Could not parse the command correctly")}
             }
         });
 
-        (output.to_string(), inputs, outputs)
+        if let Some(e) = error {
+            return Err(e)
+        }
+        // Any marker left over was not closed properly
+        let unparsed = Regex::new(r"\b(input|output|modify|param)\(").expect("Failed to compile marker regex.");
+        if unparsed.is_match(&output) {
+            return Err(GraphrError::ParseError(format!("Unterminated input/output/modify/param marker in command '{}'", command)))
+        }
 
+        Ok((output.to_string(), inputs, outputs, modified, params))
+
+    }
+
+    /// Inverse of parse_command. Puts input(<name>), output(<name>), modify(<name>) and param(<name>) markers back into the command,
+    /// so that the template could be registered again (e.g. from the command line).
+    pub fn marked_command(&self) -> String {
+        let mut command = self.command.clone();
+
+        for (i, i_id) in self.incoming.iter().enumerate().rev() {
+            command = command.replace(&format!("$i_{}", i), &format!("input({})", i_id));
+        }
+        for (o, o_id) in self.outcoming.iter().enumerate().rev() {
+            command = command.replace(&format!("$o_{}", o), &format!("output({})", o_id));
+        }
+        for (m, m_id) in self.modified.iter().enumerate().rev() {
+            command = command.replace(&format!("$m_{}", m), &format!("modify({})", m_id));
+        }
+        // The first use declares the parameter, the others only name it
+        let mut declared = Vec::new();
+        for (p, param) in self.params.iter().enumerate().rev() {
+            let slot = format!("$p_{}", p);
+            let declaration = format!("\u{1}{}\u{1}", p);
+            command = command.replacen(&slot, &declaration, 1).replace(&slot, &format!("param({})", param.name));
+            declared.push((declaration, param.spec()));
+        }
+        for (declaration, spec) in declared {
+            command = command.replace(&declaration, &format!("param({})", spec));
+        }
+        command
     }
 }
 
@@ -249,6 +457,7 @@ impl DatabaseTemplate {
         DatabaseTemplate {
             cnodes: BTreeMap::new(),
             dnodes: BTreeMap::new(),
+            versions: BTreeMap::new(),
         }
 
     }
@@ -272,44 +481,119 @@ impl DatabaseTemplate {
     }
 
 
-    pub fn create_calculation_node(&self, name:String, command: String) -> CNodeTemplate {
-        let values = CNodeTemplate::parse_command(command);
+    pub fn create_calculation_node(&self, name:String, command: String) -> Result<CNodeTemplate> {
+        versions::check_template_name(&name)?;
+        let values = CNodeTemplate::parse_command(command)?;
 
-        let cnode = CNodeTemplate {
+        Ok(CNodeTemplate {
             id: name.clone(),
             incoming: values.1,
             outcoming: values.2,
+            modified: values.3,
             command: values.0,
-        };
-        cnode
+            resources: Resources::default(),
+            params: values.4,
+        })
     }
 
     /// Register calculation node
-    /// If a key already exists, then comprate the value. If the values are the same do nothing,
-    /// If they are different then crash, otherwise, overwrite flag is passed
-    pub fn register_cnode(&mut self, name: String, command: String) -> CNodeTemplate {
-        let cnode = self.create_calculation_node(name.clone(), command);
+    /// If a different command is already registered under the name, it is kept as an earlier version (name@version)
+    /// and the new command becomes the current version.
+    pub fn register_cnode(&mut self, name: String, command: String) -> Result<CNodeTemplate> {
+        let cnode = self.create_calculation_node(name, command)?;
 
-        self.cnodes.insert(name, cnode.clone());
-        cnode
+        self.install(cnode.clone());
+        Ok(cnode)
 
     }
 
-    /// Return the database in DOT format
-    pub fn as_dot(&self) -> String {
-        let (graph,mapping) = self.generate_digraph();
-        format!("{}", Dot::with_config(&graph, &[Config::EdgeNoLabel]))
+    /// Return the template in DOT format
+    /// highlight - nodes to draw with a thick border
+    #[pyo3(signature = (highlight=None))]
+    pub fn as_dot(&self, highlight: Option<Vec<String>>) -> Result<String> {
+        let options = DotOptions { highlight: highlight.unwrap_or_default().into_iter().collect(), ..DotOptions::default() };
+        self.dot(&options)
     }
 
+    /// Return the template as a packed CWL document (a CommandLineTool per calculation and the workflow #main)
+    pub fn to_cwl(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.cwl_document()?)?)
+    }
 
-    /// get a node from a template
-    pub fn get(&self, name: String) -> NodeTemplate {
-        unimplemented!();
+    /// Return the template as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> Result<String> {
+        self.mermaid()
+    }
+
+    /// Return the template as a GraphML document (node kind, template and command as attributes)
+    pub fn to_graphml(&self) -> Result<String> {
+        self.graphml()
+    }
+
+
+    /// get a node from a template (earlier versions of calculations can be got as name@version)
+    pub fn get(&self, name: String) -> Option<NodeTemplate> {
+        if let Some(cnode) = self.get_version(&name) {
+            return Some(NodeTemplate::Calculation(cnode.clone()))
+        }
+        self.dnodes.get(&name).map(|dnode| NodeTemplate::Data(dnode.clone()))
+    }
+
+    /// All versions of the named calculation template, the current one first
+    pub fn get_versions(&self, name: String) -> Vec<CNodeTemplate> {
+        self.template_versions(&name).into_iter().cloned().collect()
+    }
+
+    /// Part of the template needed to produce the given data templates (see subtemplate.rs)
+    pub fn subtemplate(&self, targets: Vec<String>) -> Result<DatabaseTemplate> {
+        self.history_of(&targets)
     }
 
     /// Create an implementation of a given template.
-    pub fn create_calculation(&self, leafs: BTreeMap<String, String>) -> Database {
-        
+    /// leafs - names of the root data nodes (and of any other data node that should not get a generated name)
+    /// existing - ids of existing data nodes to use for data templates instead of calculating them again
+    ///            (only the calculations downstream of them are created, see subtemplate.rs)
+    /// params - values of the parameters of the calculation templates by name (see params.rs)
+    #[pyo3(signature = (leafs, existing=None, params=None))]
+    pub fn create_calculation(&self, leafs: BTreeMap<String, String>, existing: Option<BTreeMap<String, String>>,
+                              params: Option<ParamValues>) -> Result<Database> {
+        let existing = existing.unwrap_or_default().into_iter()
+            .map(|(template, id)| (template.clone(), DNode { id, template, ..DNode::default() }))
+            .collect();
+        self.instantiate(leafs, existing, &params.unwrap_or_default())
+    }
+
+    /// Create the calculations of all combinations of leaf names and parameter values as one database (see sweep.rs).
+    /// leafs - names for every root data template
+    /// params - values for every swept parameter
+    /// zip - combine the lists side by side instead of the cartesian product
+    /// Returns the database and a row for every combination with the data it produced.
+    #[pyo3(signature = (leafs, params=None, zip=false))]
+    pub fn create_sweep(&self, leafs: BTreeMap<String, Vec<String>>, params: Option<BTreeMap<String, Vec<MetadataValue>>>,
+                        zip: bool) -> Result<(Database, Vec<Combination>)> {
+        self.sweep(&leafs, &params.unwrap_or_default(), zip)
+    }
+
+}
+
+
+
+impl DatabaseTemplate {
+
+    /// Implement the template. Data templates with an existing data node are not calculated again.
+    pub(crate) fn instantiate(&self, leafs: BTreeMap<String, String>, existing: BTreeMap<IdDTemplate, DNode>, params: &ParamValues) -> Result<Database> {
+        let mut unknown: Vec<&String> = params.keys()
+            .filter(|name| !self.cnodes.values().any(|c| c.params.iter().any(|p| p.name == **name)))
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(GraphrError::InvalidParam(format!("no template has the parameters {:?}", unknown)))
+        }
+
+        // Data that exists already is not calculated again, only the part of the template downstream of it
+        let cut;
+        let template = if existing.is_empty() { self } else { cut = self.cut(&existing)?; &cut };
+
         // Need to go through all data and calculation nodes and generate actual calculations.
 
         // Go through all Data nodes
@@ -321,22 +605,23 @@ impl DatabaseTemplate {
         // Check if all root nodes have specified names
         // This is needed due to the imposed workflow.
 
-        let root_nodes = self.find_root_nodes();
-        let mut correct_input: bool = true;
-        let mut error_message = String::from("");
-        for rn in root_nodes {
-            correct_input = leafs.contains_key(&rn);
-            if !correct_input {
-                error_message += &format!("Need to provide name for {}\n", rn);
-            }
-        }
-        if !correct_input {
-            panic!("{}",&error_message)
+        let root_nodes = template.find_root_nodes()?;
+        let mut missing: Vec<String> = root_nodes.into_iter().filter(|rn| !leafs.contains_key(rn) && !existing.contains_key(rn)).collect();
+        if !missing.is_empty() {
+            missing.sort();
+            return Err(GraphrError::MissingLeaf(missing))
         }
 
 
         // data nodes
-        for (key, value) in &self.dnodes {
+        for (key, value) in &template.dnodes {
+            // Existing data is used as it is (with its revision)
+            if let Some(dnode) = existing.get(key) {
+                new_dnodes.insert(dnode.id.clone(), dnode.clone());
+                dnode_mapping.insert(key, dnode.id.clone());
+                continue
+            }
+
             // Create the node
             
             let new_id = match leafs.get(&value.id) {
@@ -347,6 +632,11 @@ impl DatabaseTemplate {
             let dnode = DNode {
                 id: new_id.clone(),
                 template: value.id.clone(),
+                revision_of: None,
+                revision: 0,
+                hash: None,
+                tags: BTreeSet::new(),
+                metadata: Metadata::new(),
             };
             // insert into the final
             new_dnodes.insert(new_id.clone(), dnode);
@@ -355,54 +645,95 @@ impl DatabaseTemplate {
 
         }
 
-        // Create all the new data nodes
-        for (key, value) in &self.cnodes {
+        // Every modification in place creates a new revision of the data node. Modifications of the same data
+        // are applied in the order of the calculations (see calculation_order), each to the revision of the one before.
+        // A calculation reads the revision of the last modification it is downstream of, the original data if there is none.
+        let order = template.calculation_order()?;
+
+        let mut modifications: BTreeMap<&IdCTemplate, Vec<(IdD, IdD)>> = BTreeMap::new();
+        // Revisions of every data template with the calculation that made them
+        let mut revisions: BTreeMap<&String, Vec<(Option<&IdCTemplate>, IdD)>> = dnode_mapping.iter()
+            .map(|(d_template, d_id)| (*d_template, vec![(None, d_id.clone())]))
+            .collect();
+        for (value, _) in &order {
+            for m_id in &value.modified {
+                let from = revisions.get(m_id).and_then(|r| r.last()).map(|(_, d_id)| d_id.clone()).ok_or_else(|| {
+                    GraphrError::DanglingReference { node: value.id.clone(), reference: m_id.clone() }
+                })?;
+                let base = new_dnodes[&from].location().clone();
+                let dnode = DNode {
+                    id: Node::generate_id(),
+                    template: m_id.clone(),
+                    revision: new_dnodes[&from].revision + 1,
+                    revision_of: Some(base),
+                    hash: None,
+                    tags: BTreeSet::new(),
+                    metadata: Metadata::new(),
+                };
+                revisions.entry(m_id).or_default().push((Some(&value.id), dnode.id.clone()));
+                modifications.entry(&value.id).or_default().push((from, dnode.id.clone()));
+                new_dnodes.insert(dnode.id.clone(), dnode);
+            }
+        }
+
+        // Create all the new calculation nodes
+        for (value, upstream) in order {
             let cid = Node::generate_id();
 
-            let map_with_error = |k: &String| {
-                dnode_mapping.get(k).cloned().unwrap_or_else(|| {
-                    panic!("Failed to find '{}' in input mappings. Aborting.", k);
+            let map_with_error = |mapping: &BTreeMap<&String, String>, k: &String| {
+                mapping.get(k).cloned().ok_or_else(|| {
+                    GraphrError::DanglingReference { node: value.id.clone(), reference: k.clone() }
                 })
             };
+            let read = |k: &String| {
+                revisions.get(k)
+                    .and_then(|r| r.iter().rev().find(|(made_by, _)| made_by.is_none_or(|c_id| upstream.contains(c_id))))
+                    .map(|(_, d_id)| d_id.clone())
+                    .ok_or_else(|| GraphrError::DanglingReference { node: value.id.clone(), reference: k.clone() })
+            };
         
             let cnode = CNode {
                 id: cid.clone(),
                 template: value.id.clone(),
-                incoming: value.incoming.iter().map(map_with_error).collect(),
-                outcoming: value.outcoming.iter().map(map_with_error).collect(),
+                incoming: value.incoming.iter().map(read).collect::<Result<_>>()?,
+                outcoming: value.outcoming.iter().map(|k| map_with_error(&dnode_mapping, k)).collect::<Result<_>>()?,
+                modified: modifications.remove(&value.id).unwrap_or_default(),
+                params: value.param_values(params)?,
+                run: None,
+                tags: BTreeSet::new(),
+                metadata: Metadata::new(),
             };
         
             new_cnodes.insert(cid.clone(), cnode);
         }
 
-
-
         // Generate a database
         let db = Database {
-            template: self.clone(),
+            template: template.clone(),
             cnodes : new_cnodes,
             dnodes : new_dnodes,
+            loaded_version: None,
+            journal: Vec::new(),
             };
-        db
-    }
-
-}
-
 
+        // A calculation that reads modified data can not be upstream of the modification
+        let (graph, retrieval) = db.generate_digraph()?;
+        toposort(&graph, None).map_err(|cycle| GraphrError::Cycle(retrieval[&cycle.node_id()].clone()))?;
+        Ok(db)
+    }
 
-impl DatabaseTemplate {
 
     /// Generates a graph
     /// DiGraph. contains node names
     /// BTreeMap - contains key - graph NodeIndex; value - object id. (allows retrieving actual object)
     /// I use this bocause in some places I want to find the orignal object given the label
-    fn generate_digraph(&self) -> (DiGraph::<String, String>, BTreeMap<NodeIndex, String>){
+    fn generate_digraph(&self) -> Result<(DiGraph::<String, String>, BTreeMap<NodeIndex, String>)>{
         
         let mut graph = DiGraph::<String, String>::new(); // initialize the final graph
         let mut back_retrieval: BTreeMap<NodeIndex, String> = BTreeMap::new();
         // Define all graph node object and place them into a BTreeMap. Used for constructing the graph
         let mut graph_nodes:  BTreeMap<String, NodeIndex> = BTreeMap::new(); // node storage thing
-        let mut edges: Vec<(NodeIndex,NodeIndex)> = Vec::new(); 
+        let mut edges: Vec<(NodeIndex,NodeIndex,String)> = Vec::new(); 
 
         // Create nodes for the graph
         for (id, node) in self.cnodes.iter() {
@@ -423,43 +754,75 @@ impl DatabaseTemplate {
         // Go through all nodes
         for (id, node) in self.cnodes.iter() {
             // Go through all inputs in a node
+            let dangling = |i_id: &String| GraphrError::DanglingReference { node: id.clone(), reference: i_id.clone() };
+            let this_node = graph_nodes[id];
+
             for i_id in &node.incoming {
-                let starting_node =  match graph_nodes.get(i_id) {
-                    Some(value) => value,
-                    None => panic!("{}",format!{"Node {} has not been found in the diGraph object.", i_id})
-                };
-                let end_node = graph_nodes.get(id).expect(&format!("input {} found for {} calculation", &i_id, &id));
-                edges.push((*starting_node, *end_node));
+                let starting_node = graph_nodes.get(i_id).ok_or_else(|| dangling(i_id))?;
+                edges.push((*starting_node, this_node, String::new()));
             }
 
             for i_id in &node.outcoming {
-                let starting_node = graph_nodes.get(id).expect(&format!("input {} found for {} calculation", &i_id, &id));
-                let end_node =  match graph_nodes.get(i_id) {
-                    Some(value) => value,
-                    None => panic!("{}",format!{"Node {} has not been found in the diGraph object.", i_id})
-                };
-                edges.push((*starting_node, *end_node));
+                let end_node = graph_nodes.get(i_id).ok_or_else(|| dangling(i_id))?;
+                edges.push((this_node, *end_node, String::new()));
+
+            }
 
+            // Modified data is only an input of the template calculation, otherwise it would form a loop.
+            for m_id in &node.modified {
+                let starting_node = graph_nodes.get(m_id).ok_or_else(|| dangling(m_id))?;
+                edges.push((*starting_node, this_node, MODIFY_EDGE.to_string()));
             }
         }
 
         graph.extend_with_edges(&edges);
-        return (graph, back_retrieval)
+        Ok((graph, back_retrieval))
 
     }
 
     /// Find all root nodes (all input files needed to implement the template.)
-    fn find_root_nodes(&self) -> HashSet<IdNodeTemplate> {
+    fn find_root_nodes(&self) -> Result<HashSet<IdNodeTemplate>> {
 
-        let (graph, mappings) = self.generate_digraph();
+        let (graph, mappings) = self.generate_digraph()?;
 
-        graph
+        Ok(graph
         .node_indices()
         .filter(|&node| graph.neighbors_directed(node, Direction::Incoming).next().is_none())
         .filter_map(|node_id| mappings.get(&node_id).cloned())  // get and clone the IdNodeTemplate
-        .collect()
+        .collect())
 }
 
+    /// Calculation templates in topological order, with the calculations upstream of each.
+    /// Of the calculations that are ready, the one with the smallest id comes first, so the order is always the same.
+    pub(crate) fn calculation_order(&self) -> Result<Vec<(&CNodeTemplate, BTreeSet<&IdCTemplate>)>> {
+        let producers: BTreeMap<&IdDTemplate, &IdCTemplate> = self.cnodes.values()
+            .flat_map(|c| c.outcoming.iter().map(move |d_id| (d_id, &c.id)))
+            .collect();
+        let mut upstream: BTreeMap<&IdCTemplate, BTreeSet<&IdCTemplate>> = BTreeMap::new();
+        let mut order = Vec::new();
+        while order.len() < self.cnodes.len() {
+            let ready = self.cnodes.values().find_map(|c| {
+                if upstream.contains_key(&c.id) {
+                    return None
+                }
+                let before: Vec<&IdCTemplate> = c.incoming.iter().chain(&c.modified).filter_map(|d_id| producers.get(d_id).copied()).collect();
+                let mut all = BTreeSet::new();
+                for b_id in before {
+                    all.extend(upstream.get(b_id)?.iter().copied());
+                    all.insert(b_id);
+                }
+                Some((c, all))
+            });
+            let Some((cnode, all)) = ready else {
+                let waiting = self.cnodes.keys().find(|id| !upstream.contains_key(id)).cloned().unwrap_or_default();
+                return Err(GraphrError::Cycle(waiting))
+            };
+            upstream.insert(&cnode.id, all.clone());
+            order.push((cnode, all));
+        }
+        Ok(order)
+    }
+
 }
 
 
@@ -473,12 +836,15 @@ impl Database {
         let template = DatabaseTemplate {
             cnodes: BTreeMap::new(),
             dnodes: BTreeMap::new(),
+            versions: BTreeMap::new(),
         };
 
         Database {
-            template : template,
+            template,
             cnodes:BTreeMap::new(),
             dnodes:BTreeMap::new(),
+            loaded_version: None,
+            journal: Vec::new(),
         }
 
     }
@@ -494,57 +860,129 @@ impl Database {
 
 
     /// Generate an empty database with the same template
-    fn generate_empty(&self) -> Database{
+    pub fn generate_empty(&self) -> Database{
 
         Database{
             dnodes: BTreeMap::new(),
             cnodes: BTreeMap::new(),
-            template: self.template.clone()
+            template: self.template.clone(),
+            loaded_version: None,
+            journal: Vec::new(),
         }
     }
 
 
     /// methods to interact with the template object.
-    fn template_register_dnode(&mut self, name:String ) -> DNodeTemplate {
+    pub fn template_register_dnode(&mut self, name:String ) -> DNodeTemplate {
+        self.record(Operation::TemplateRegisterDnode { name: name.clone() });
         self.template.register_dnode(name)
     }
 
     /// Register a new calculation
-    /// If a calculation already exists, then update the whole database with the new command.
-    fn template_register_cnode(&mut self, name:String, command : String) -> CNodeTemplate{
+    /// If a different command is already registered under the name, the new command becomes the current version of the template.
+    /// Existing calculations keep pointing at the version they were made with (name@version),
+    /// use overwrite_calculation to move them to the new version.
+    pub fn template_register_cnode(&mut self, name:String, command : String) -> Result<CNodeTemplate>{
 
-        /// Check if the node has chaned of been overwritten
-        let node_id = match self.template.cnodes.get(&name) {
-            Some(old_node) => {
-                let new_node = self.template.create_calculation_node(name, command);
-                
-                if new_node != *old_node {
-                    panic!("A same node in the template has been found! The new node is different. If you want to overwrite the node use explicit mechanism of seach for nodes manually overwrit.");
+        let new_node = self.template.create_calculation_node(name.clone(), command.clone())?;
 
-                }
+        // Registering the same command again keeps the template (and its resources) as it is
+        if let Some(old_node) = self.template.cnodes.get(&name)
+            && *old_node == new_node {
+            return Ok(old_node.clone())
+        }
 
-                new_node
-            },
-            None => {self.template.register_cnode(name, command)}
+        self.install_template(new_node.clone());
+        self.record(Operation::TemplateRegisterCnode { name, command });
+        Ok(new_node)
+    }
 
-        };
+    /// Set the resource hints of a calculation template.
+    /// cores - number of job slots the calculation takes
+    /// memory - memory in MB the calculation needs
+    #[pyo3(signature = (name, cores=1, memory=None))]
+    pub fn template_set_resources(&mut self, name: String, cores: u32, memory: Option<u64>) -> Result<()> {
+        let cnode = self.template.cnodes.get_mut(&name).ok_or(GraphrError::UnknownNode(name.clone()))?;
+        cnode.resources = Resources { cores, memory };
+        self.record(Operation::TemplateSetResources { name, cores, memory });
+        Ok(())
+    }
 
-        node_id
-        
+    #[pyo3(signature = (highlight=None))]
+    pub fn template_as_dot(&self, highlight: Option<Vec<String>>) -> Result<String> {
+        self.template.as_dot(highlight)
+    }
+
+    /// Create an implementation of the template.
+    /// existing - data template -> id of a data node of this database, used instead of calculating it again
+    /// targets - only create the calculations needed for these data templates
+    /// params - values of the parameters of the calculation templates by name
+    #[pyo3(signature = (leafs, existing=None, targets=None, params=None))]
+    pub fn template_create_calculation(&self, leafs: BTreeMap<String, String>, existing: Option<BTreeMap<String, String>>,
+                                       targets: Option<Vec<String>>, params: Option<ParamValues>) -> Result<Database> {
+        let mut existing_dnodes = BTreeMap::new();
+        for (d_template, d_id) in existing.unwrap_or_default() {
+            let dnode = self.dnodes.get(&d_id).ok_or_else(|| GraphrError::UnknownNode(d_id.clone()))?;
+            existing_dnodes.insert(d_template, dnode.clone());
+        }
+        let params = params.unwrap_or_default();
+        match targets {
+            Some(targets) => self.template.history_of(&targets)?.instantiate(leafs, existing_dnodes, &params),
+            None => self.template.instantiate(leafs, existing_dnodes, &params),
+        }
+    }
+
+    /// Create the calculations of all combinations of leaf names and parameter values (see DatabaseTemplate.create_sweep)
+    #[pyo3(signature = (leafs, params=None, zip=false))]
+    pub fn template_create_sweep(&self, leafs: BTreeMap<String, Vec<String>>, params: Option<BTreeMap<String, Vec<MetadataValue>>>,
+                                 zip: bool) -> Result<(Database, Vec<Combination>)> {
+        self.template.sweep(&leafs, &params.unwrap_or_default(), zip)
     }
 
-    fn template_as_dot(&self) -> String {
-        self.template.as_dot()
+    /// Part of the template needed to produce the given data templates
+    pub fn template_subtemplate(&self, targets: Vec<String>) -> Result<DatabaseTemplate> {
+        self.template.history_of(&targets)
     }
 
-    pub fn template_create_calculation(&self, leafs: BTreeMap<String, String>) -> Database {
-        self.template.create_calculation(leafs)
+    pub fn template_to_cwl(&self) -> Result<String> {
+        self.template.to_cwl()
     }
 
+    pub fn template_to_mermaid(&self) -> Result<String> {
+        self.template.mermaid()
+    }
+
+    pub fn template_to_graphml(&self) -> Result<String> {
+        self.template.graphml()
+    }
+
+
+    /// Return the database in DOT format
+    /// cluster - group the nodes of every template
+    /// id_length - number of trailing characters of the id shown next to the template (0 - none)
+    /// highlight - nodes to draw with a thick border (for example a selection)
+    /// data_folder - colour the nodes that are stale, modified or missing in the data folder
+    #[pyo3(signature = (cluster=false, id_length=6, highlight=None, data_folder=None))]
+    pub fn as_dot(&self, cluster: bool, id_length: usize, highlight: Option<Vec<String>>, data_folder: Option<String>) -> Result<String> {
+        let highlight = highlight.unwrap_or_default().into_iter().collect();
+        self.dot(&DotOptions { cluster, id_length, highlight, data_folder })
+    }
+
+    /// Return the database as a Mermaid flowchart
+    pub fn to_mermaid(&self) -> Result<String> {
+        self.mermaid()
+    }
+
+    /// Return the database as a GraphML document (node kind, template, command and location as attributes)
+    pub fn to_graphml(&self) -> Result<String> {
+        self.graphml()
+    }
 
-    pub fn as_dot(&self) -> String {
-        let (graph, retrieval) = self.generate_digraph();
-        format!("{}", Dot::with_config(&graph, &[Config::EdgeNoLabel]))
+    /// Return a self-contained interactive HTML page of the database (pan/zoom, search, history and future of a node)
+    /// root_folder - folder of the data nodes, used to expand the commands
+    #[pyo3(signature = (root_folder="data".to_string()))]
+    pub fn to_html(&self, root_folder: String) -> Result<String> {
+        self.html(&root_folder)
     }
 
     /// Get a DataNode and CalculationNode from a database
@@ -560,13 +998,13 @@ impl Database {
         if calculation_branch {
             let node = self.cnodes.get(&id).expect("Failed to find a calculation node.");
             let return_node = Node::Calculation(node.clone());
-            return Some(return_node)
+            Some(return_node)
 
         }
         else {
             let node = self.dnodes.get(&id).expect("Failed to find the data node");
             let return_node = Node::Data(node.clone());
-            return Some(return_node)
+            Some(return_node)
         }
     }
 
@@ -575,12 +1013,12 @@ impl Database {
         
         let mut filtered_names: Vec<Node> = Vec::new();
 
-        for (key, cnode) in self.cnodes.iter() {
+        for (_key, cnode) in self.cnodes.iter() {
             if cnode.template == template_name {
                 filtered_names.push(Node::Calculation(cnode.clone()));
             }
         }
-        for (key, cnode) in self.dnodes.iter() {
+        for (_key, cnode) in self.dnodes.iter() {
             if cnode.template == template_name {
                 filtered_names.push(Node::Data(cnode.clone()));
             }
@@ -590,73 +1028,77 @@ impl Database {
 
     }
 
+    /// Select all nodes of the template name, whatever version of the template they were made with
+    pub fn select_similar_versions(&self, template_name: String) -> Vec<Node> {
+        self.similar_versions(&template_name)
+    }
+
+    /// All versions of the named calculation template, the current one first
+    pub fn template_versions(&self, name: String) -> Vec<CNodeTemplate> {
+        self.template.get_versions(name)
+    }
+
     /// This is pretty much the same as check agains, but the global database
     /// gets updated too. If there are new template nodes, then the total template
     /// gets expanded
     /// new calculations are also merged
-pub fn check_against_and_register(&mut self, global_db: &mut Database){
+pub fn check_against_and_register(&mut self, global_db: &mut Database) -> Result<()> {
+    let original = self.clone();
+
     // 1. Check template compatibility and expand global template if needed
     for (key, value) in self.template.dnodes.iter() {
         if let Some(global_value) = global_db.template.dnodes.get(key) {
             if value != global_value {
-                panic!("Template mismatch for data node '{}': existing and global templates differ", key);
+                return Err(GraphrError::TemplateConflict(key.clone()))
             }
         } else {
             global_db.template.dnodes.insert(key.clone(), value.clone());
         }
     }
-    for (key, value) in self.template.cnodes.iter() {
-        if let Some(global_value) = global_db.template.cnodes.get(key) {
-            if value != global_value {
-                panic!("Template mismatch for calculation node '{}': existing and global templates differ", key);
-            }
-        } else {
-            global_db.template.cnodes.insert(key.clone(), value.clone());
-        }
-    }
+    // A different version of a calculation template becomes the current one, the calculations follow their version
+    let ids = global_db.merge_templates(&self.template);
+    self.rename_templates(&ids);
 
     // 2. Merge/rename nodes in self to match global_db where possible
-    self.check_against(global_db);
+    self.check_against(global_db)?;
 
-    // 3. Register only new nodes in the global database
+    // 3. Register only new nodes in the global database, existing ones get the tags and metadata
     for (key, value) in self.cnodes.iter() {
-        if !global_db.cnodes.contains_key(key) {
-            global_db.cnodes.insert(key.clone(), value.clone());
+        match global_db.cnodes.get_mut(key) {
+            Some(existing) => existing.merge_annotations(value),
+            None => { global_db.cnodes.insert(key.clone(), value.clone()); }
         }
     }
     for (key, value) in self.dnodes.iter() {
-        if !global_db.dnodes.contains_key(key) {
-            global_db.dnodes.insert(key.clone(), value.clone());
+        match global_db.dnodes.get_mut(key) {
+            Some(existing) => existing.merge_annotations(value),
+            None => { global_db.dnodes.insert(key.clone(), value.clone()); }
         }
     }
+    global_db.record(Operation::CheckAgainstAndRegister { database: original });
+    Ok(())
 }
 /// Given the global database - the method adjusts the self database so that if there are calculations that are the same
 /// (determined from the leaf nodes and the template name), then
 /// the file is renamed.
 /// There could be cases where a template node does not exist in the global_db (in this case the database is being expanded with new unseen calculations), in that case just take the calculation by given a warning that such template has not been found.
-pub fn check_against(&mut self, global_db: &Database) {
+pub fn check_against(&mut self, global_db: &Database) -> Result<()> {
     // 1. Check template compatibility
     for (key, value) in self.template.dnodes.iter() {
-        if let Some(global_value) = global_db.template.dnodes.get(key) {
-            if value != global_value {
-                panic!("Template mismatch for data node '{}': existing and global templates differ", key);
-            }
-        }
-    }
-    for (key, value) in self.template.cnodes.iter() {
-        if let Some(global_value) = global_db.template.cnodes.get(key) {
-            if value != global_value {
-                panic!("Template mismatch for calculation node '{}': existing and global templates differ", key);
-            }
-        } else {
-            println!("Warning: Template '{}' not found in global database", key);
+        if let Some(global_value) = global_db.template.dnodes.get(key)
+            && value != global_value {
+            return Err(GraphrError::TemplateConflict(key.clone()))
         }
     }
+    // Calculation templates are matched by name and version
+    let ids = self.match_templates(global_db);
+    self.rename_templates(&ids);
 
     #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
     enum NodeIdentifier {
         Calculation {
             template: String,
+            params: String,
             input_ids: BTreeSet<String>,
         },
         LeafData {
@@ -670,7 +1112,7 @@ pub fn check_against(&mut self, global_db: &Database) {
     }
 
     // --- First pass: Merge data nodes (leaf and derived) ---
-    let (global_graph, global_retrieval) = global_db.generate_digraph();
+    let (global_graph, global_retrieval) = global_db.generate_digraph()?;
     let mut global_identifiers: BTreeMap<NodeIdentifier, String> = BTreeMap::new();
 
     for node in global_graph.node_indices() {
@@ -688,6 +1130,7 @@ pub fn check_against(&mut self, global_db: &Database) {
                 }
                 NodeIdentifier::Calculation {
                     template: calc.template.clone(),
+                    params: calc.param_key(),
                     input_ids,
                 }
             }
@@ -714,6 +1157,7 @@ pub fn check_against(&mut self, global_db: &Database) {
                             }
                             let calc_ident = NodeIdentifier::Calculation {
                                 template: calc.template.clone(),
+                                params: calc.param_key(),
                                 input_ids,
                             };
                             NodeIdentifier::DerivedData {
@@ -745,7 +1189,7 @@ pub fn check_against(&mut self, global_db: &Database) {
         global_identifiers.insert(identifier, node_id.clone());
     }
 
-    let (self_graph, self_retrieval) = self.generate_digraph();
+    let (self_graph, self_retrieval) = self.generate_digraph()?;
     let mut rename_map: BTreeMap<String, String> = BTreeMap::new();
 
     // --- First pass: Data nodes only ---
@@ -777,6 +1221,7 @@ pub fn check_against(&mut self, global_db: &Database) {
                             }
                             let calc_ident = NodeIdentifier::Calculation {
                                 template: calc.template.clone(),
+                                params: calc.param_key(),
                                 input_ids,
                             };
                             NodeIdentifier::DerivedData {
@@ -807,19 +1252,17 @@ pub fn check_against(&mut self, global_db: &Database) {
         };
 
         let is_root = self_graph.neighbors_directed(node, Direction::Incoming).next().is_none();
-        if !is_root {
-            if let Some(global_node_id) = global_identifiers.get(&identifier) {
-                if node_id != global_node_id {
-                    rename_map.insert(node_id.clone(), global_node_id.clone());
-                }
-            }
+        if !is_root
+            && let Some(global_node_id) = global_identifiers.get(&identifier)
+            && node_id != global_node_id {
+            rename_map.insert(node_id.clone(), global_node_id.clone());
         }
     }
 
     self.apply_renames(&rename_map);
 
     // --- Second pass: Calculation nodes, using possibly renamed data node IDs ---
-    let mut renamed_id = |id: &String| -> String {
+    let renamed_id = |id: &String| -> String {
         rename_map.get(id).cloned().unwrap_or_else(|| id.clone())
     };
 
@@ -840,6 +1283,7 @@ pub fn check_against(&mut self, global_db: &Database) {
                 }
                 NodeIdentifier::Calculation {
                     template: calc.template.clone(),
+                    params: calc.param_key(),
                     input_ids,
                 }
             }
@@ -847,45 +1291,44 @@ pub fn check_against(&mut self, global_db: &Database) {
         };
 
         let is_root = self_graph.neighbors_directed(node, Direction::Incoming).next().is_none();
-        if !is_root {
-            if let Some(global_node_id) = global_identifiers.get(&identifier) {
-                if node_id != global_node_id {
-                    calc_rename_map.insert(node_id.clone(), global_node_id.clone());
-                }
-            }
+        if !is_root
+            && let Some(global_node_id) = global_identifiers.get(&identifier)
+            && node_id != global_node_id {
+            calc_rename_map.insert(node_id.clone(), global_node_id.clone());
         }
     }
 
     self.apply_renames(&calc_rename_map);
+    Ok(())
 }
 
 
 
-pub fn merge_into(&mut self, global_db: &mut Database) {
+pub fn merge_into(&mut self, global_db: &mut Database) -> Result<()> {
     // 1. Merge templates
+    // Check everything first, so that a failed merge leaves global_db untouched
     for (key, value) in self.template.dnodes.iter() {
-        match global_db.template.dnodes.get(key) {
-            Some(global_value) if global_value != value => {
-                panic!("Template mismatch for data node '{}'", key);
-            }
-            None => {
-                global_db.template.dnodes.insert(key.clone(), value.clone());
-            }
-            _ => {}
+        if global_db.template.dnodes.get(key).is_some_and(|global_value| global_value != value) {
+            return Err(GraphrError::TemplateConflict(key.clone()))
         }
     }
-    for (key, value) in self.template.cnodes.iter() {
-        match global_db.template.cnodes.get(key) {
-            Some(global_value) if global_value != value => {
-                panic!("Template mismatch for calculation node '{}'", key);
+    for c in self.cnodes.values() {
+        for d_id in c.inputs().chain(c.outputs()) {
+            if !self.dnodes.contains_key(d_id) {
+                return Err(GraphrError::DanglingReference { node: c.id.clone(), reference: d_id.clone() })
             }
-            None => {
-                global_db.template.cnodes.insert(key.clone(), value.clone());
-            }
-            _ => {}
         }
     }
 
+    let original = self.clone();
+    for (key, value) in self.template.dnodes.iter() {
+        global_db.template.dnodes.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+    // The incoming calculation templates (and their resource hints) become the current versions,
+    // the calculations refer to the versions by their ids in global_db
+    let ids = global_db.merge_templates(&self.template);
+    self.rename_templates(&ids);
+
     // 2. Merge nodes and build mapping from self IDs to global_db IDs
     let mut id_map: BTreeMap<String, String> = BTreeMap::new();
 
@@ -894,61 +1337,86 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         self_db: &Database,
         global_db: &mut Database,
         id_map: &mut BTreeMap<String, String>,
-    ) -> String {
+    ) -> Result<String> {
         if let Some(mapped) = id_map.get(node_id) {
-            return mapped.clone();
+            return Ok(mapped.clone());
         }
-        let node = self_db.get(node_id.clone()).unwrap();
+        let node = self_db.get(node_id.clone()).ok_or_else(|| GraphrError::UnknownNode(node_id.clone()))?;
         match node {
             Node::Data(d) => {
+                // Revisions keep pointing to the (merged) first revision
+                let mut d = d.clone();
+                if let Some(base) = &d.revision_of
+                    && self_db.dnodes.contains_key(base) {
+                    d.revision_of = Some(merge_node(base, self_db, global_db, id_map)?);
+                }
+
                 // Find parent calculation (if any)
-                let parent_calc_id = self_db.cnodes.values().find(|c| c.outcoming.contains(&d.id)).map(|c| c.id.clone());
-                let global_node_id = if let Some(parent_calc_id) = parent_calc_id {
-                    let global_parent_calc_id = merge_node(&parent_calc_id, self_db, global_db, id_map);
-                    let found = global_db.dnodes.values().find(|dn| {
-                        dn.template == d.template &&
-                        global_db.cnodes.values().any(|c| c.outcoming.contains(&dn.id) && c.id == global_parent_calc_id)
-                    });
-                    if let Some(existing) = found {
-                        existing.id.clone()
-                    } else {
-                        let new_id = d.id.clone();
-                        global_db.dnodes.insert(new_id.clone(), d.clone());
-                        new_id
-                    }
+                let parent_calc = self_db.cnodes.values().find(|c| c.produces(&d.id));
+                let global_node_id = if let Some(parent_calc) = parent_calc {
+                    let global_parent_calc_id = merge_node(&parent_calc.id, self_db, global_db, id_map)?;
+                    // Same template, so the data is at the same position among the outputs of the global calculation
+                    let dangling = || GraphrError::DanglingReference { node: parent_calc.id.clone(), reference: d.id.clone() };
+                    let position = parent_calc.outputs().position(|o_id| *o_id == d.id).ok_or_else(dangling)?;
+                    global_db.cnodes.get(&global_parent_calc_id)
+                        .and_then(|global_c| global_c.outputs().nth(position))
+                        .ok_or_else(dangling)?
+                        .clone()
                 } else {
-                    let found = global_db.dnodes.values().find(|dn| dn.template == d.template && dn.id == d.id);
-                    if let Some(existing) = found {
-                        existing.id.clone()
-                    } else {
-                        let new_id = d.id.clone();
-                        global_db.dnodes.insert(new_id.clone(), d.clone());
-                        new_id
-                    }
+                    d.id.clone()
                 };
+
+                match global_db.dnodes.get_mut(&global_node_id).filter(|dn| dn.template == d.template) {
+                    Some(existing) => {
+                        // Keep the known hash of the data
+                        if existing.hash.is_none() {
+                            existing.hash = d.hash.clone();
+                        }
+                        existing.merge_annotations(&d);
+                    }
+                    None => {
+                        d.id = global_node_id.clone();
+                        global_db.dnodes.insert(global_node_id.clone(), d);
+                    }
+                }
                 id_map.insert(node_id.clone(), global_node_id.clone());
-                global_node_id
+                Ok(global_node_id)
             }
             Node::Calculation(c) => {
                 let mut global_input_ids = Vec::new();
                 for input_id in &c.incoming {
-                    let global_input_id = merge_node(input_id, self_db, global_db, id_map);
+                    let global_input_id = merge_node(input_id, self_db, global_db, id_map)?;
                     global_input_ids.push(global_input_id);
                 }
+                let mut global_modified_ids = Vec::new();
+                for (from_id, _) in &c.modified {
+                    global_modified_ids.push(merge_node(from_id, self_db, global_db, id_map)?);
+                }
                 let found = global_db.cnodes.values().find(|cn| {
                     cn.template == c.template &&
-                    cn.incoming == global_input_ids
+                    cn.params == c.params &&
+                    cn.incoming == global_input_ids &&
+                    cn.modified.iter().map(|(from_id, _)| from_id).eq(global_modified_ids.iter())
                 });
                 let global_calc_id = if let Some(existing) = found {
-                    existing.id.clone()
+                    let existing_id = existing.id.clone();
+                    let global_c = global_db.cnodes.get_mut(&existing_id).ok_or_else(|| GraphrError::UnknownNode(existing_id.clone()))?;
+                    // Keep the most recent run of the calculation
+                    if let Some(run) = &c.run
+                        && global_c.run.as_ref().is_none_or(|r| r.end < run.end) {
+                        global_c.run = Some(run.clone());
+                    }
+                    global_c.merge_annotations(&c);
+                    existing_id
                 } else {
                     let mut new_c = c.clone();
                     new_c.incoming = global_input_ids.clone();
+                    new_c.modified = global_modified_ids.into_iter().zip(c.modified.iter().map(|(_, to_id)| to_id.clone())).collect();
                     global_db.cnodes.insert(new_c.id.clone(), new_c.clone());
                     new_c.id.clone()
                 };
                 id_map.insert(node_id.clone(), global_calc_id.clone());
-                global_calc_id
+                Ok(global_calc_id)
             }
         }
     }
@@ -956,49 +1424,54 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
     // Merge all nodes in self into global_db and build id_map
     let all_node_ids: Vec<String> = self.cnodes.keys().chain(self.dnodes.keys()).cloned().collect();
     for node_id in all_node_ids {
-        merge_node(&node_id, self, global_db, &mut id_map);
+        merge_node(&node_id, self, global_db, &mut id_map)?;
     }
 
     // 3. Update all references in self to use canonical global_db IDs
-    // Update calculation node inputs and outputs
-    for c in self.cnodes.values_mut() {
-        c.incoming = c.incoming.iter().map(|id| id_map.get(id).cloned().unwrap_or_else(|| id.clone())).collect();
-        c.outcoming = c.outcoming.iter().map(|id| id_map.get(id).cloned().unwrap_or_else(|| id.clone())).collect();
-    }
-    // Update data node IDs if needed (optional, if you want to fully canonicalize)
-    let mut new_dnodes = BTreeMap::new();
-    for (id, d) in &self.dnodes {
-        let new_id = id_map.get(id).cloned().unwrap_or_else(|| id.clone());
-        let mut new_d = d.clone();
-        new_d.id = new_id.clone();
-        new_dnodes.insert(new_id, new_d);
-    }
-    self.dnodes = new_dnodes;
-    // Update calculation node IDs if needed (optional)
-    let mut new_cnodes = BTreeMap::new();
-    for (id, c) in &self.cnodes {
-        let new_id = id_map.get(id).cloned().unwrap_or_else(|| id.clone());
-        let mut new_c = c.clone();
-        new_c.id = new_id.clone();
-        new_cnodes.insert(new_id, new_c);
-    }
-    self.cnodes = new_cnodes;
+    self.apply_renames(&id_map);
+    global_db.record(Operation::MergeInto { database: original });
+    Ok(())
 }
 
 
-    pub fn to_snakemake(&self) -> String {
-        let mut result = String::new();
-        for (id, node) in &self.cnodes {
-            let inputs: Vec<String> = node.incoming.iter().map(|i| format!("directory({}/{})","data".to_string(), i)).collect();
-            let outputs: Vec<String> = node.outcoming.iter().map(|o| format!("directory({}/{})", "data".to_string(), o)).collect();
+    /// Export the database as a Snakefile.
+    /// root_folder - folder of the data nodes (can be changed with `snakemake --config root=<folder>`)
+    /// conda - conda environment file for every rule
+    /// conda_envs - conda environment files of single calculation templates
+    #[pyo3(signature = (root_folder="data".to_string(), conda=None, conda_envs=None))]
+    pub fn to_snakemake(&self, root_folder: String, conda: Option<String>, conda_envs: Option<BTreeMap<String, String>>) -> Result<String> {
+        self.snakefile(&SnakemakeOptions { root_folder, conda, conda_envs: conda_envs.unwrap_or_default() })
+    }
+
+    /// Export the database as a Nextflow (DSL2) script with a process per calculation template.
+    /// root_folder - folder of the data nodes (can be changed with `nextflow run --root <folder>`)
+    #[pyo3(signature = (root_folder="data".to_string()))]
+    pub fn to_nextflow(&self, root_folder: String) -> Result<String> {
+        self.nextflow_script(&root_folder)
+    }
+
+    /// Export the database as a GNU Makefile with a target for every output data node.
+    /// root_folder - folder of the data nodes (can be changed with `make ROOT=<folder>`)
+    #[pyo3(signature = (root_folder="data".to_string()))]
+    pub fn to_makefile(&self, root_folder: String) -> Result<String> {
+        self.makefile(&root_folder)
+    }
 
-            let command_string = self.get_command(id.clone(), "data".to_string());
+    /// Export CWL job files for the workflow of template_to_cwl, one for every set of root data in the database.
+    /// root_folder - folder of the data nodes
+    #[pyo3(signature = (root_folder="data".to_string()))]
+    pub fn to_cwl_jobs(&self, root_folder: String) -> Result<Vec<String>> {
+        self.cwl_jobs(&root_folder)?.iter().map(|job| Ok(serde_json::to_string_pretty(job)?)).collect()
+    }
 
-            let command = format!("rule {}:\n    input: {}\n    output: {}\n    shell: '{}'\n",
-                                  id, inputs.join(", "), outputs.join(", "), command_string);
-            result.push_str(&command);
-        }
-        result
+    /// Export the provenance of the database as a W3C PROV-JSON document
+    pub fn to_prov_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.prov_json()?)?)
+    }
+
+    /// Export the provenance of the database in W3C PROV-N notation
+    pub fn to_prov_n(&self) -> Result<String> {
+        self.prov_n()
     }
 
 
@@ -1007,11 +1480,13 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
     /// nodes are same if 1) they have the same template tag; 2) have the same root nodes
     /// The database gets modified in place with addition of new nodes
     /// And the provided database gets returned with some nodes relabeled to match the old database
-    pub fn register_pipeline(&mut self, other: Database) -> Database {
+    pub fn register_pipeline(&mut self, other: Database) -> Result<Database> {
+
+        let original = other.clone();
 
         // generate_graphs
-        let (this_graph, this_retrieval) = self.generate_digraph();
-        let (other_graph, other_retrieval) = other.generate_digraph();
+        let (this_graph, this_retrieval) = self.generate_digraph()?;
+        let (other_graph, other_retrieval) = other.generate_digraph()?;
 
         // Create a new db out of the old ones
         let mut new_cnodes :BTreeMap<IdC, CNode> = BTreeMap::new();
@@ -1026,6 +1501,8 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         struct NodeIdentifier {
             template: String,
             root_node_names: HashSet<String>,
+            /// Parameter values of the calculations leading to the node, calculations that only differ in them are different
+            params: BTreeSet<String>,
         }
         impl Ord for NodeIdentifier {
             fn cmp(&self, other: &Self) -> Ordering {
@@ -1037,7 +1514,7 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
                         let mut other_vec: Vec<_> = other.root_node_names.iter().collect();
                         self_vec.sort();
                         other_vec.sort();
-                        self_vec.cmp(&other_vec)
+                        self_vec.cmp(&other_vec).then_with(|| self.params.cmp(&other.params))
                     }
                     ord => ord,
                 }
@@ -1050,8 +1527,23 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
             }
         }
 
-        fn find_roots_from_node(graph: &DiGraph<String, String>, start: NodeIndex) -> HashSet<String> {
+        /// Node of the database behind a graph node
+        fn graph_node(db: &Database, retrieval: &BTreeMap<NodeIndex, String>, node: NodeIndex) -> Result<Node> {
+            let node_id = retrieval.get(&node).ok_or_else(|| GraphrError::UnknownNode(format!("{:?}", node)))?;
+            db.get(node_id.clone()).ok_or_else(|| GraphrError::UnknownNode(node_id.clone()))
+        }
+
+        fn node_template(node: &Node) -> String {
+            match node {
+                Node::Calculation(value) => value.template.clone(),
+                Node::Data(value) => value.template.clone(),
+            }
+        }
+
+        /// Root nodes upstream of the node and the parameter values of the calculations on the way
+        fn find_roots_from_node(db: &Database, graph: &DiGraph<String, String>, start: NodeIndex) -> (HashSet<String>, BTreeSet<String>) {
             let mut roots = HashSet::new();
+            let mut params = BTreeSet::new();
             let mut visited = HashSet::new();
             let mut to_visit = VecDeque::new();
 
@@ -1062,6 +1554,10 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
                 if !visited.insert(node) {
                     continue;
                 }
+                if let Some(cnode) = db.cnodes.get(&graph[node])
+                    && !cnode.params.is_empty() {
+                    params.insert(format!("{} {}", cnode.template, cnode.param_key()));
+                }
 
                 let parents: Vec<_> = graph.neighbors_directed(node, Direction::Incoming).collect();
 
@@ -1075,54 +1571,27 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
                     }
                 }
             }
-            roots
+            (roots, params)
         }
 
 
 
         // Go through the other object
         for node in other_graph.node_indices() {
-            let roots = find_roots_from_node(&other_graph,node);
-            let node_id = other_retrieval.get(&node).unwrap();
-            let node_obj = other.get(node_id.clone()).unwrap();
-
-            let (template, id) = match node_obj {
-                Node::Calculation(value) => {
-                    let template = value.template;
-                    let x = other.get(value.id.clone()).expect(&format!("could not find {} in the other database",value.id.clone()));
-                    (template, x)
-                }
-                Node::Data(value) => {
-                    let template = value.template;
-                    let x = other.get(value.id.clone()).expect(&format!("could not find {} in the other database",value.id.clone()));
-                    (template, x)
-                }
-            };
-            mapper.insert(NodeIdentifier {template :template, root_node_names : roots},id);
+            let (roots, params) = find_roots_from_node(&other, &other_graph, node);
+            let node_obj = graph_node(&other, &other_retrieval, node)?;
+            let template = node_template(&node_obj);
+            mapper.insert(NodeIdentifier {template, root_node_names : roots, params}, node_obj);
         }
 
         // Go through the self object
         for node in this_graph.node_indices() {
-            let roots = find_roots_from_node(&this_graph,node);
-            let node_id = this_retrieval.get(&node).unwrap();
-            let node_obj = self.get(node_id.clone()).unwrap();
-
-            let (template, id) = match &node_obj {
-                Node::Calculation(value) => {
-                    let template = value.template.clone();
-                    let x = self.get(value.id.clone()).expect(&format!("could not find {} in the this database",value.id.clone()));
-                    (template, x)
-                }
-                Node::Data(value) => {
-                    let template = value.template.clone();
-                    let x = self.get(value.id.clone()).expect(&format!("could not find {} in the this database",value.id.clone()));
-                    (template, x)
-                    
-                }
-            };
+            let (roots, params) = find_roots_from_node(self, &this_graph, node);
+            let node_obj = graph_node(self, &this_retrieval, node)?;
+            let template = node_template(&node_obj);
             
             
-            let node_identifier = NodeIdentifier {template :template.clone(), root_node_names : roots.clone()};
+            let node_identifier = NodeIdentifier {template :template.clone(), root_node_names : roots.clone(), params: params.clone()};
 
             // Check if this key already exists. If id does, then if it's a computing node
             // the key needs to be modified.
@@ -1139,12 +1608,12 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
                 }
             }
 
-            mapper.insert(NodeIdentifier {template :template, root_node_names : roots},id);
+            mapper.insert(NodeIdentifier {template, root_node_names : roots, params}, node_obj);
         }
 
         // Create a new database
 
-        for (key, value) in mapper.iter(){
+        for (_key, value) in mapper.iter(){
             match value {
                 Node::Calculation(value) => {
                     let mut insert_cnode = value.clone();
@@ -1161,6 +1630,14 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
                         }
                     }
 
+                    for (from, to) in &mut insert_cnode.modified {
+                        for v in [from, to] {
+                            if let Some(replacement) = data_id_overwrites.get(v) {
+                                *v = replacement.clone();
+                            }
+                        }
+                    }
+
                     new_cnodes.insert(value.id.clone(),insert_cnode);
                 },
                 Node::Data(value) => {new_dnodes.insert(value.id.clone(), value.clone());}
@@ -1183,8 +1660,9 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         for (key, value) in self.template.dnodes.iter() {
             match new_template.dnodes.get(key){
                 Some(v) => {
+                    // Templates need to be compatable to merge.
                     if v != value {
-                        panic!("Two data nodes are given, but they are different. Templates need to be compatable to merge.");
+                        return Err(GraphrError::TemplateConflict(key.clone()))
                     }; 
                 }
                 None => {new_template.dnodes.insert(key.clone(), value.clone());}
@@ -1196,18 +1674,14 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
             match new_template.cnodes.get(key){
                 Some(v) => {
                     if v != value {
-                        panic!("Two data nodes are given, but they are different. Templates need to be compatable to merge.{:?}; {:?}", v, value);
+                        return Err(GraphrError::TemplateConflict(key.clone()))
                     }; 
                 }
                 None => {
-                    for dkey in value.incoming.iter() {
-                        if !new_template.dnodes.contains_key(dkey) {
-                            panic!("Data needed for a calculation not found");
-                        }
-                    }
-                    for dkey in value.outcoming.iter() {
+                    // Data needed for a calculation has to be in the template
+                    for dkey in value.incoming.iter().chain(value.outcoming.iter()).chain(value.modified.iter()) {
                         if !new_template.dnodes.contains_key(dkey) {
-                            panic!("Data needed for a calculation not found");
+                            return Err(GraphrError::DanglingReference { node: key.clone(), reference: dkey.clone() })
                         }
                     }
 
@@ -1229,38 +1703,23 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         let mut mutted_other = Database{
             dnodes: BTreeMap::new(),
             cnodes: BTreeMap::new(),
-            template: other.template.clone()
+            template: other.template.clone(),
+            loaded_version: None,
+            journal: Vec::new(),
         };
 
 
-        for (key, value) in other.dnodes.iter() {
-            match data_id_overwrites.get(key){
-                Some(new_key) =>{
-                    // Data node has been overwritten and I need to get the new one
-                    let new_value = new_dnodes.get(new_key).expect("failed to find a key");
-                    mutted_other.dnodes.insert(new_key.clone(), new_value.clone());
-                }
-                None =>{
-                    // The node has not been overwritten. 
-                    let new_value = new_dnodes.get(key).expect("failed to find a key");
-                    mutted_other.dnodes.insert(key.clone(), new_value.clone());
-                }
-            }
+        for (key, _value) in other.dnodes.iter() {
+            // Data node that has been overwritten is replaced by the new one
+            let new_key = data_id_overwrites.get(key).unwrap_or(key);
+            let new_value = new_dnodes.get(new_key).ok_or_else(|| GraphrError::UnknownNode(new_key.clone()))?;
+            mutted_other.dnodes.insert(new_key.clone(), new_value.clone());
         }
 
-        for (key, value) in other.cnodes.iter() {
-            match data_id_overwrites.get(key){
-                Some(new_key) =>{
-                    // Data node has been overwritten and I need to get the new one
-                    let new_value = new_cnodes.get(new_key).expect("failed to find a key");
-                    mutted_other.cnodes.insert(new_key.clone(), new_value.clone());
-                }
-                None =>{
-                    // The node has not been overwritten. 
-                    let new_value = new_cnodes.get(key).expect("failed to find a key");
-                    mutted_other.cnodes.insert(key.clone(), new_value.clone());
-                }
-            }
+        for (key, _value) in other.cnodes.iter() {
+            let new_key = data_id_overwrites.get(key).unwrap_or(key);
+            let new_value = new_cnodes.get(new_key).ok_or_else(|| GraphrError::UnknownNode(new_key.clone()))?;
+            mutted_other.cnodes.insert(new_key.clone(), new_value.clone());
         }
 
         
@@ -1291,18 +1750,20 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         // }
 
 
-        mutted_other
+        self.record(Operation::RegisterPipeline { database: original });
+        Ok(mutted_other)
 
     }
 
 
     /// Create a single new data node
     /// Used when the database is being changed manually.
-    pub fn register_dnode(&mut self, template_id: String, name:Option<String>) -> DNode {
+    pub fn register_dnode(&mut self, template_id: String, name:Option<String>) -> Result<DNode> {
 
         // check if the template id exists amond the template
+        // Make sure that this type of dnode is registered among the templates and that your're providing a Data Node.
         if !self.template.dnodes.contains_key(&template_id) {
-            panic!("DNode with template id {} does not exist. Make sure that this type of dnode is registered among the templates and that your're providing a Data Node.", template_id);
+            return Err(GraphrError::UnknownNode(template_id))
         }
 
         let node_id = match name {
@@ -1313,11 +1774,17 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
         // Create a dnode
         let new_dnode = DNode { id: node_id.clone(),
                                 template: template_id.clone(),
+                                revision_of: None,
+                                revision: 0,
+                                hash: None,
+                                tags: BTreeSet::new(),
+                                metadata: Metadata::new(),
                             };
 
-        self.dnodes.insert(node_id, new_dnode.clone());
+        self.dnodes.insert(node_id.clone(), new_dnode.clone());
+        self.record(Operation::RegisterDnode { template: template_id, name: node_id });
 
-        new_dnode
+        Ok(new_dnode)
 
     }
 
@@ -1328,123 +1795,290 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
     /// calculation_name - template name of the calculation
     /// new_command - string that specifies the new command to overwrite with
     /// database_corrections BTreeMap<'calculation id', 'BTreeMap<'data node template id', (["name","filename of the new dnode"]|"id", "id of an existing dnode"])>>
-    pub fn overwrite_calculation(&mut self, calculation_name: String, new_command:String, database_correction:BTreeMap<String, BTreeMap<String, String>>) {
-        // Check if all calculation nodes are provided.
-        // Also make sure that all inputs and outputs are specfied. Fully overwrite every node.
+    /// Every calculation of the template (in any version) has to be given, data templates that are not corrected keep their data node.
+    /// Returns the calculations downstream of the overwritten ones, which are invalidated by the change (see overwrite.rs).
+    pub fn overwrite_calculation(&mut self, calculation_name: String, new_command:String, database_correction: Corrections) -> Result<Vec<String>> {
         // This dictionary can be generated by using the selection functions and the database.
-
-        // Check the inputs
-
+        self.apply_overwrite(&calculation_name, &new_command, &database_correction, &BTreeMap::new())
     }
 
     /// Selects Future of given Node
-    pub fn select_future(&self, name: String) -> Database {
-        let subgraph = self.select_node_future(name);
-        let graph = self.digraph_to_database(&subgraph);
-        graph
+    pub fn select_future(&self, name: String) -> Result<Database> {
+        let subgraph = self.select_node_future(name)?;
+        Ok(self.digraph_to_database(&subgraph))
     }
 
     /// Select History of a given node
-    pub fn select_history(&self, name: String) -> Database {
-        let subgraph = self.select_node_history(name);
-        let graph = self.digraph_to_database(&subgraph);
-        graph
+    pub fn select_history(&self, name: String) -> Result<Database> {
+        let subgraph = self.select_node_history(name)?;
+        Ok(self.digraph_to_database(&subgraph))
     }
 
-    // /// Select template history
-    // /// Used for subselecting templates. Allows creating partial workflows.
-    // /// Need to filder the database and the calculations, so that the data in the template match.
-    // pub fn select_template_hisotyr(&self, name:String) -> Database {
+    /// Select all nodes connected to the given node, in either direction (the branch of the workflow it belongs to)
+    pub fn select_subbranch(&self, name: String) -> Result<Database> {
+        let (graph, retrieval) = self.generate_digraph()?;
+        let start = retrieval.iter().find(|(_, id)| **id == name).map(|(index, _)| *index)
+            .ok_or_else(|| GraphrError::UnknownNode(name.clone()))?;
+
+        let mut seen = BTreeSet::from([start]);
+        let mut queue = vec![start];
+        while let Some(index) = queue.pop() {
+            for neighbour in graph.neighbors_undirected(index) {
+                if seen.insert(neighbour) {
+                    queue.push(neighbour);
+                }
+            }
+        }
+        Ok(self.select_by_name(seen.iter().map(|index| retrieval[index].clone()).collect()))
+    }
 
-    // }
+    /// Select nodes with a query, for example `future("x") & template("p2") - id("^tmp")`.
+    /// Predicates: all(), id(regex), template(name), versions(name), history(id), future(id), tag(name), attr(key[, value]); operators: | & - and parentheses.
+    pub fn select(&self, query: String) -> Result<Database> {
+        self.select_query(&query)
+    }
+
+    /// Add tags to the named nodes (all nodes if not given)
+    #[pyo3(signature = (tags, names=None))]
+    pub fn add_tags(&mut self, tags: Vec<String>, names: Option<Vec<String>>) -> Result<()> {
+        let names = self.annotate(names, |node_tags, _| node_tags.extend(tags.iter().cloned()))?;
+        self.record(Operation::AddTags { names, tags });
+        Ok(())
+    }
+
+    /// Replace the tags of the named nodes (all nodes if not given)
+    #[pyo3(signature = (tags, names=None))]
+    pub fn set_tags(&mut self, tags: Vec<String>, names: Option<Vec<String>>) -> Result<()> {
+        let names = self.annotate(names, |node_tags, _| *node_tags = tags.iter().cloned().collect())?;
+        self.record(Operation::SetTags { names, tags });
+        Ok(())
+    }
+
+    /// Remove tags from the named nodes (all nodes if not given)
+    #[pyo3(signature = (tags, names=None))]
+    pub fn remove_tags(&mut self, tags: Vec<String>, names: Option<Vec<String>>) -> Result<()> {
+        let names = self.annotate(names, |node_tags, _| node_tags.retain(|t| !tags.contains(t)))?;
+        self.record(Operation::RemoveTags { names, tags });
+        Ok(())
+    }
+
+    /// Set a metadata entry (bool, int, float or str) of the named nodes (all nodes if not given)
+    #[pyo3(signature = (key, value, names=None))]
+    pub fn set_metadata(&mut self, key: String, value: MetadataValue, names: Option<Vec<String>>) -> Result<()> {
+        let names = self.annotate(names, |_, metadata| { metadata.insert(key.clone(), value.clone()); })?;
+        self.record(Operation::SetMetadata { names, key, value });
+        Ok(())
+    }
+
+    /// Remove a metadata entry from the named nodes (all nodes if not given)
+    #[pyo3(signature = (key, names=None))]
+    pub fn remove_metadata(&mut self, key: String, names: Option<Vec<String>>) -> Result<()> {
+        let names = self.annotate(names, |_, metadata| { metadata.remove(&key); })?;
+        self.record(Operation::RemoveMetadata { names, key });
+        Ok(())
+    }
+
+    /// Select the nodes that have any of the included tags (every node if none are given) and none of the excluded ones
+    #[pyo3(signature = (include=Vec::new(), exclude=Vec::new()))]
+    pub fn filter_by_tags(&self, include: Vec<String>, exclude: Vec<String>) -> Database {
+        self.select_annotated(|tags, _| {
+            (include.is_empty() || include.iter().any(|t| tags.contains(t))) && !exclude.iter().any(|t| tags.contains(t))
+        })
+    }
+
+    /// Select the nodes that have the metadata key (with the value, if given)
+    #[pyo3(signature = (key, value=None))]
+    pub fn select_metadata(&self, key: String, value: Option<MetadataValue>) -> Database {
+        self.select_annotated(|_, metadata| match (metadata.get(&key), &value) {
+            (Some(found), Some(value)) => found == value,
+            (found, None) => found.is_some(),
+            (None, Some(_)) => false,
+        })
+    }
+
+    /// Select nodes by their ids. Ids that are not found are ignored.
+    pub fn select_by_name(&self, names: Vec<String>) -> Database {
+        let mut db = self.generate_empty();
+
+        for name in names {
+            if let Some(cnode) = self.cnodes.get(&name) {
+                db.cnodes.insert(name.clone(), cnode.clone());
+            }
+            if let Some(dnode) = self.dnodes.get(&name) {
+                db.dnodes.insert(name.clone(), dnode.clone());
+            }
+        }
+        db
+    }
+
+    /// Deletes nodes from the database.
+    /// Deleting a calculation also deletes its outputs. Any calculation that refers to
+    /// a deleted data node gets deleted too, so the database never has dangling references.
+    pub fn delete(&mut self, names: Vec<String>) {
+        let mut remove_cnodes: BTreeSet<IdC> = names.iter().filter(|n| self.cnodes.contains_key(*n)).cloned().collect();
+        let mut remove_dnodes: BTreeSet<IdD> = names.iter().filter(|n| self.dnodes.contains_key(*n)).cloned().collect();
+
+        loop {
+            let mut changed = false;
+
+            for cnode in self.cnodes.values() {
+                if remove_cnodes.contains(&cnode.id) {
+                    for o_id in cnode.outputs() {
+                        changed |= remove_dnodes.insert(o_id.clone());
+                    }
+                }
+                else if cnode.inputs().chain(cnode.outputs()).any(|d| remove_dnodes.contains(d)) {
+                    changed |= remove_cnodes.insert(cnode.id.clone());
+                }
+            }
+
+            if !changed {
+                break
+            }
+        }
+
+        self.cnodes.retain(|id, _| !remove_cnodes.contains(id));
+        self.dnodes.retain(|id, _| !remove_dnodes.contains(id));
+        self.record(Operation::Delete { names });
+    }
+
+    /// Copies the database with new ids.
+    /// reattachments - pairs of (old id, existing id). Used to attach the loose ends of the copy
+    /// (for example leaf inputs) to nodes that already exist in the global database.
+    pub fn copy(&self, reattachments: Vec<(String, String)>) -> Database {
+        let mut rename_map: BTreeMap<String, String> = BTreeMap::new();
+
+        for id in self.cnodes.keys().chain(self.dnodes.keys()) {
+            rename_map.insert(id.clone(), Node::generate_id());
+        }
+        for (old_id, new_id) in reattachments {
+            rename_map.insert(old_id, new_id);
+        }
+
+        let mut db = self.clone();
+        db.apply_renames(&rename_map);
+        // Copies are new calculations, that have not been run yet
+        for cnode in db.cnodes.values_mut() {
+            cnode.run = None;
+        }
+        db
+    }
 
     /// Convert to nodes
     pub fn to_nodes(&self) -> Vec<Node> {
-        unimplemented!();
+        let cnodes = self.cnodes.values().map(|cnode| Node::Calculation(cnode.clone()));
+        let dnodes = self.dnodes.values().map(|dnode| Node::Data(dnode.clone()));
+        cnodes.chain(dnodes).collect()
     }
 
     /// generate the full command to run.
     /// root_folder - prepend a string to all commands.
-    fn get_command(&self,cnode_id: String, root_folder: String) -> String {
+    pub fn get_command(&self,cnode_id: String, root_folder: String) -> Result<String> {
 
         // Go through all inputs and outputs and replace them with appropriate inputs
 
-        let cnode = self.cnodes.get(&cnode_id).expect(&format!("Failed to find '{}' among the calculation nodes!", cnode_id));
+        let cnode = self.cnodes.get(&cnode_id).ok_or_else(|| GraphrError::UnknownNode(cnode_id.clone()))?;
 
-        let template_cnode = self.template.cnodes.get(&cnode.template).expect("Failed to find the template node.");
+        let template_cnode = self.template.get_version(&cnode.template).ok_or_else(|| {
+            GraphrError::DanglingReference { node: cnode_id.clone(), reference: cnode.template.clone() }
+        })?;
 
         let mut full_command = template_cnode.command.clone();
+        // Revisions of a data node are all stored in the same place
+        let location = |d_id: &IdD| self.dnodes.get(d_id).map(|d| d.location().clone()).unwrap_or_else(|| d_id.clone());
 
 
-        for (i, i_id) in cnode.incoming.iter().enumerate() {
-            full_command = full_command.replace(&format!("$i_{}", i), &format!("{}/{}",root_folder,i_id));
+        // Replace in reverse order so that $i_1 does not clobber $i_10
+        for (i, i_id) in cnode.incoming.iter().enumerate().rev() {
+            full_command = full_command.replace(&format!("$i_{}", i), &format!("{}/{}",root_folder,location(i_id)));
         }
 
 
-        for (o, o_id) in cnode.outcoming.iter().enumerate() {
-            full_command = full_command.replace(&format!("$o_{}", o), &format!("{}/{}",root_folder,o_id));
+        for (o, o_id) in cnode.outcoming.iter().enumerate().rev() {
+            full_command = full_command.replace(&format!("$o_{}", o), &format!("{}/{}",root_folder,location(o_id)));
         }
 
-        full_command
+        for (m, (m_id, _)) in cnode.modified.iter().enumerate().rev() {
+            full_command = full_command.replace(&format!("$m_{}", m), &format!("{}/{}",root_folder,location(m_id)));
+        }
 
-    }
+        let values = template_cnode.param_values(&cnode.params)?;
+        full_command = template_cnode.fill_params(&full_command, |_, param| shell_word(&values[&param.name]));
 
-    pub fn write(&self, folder: String) -> PyResult<()> {
-        let path = Path::new(&folder);
+        Ok(full_command)
 
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent).map_err(|e| {
-                pyo3::exceptions::PyIOError::new_err(format!("Failed to create directory: {e}"))
-            })?;
-        }
+    }
 
-        let mut file = std::fs::File::create(&path).map_err(|e| {
-            pyo3::exceptions::PyIOError::new_err(format!("Failed to create file: {e}"))
-        })?;
+    /// Classify every node as missing, up-to-date, modified or stale.
+    #[pyo3(signature = (data_folder="data".to_string()))]
+    pub fn status(&self, data_folder: String) -> Result<BTreeMap<String, NodeStatus>> {
+        self.node_status(&data_folder)
+    }
 
-        let write_string = serde_json::to_string_pretty(self).map_err(|e| {
-            pyo3::exceptions::PyValueError::new_err(format!("Serialization failed: {e}"))
-        })?;
+    /// Record the content hashes of the data nodes as they are in the data folder.
+    /// names - data nodes to record. All data nodes if not given.
+    #[pyo3(signature = (names=None, data_folder="data".to_string()))]
+    pub fn register_hashes(&mut self, names: Option<Vec<String>>, data_folder: String) -> Result<()> {
+        self.record_hashes(names, &data_folder)
+    }
 
-        file.write_all(write_string.as_bytes()).map_err(|e| {
-            pyo3::exceptions::PyIOError::new_err(format!("Failed to write file: {e}"))
-        })?;
+    /// Execute all calculations that have not run successfully yet.
+    /// data_folder - folder that holds the data nodes
+    /// log_folder - stdout and stderr of every calculation is written here
+    /// keep_going - if true, calculations that do not depend on a failed calculation still run
+    /// jobs - number of job slots. Independent calculations run in parallel.
+    /// memory - total memory in MB available to the calculations
+    #[pyo3(signature = (data_folder="data".to_string(), log_folder=".graph/logs".to_string(), keep_going=false, jobs=1, memory=None, progress=false))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn run(&mut self, data_folder: String, log_folder: String, keep_going: bool, jobs: usize, memory: Option<u64>, progress: bool) -> Result<RunSummary> {
+        let policy = if keep_going {FailurePolicy::Continue} else {FailurePolicy::Stop};
+        self.run_calculations(&RunOptions { data_folder, log_folder, policy, jobs, memory, progress })
+    }
 
-        Ok(())
+    /// Bring the target node up to date. Only the calculations in its history whose inputs, outputs
+    /// or command template changed since their last run are executed again (together with everything downstream of them).
+    #[pyo3(signature = (target, data_folder="data".to_string(), log_folder=".graph/logs".to_string(), keep_going=false, jobs=1, memory=None, progress=false))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(&mut self, target: String, data_folder: String, log_folder: String, keep_going: bool, jobs: usize, memory: Option<u64>, progress: bool) -> Result<RunSummary> {
+        let policy = if keep_going {FailurePolicy::Continue} else {FailurePolicy::Stop};
+        self.update_target(&target, &RunOptions { data_folder, log_folder, policy, jobs, memory, progress })
     }
 
+    /// Write the database. Paths ending with .sqlite, .sqlite3 or .db are written as sqlite, everything else as json.
+    pub fn write(&mut self, folder: String) -> Result<()> {
+        open_storage(&folder)?.save(self)
+    }
 
+    /// Merge the database into the stored one without rewriting all of it.
+    /// The database is renamed to the ids used by the stored database.
+    pub fn merge_into_storage(&mut self, path: String) -> Result<()> {
+        open_storage(&path)?.merge(self)
+    }
 
     /// Read the databsase.
     /// If a file exist then it's read as normal.
     /// If it does not exist, then a new database is returned.
     #[classmethod]
-    pub fn read(_cls: &Bound<'_, PyType>, path: String) -> PyResult<Self> {
-
-        let path_o = Path::new(&path);
+    pub fn read(_cls: &Bound<'_, PyType>, path: String) -> Result<Self> {
 
-        if path_o.exists(){
-            let content = std::fs::read_to_string(path_o).map_err(|e| {
-                pyo3::exceptions::PyIOError::new_err(format!("Failed to read file: {e}"))
-            })?;
-
-            serde_json::from_str(&content).map_err(|e| {
-                pyo3::exceptions::PyValueError::new_err(format!("Failed to parse JSON: {e}"))
-            })
-        }
-        else {
+        if !Path::new(&path).exists() {
             println!("File not found. Initializing an empty database.");
-            let db = Database {
-                template: DatabaseTemplate {
-                    cnodes : BTreeMap::new(),
-                    dnodes : BTreeMap::new()
-                },
-                cnodes: BTreeMap::new(),
-                dnodes: BTreeMap::new()
-            };
-            Ok(db)
         }
 
+        open_storage(&path)?.load()
+    }
+
+    /// Undo the last operation on the stored database (see the journal next to the file).
+    /// Returns the database as it is after the undo.
+    #[classmethod]
+    pub fn undo(_cls: &Bound<'_, PyType>, path: String) -> Result<Self> {
+        undo_last(&path)
+    }
+
+    /// Rebuild the stored database from its journal.
+    #[classmethod]
+    pub fn replay(_cls: &Bound<'_, PyType>, path: String) -> Result<Self> {
+        replay(&path)
     }
 
 
@@ -1453,17 +2087,57 @@ pub fn merge_into(&mut self, global_db: &mut Database) {
 
 impl Database{
 
+    /// Write the database to a json file. Parent folders are created if needed.
+    pub fn write_file(&self, path: &str) -> Result<()> {
+        let path = Path::new(path);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let write_string = serde_json::to_string_pretty(self)?;
+
+        // Write to a temporary file first and move it in place, so that nobody reads a half written file
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(write_string.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Get a calculation node template by its id (name for the current version, name@version for earlier ones).
+    pub fn get_template_cnode(&self, id: &str) -> Option<&CNodeTemplate> {
+        self.template.get_version(id)
+    }
+
+    /// Read the database from a json file.
+    /// If the file does not exist, then an empty database is returned.
+    pub fn read_file(path: &str) -> Result<Database> {
+        let path = Path::new(path);
+
+        if !path.exists() {
+            return Ok(Database::new())
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        let db = serde_json::from_str(&content)?;
+        Ok(db)
+    }
+
     /// Generates a graph
     /// DiGraph. contains node names
     /// BTreeMap - contains key - graph NodeIndex; value - object id. (allows retrieving actual object)
     /// I use this bocause in some places I want to find the orignal object given the label
-    fn generate_digraph(&self) -> (DiGraph::<String, String>, BTreeMap<NodeIndex, String>){
+    fn generate_digraph(&self) -> Result<(DiGraph::<String, String>, BTreeMap<NodeIndex, String>)>{
         
         let mut graph = DiGraph::<String, String>::new(); // initialize the final graph
         let mut back_retrieval: BTreeMap<NodeIndex, String> = BTreeMap::new();
         // Define all graph node object and place them into a BTreeMap. Used for constructing the graph
         let mut graph_nodes:  BTreeMap<String, NodeIndex> = BTreeMap::new(); // node storage thing
-        let mut edges: Vec<(NodeIndex,NodeIndex)> = Vec::new(); 
+        let mut edges: Vec<(NodeIndex,NodeIndex,String)> = Vec::new(); 
 
         // Create nodes for the graph
         for (id, node) in self.cnodes.iter() {
@@ -1484,28 +2158,31 @@ impl Database{
         // Go through all nodes
         for (id, node) in self.cnodes.iter() {
             // Go through all inputs in a node
+            let dangling = |i_id: &String| GraphrError::DanglingReference { node: id.clone(), reference: i_id.clone() };
+            let this_node = graph_nodes[id];
+
             for i_id in &node.incoming {
-                let starting_node =  match graph_nodes.get(i_id) {
-                    Some(value) => value,
-                    None => panic!("{}",format!{"Node {} has not been found in the diGraph object.", i_id})
-                };
-                let end_node = graph_nodes.get(id).expect(&format!("input {} found for {} calculation", &i_id, &id));
-                edges.push((*starting_node, *end_node));
+                let starting_node = graph_nodes.get(i_id).ok_or_else(|| dangling(i_id))?;
+                edges.push((*starting_node, this_node, String::new()));
             }
 
             for i_id in &node.outcoming {
-                let starting_node = graph_nodes.get(id).expect(&format!("input {} found for {} calculation", &i_id, &id));
-                let end_node =  match graph_nodes.get(i_id) {
-                    Some(value) => value,
-                    None => panic!("{}",format!{"Node {} has not been found in the diGraph object.", i_id})
-                };
-                edges.push((*starting_node, *end_node));
+                let end_node = graph_nodes.get(i_id).ok_or_else(|| dangling(i_id))?;
+                edges.push((this_node, *end_node, String::new()));
 
             }
+
+            // Every modification reads one revision and writes the next one, so there is no loop.
+            for (from_id, to_id) in &node.modified {
+                let starting_node = graph_nodes.get(from_id).ok_or_else(|| dangling(from_id))?;
+                let end_node = graph_nodes.get(to_id).ok_or_else(|| dangling(to_id))?;
+                edges.push((*starting_node, this_node, MODIFY_EDGE.to_string()));
+                edges.push((this_node, *end_node, MODIFY_EDGE.to_string()));
+            }
         }
 
         graph.extend_with_edges(&edges);
-        return (graph, back_retrieval)
+        Ok((graph, back_retrieval))
 
     }
 
@@ -1533,6 +2210,14 @@ impl Database{
                     *outgoing_ref = new_ref.clone();
                 }
             }
+            for (from_ref, to_ref) in &mut new_cnode.modified {
+                if let Some(new_ref) = rename_map.get(from_ref) {
+                    *from_ref = new_ref.clone();
+                }
+                if let Some(new_ref) = rename_map.get(to_ref) {
+                    *to_ref = new_ref.clone();
+                }
+            }
             
             new_cnodes.insert(new_id.clone(), new_cnode);
         }
@@ -1543,6 +2228,10 @@ impl Database{
             
             let mut new_dnode = dnode.clone();
             new_dnode.id = new_id.clone();
+            if let Some(base) = &mut new_dnode.revision_of
+                && let Some(new_ref) = rename_map.get(base) {
+                *base = new_ref.clone();
+            }
             
             new_dnodes.insert(new_id.clone(), new_dnode);
         }
@@ -1570,15 +2259,15 @@ impl Database{
             }
         }
 
-        Database {cnodes : cnodes, dnodes : dnodes, template: self.template.clone()}
+        Database {cnodes, dnodes, template: self.template.clone(), loaded_version: None, journal: Vec::new()}
     }
 
 
-    pub fn select_node_history(&self, name: String) -> DiGraph<String, ()> {
+    pub fn select_node_history(&self, name: String) -> Result<DiGraph<String, ()>> {
 
         let mut new_graph: DiGraph<String, ()> = DiGraph::new();
-        let (current_graph, current_node_name_map) = self.generate_digraph();
-        let origin_node = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("failed to find the origin node! Wrong name provided.");
+        let (current_graph, current_node_name_map) = self.generate_digraph()?;
+        let origin_node = current_graph.node_indices().find(|&node| current_graph[node] == name).ok_or_else(|| GraphrError::UnknownNode(name.clone()))?;
         
         // Create a mapping between original node indices and new node indices
         let mut node_mapping: BTreeMap<NodeIndex, NodeIndex> = BTreeMap::new();
@@ -1591,16 +2280,14 @@ impl Database{
                 node_mapping.insert(*node_index, new_idx);
 
                 // Instert calculation nodes inputs and outputs to the mapping
-                for input_name in &self.cnodes.get(node_name).expect("failed to find a calculation node").incoming {
-                    let new_idx = new_graph.add_node(input_name.clone());
-                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("Failed to find a node");
-                    node_mapping.insert(old_idx, new_idx);
+                for input_name in self.cnodes.get(node_name).expect("failed to find a calculation node").inputs() {
+                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == *input_name).expect("Failed to find a node");
+                    node_mapping.entry(old_idx).or_insert_with(|| new_graph.add_node(input_name.clone()));
 
                 }
-                for output_name in &self.cnodes.get(node_name).expect("failed to find a calculation node").outcoming {
-                    let new_idx = new_graph.add_node(output_name.clone());
-                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("Failed to find a node");
-                    node_mapping.insert(old_idx, new_idx);
+                for output_name in self.cnodes.get(node_name).expect("failed to find a calculation node").outputs() {
+                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == *output_name).expect("Failed to find a node");
+                    node_mapping.entry(old_idx).or_insert_with(|| new_graph.add_node(output_name.clone()));
                 }
             }
         }
@@ -1610,41 +2297,38 @@ impl Database{
         
         // Now add the edges between the nodes in the new graph
         for (node_index,node_name) in current_node_name_map.iter() {
-            if let Some(&new_idx) = node_mapping.get(node_index) {
-                if self.cnodes.contains_key(node_name) {
+            if let Some(&new_idx) = node_mapping.get(node_index)
+                && self.cnodes.contains_key(node_name) {
                     let calc_node = self.cnodes.get(node_name).expect("failed to get the node.");
                     
                     // Add edges for inputs
                     for inp in &calc_node.incoming {
-                        if let Some(input_node) = current_graph.node_indices().find(|&node| current_graph[node] == inp.clone()) {
-                            if let Some(&new_input_idx) = node_mapping.get(&input_node) {
+                        if let Some(input_node) = current_graph.node_indices().find(|&node| current_graph[node] == inp.clone())
+                            && let Some(&new_input_idx) = node_mapping.get(&input_node) {
                                 new_graph.add_edge(new_input_idx, new_idx, ());
                             }
-                        }
                     }
                     
                     // Add edges for outputs
                     for outp in &calc_node.outcoming {
-                        if let Some(output_node) = current_graph.node_indices().find(|&node| current_graph[node] == outp.clone()) {
-                            if let Some(&new_output_idx) = node_mapping.get(&output_node) {
+                        if let Some(output_node) = current_graph.node_indices().find(|&node| current_graph[node] == outp.clone())
+                            && let Some(&new_output_idx) = node_mapping.get(&output_node) {
                                 new_graph.add_edge(new_idx, new_output_idx, ());
                             }
-                        }
                     }
                 }
-            }
         }
 
-        new_graph
+        Ok(new_graph)
     }
 
 
 
-    pub fn select_node_future(&self, name: String) -> DiGraph<String, ()> {
+    pub fn select_node_future(&self, name: String) -> Result<DiGraph<String, ()>> {
 
         let mut new_graph: DiGraph<String, ()> = DiGraph::new();
-        let (mut current_graph, current_node_name_map) = self.generate_digraph();
-        let origin_node = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("failed to find the origin node! Wrong name provided.");
+        let (mut current_graph, current_node_name_map) = self.generate_digraph()?;
+        let origin_node = current_graph.node_indices().find(|&node| current_graph[node] == name).ok_or_else(|| GraphrError::UnknownNode(name.clone()))?;
         current_graph.reverse();
         // Create a mapping between original node indices and new node indices
         let mut node_mapping: BTreeMap<NodeIndex, NodeIndex> = BTreeMap::new();
@@ -1657,16 +2341,14 @@ impl Database{
                 node_mapping.insert(*node_index, new_idx);
 
                 // Instert calculation nodes inputs and outputs to the mapping
-                for input_name in &self.cnodes.get(node_name).expect("failed to find a calculation node").incoming {
-                    let new_idx = new_graph.add_node(input_name.clone());
-                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("Failed to find a node");
-                    node_mapping.insert(old_idx, new_idx);
+                for input_name in self.cnodes.get(node_name).expect("failed to find a calculation node").inputs() {
+                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == *input_name).expect("Failed to find a node");
+                    node_mapping.entry(old_idx).or_insert_with(|| new_graph.add_node(input_name.clone()));
 
                 }
-                for output_name in &self.cnodes.get(node_name).expect("failed to find a calculation node").outcoming {
-                    let new_idx = new_graph.add_node(output_name.clone());
-                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == name).expect("Failed to find a node");
-                    node_mapping.insert(old_idx, new_idx);
+                for output_name in self.cnodes.get(node_name).expect("failed to find a calculation node").outputs() {
+                    let old_idx = current_graph.node_indices().find(|&node| current_graph[node] == *output_name).expect("Failed to find a node");
+                    node_mapping.entry(old_idx).or_insert_with(|| new_graph.add_node(output_name.clone()));
                 }
             }
         }
@@ -1676,32 +2358,29 @@ impl Database{
         
         // Now add the edges between the nodes in the new graph
         for (node_index,node_name) in current_node_name_map.iter() {
-            if let Some(&new_idx) = node_mapping.get(node_index) {
-                if self.cnodes.contains_key(node_name) {
+            if let Some(&new_idx) = node_mapping.get(node_index)
+                && self.cnodes.contains_key(node_name) {
                     let calc_node = self.cnodes.get(node_name).expect("failed to get the node.");
                     
                     // Add edges for inputs
                     for inp in &calc_node.incoming {
-                        if let Some(input_node) = current_graph.node_indices().find(|&node| current_graph[node] == inp.clone()) {
-                            if let Some(&new_input_idx) = node_mapping.get(&input_node) {
+                        if let Some(input_node) = current_graph.node_indices().find(|&node| current_graph[node] == inp.clone())
+                            && let Some(&new_input_idx) = node_mapping.get(&input_node) {
                                 new_graph.add_edge(new_input_idx, new_idx, ());
                             }
-                        }
                     }
                     
                     // Add edges for outputs
                     for outp in &calc_node.outcoming {
-                        if let Some(output_node) = current_graph.node_indices().find(|&node| current_graph[node] == outp.clone()) {
-                            if let Some(&new_output_idx) = node_mapping.get(&output_node) {
+                        if let Some(output_node) = current_graph.node_indices().find(|&node| current_graph[node] == outp.clone())
+                            && let Some(&new_output_idx) = node_mapping.get(&output_node) {
                                 new_graph.add_edge(new_idx, new_output_idx, ());
                             }
-                        }
                     }
                 }
-            }
         }
         new_graph.reverse();
-        new_graph
+        Ok(new_graph)
     }
 
 }
@@ -1710,8 +2389,132 @@ impl Database{
 
 
 #[pymodule]
-fn graphrlib(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+fn graphrlib(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Database>()?;
     m.add_class::<DatabaseTemplate>()?;
+    m.add_class::<RunRecord>()?;
+    m.add_class::<RunSummary>()?;
+    m.add_class::<NodeStatus>()?;
+    m.add_class::<Param>()?;
+    m.add_class::<Combination>()?;
+
+    m.add("GraphrException", py.get_type::<error::GraphrException>())?;
+    m.add("MissingLeafError", py.get_type::<error::MissingLeafError>())?;
+    m.add("TemplateConflictError", py.get_type::<error::TemplateConflictError>())?;
+    m.add("DanglingReferenceError", py.get_type::<error::DanglingReferenceError>())?;
+    m.add("UnknownNodeError", py.get_type::<error::UnknownNodeError>())?;
+    m.add("CycleError", py.get_type::<error::CycleError>())?;
+    m.add("ParseError", py.get_type::<error::ParseError>())?;
+    m.add("GraphrIOError", py.get_type::<error::GraphrIOError>())?;
+    m.add("SerdeError", py.get_type::<error::SerdeError>())?;
+    m.add("StorageError", py.get_type::<error::StorageError>())?;
+    m.add("JournalError", py.get_type::<error::JournalError>())?;
+    m.add("InvalidCorrectionError", py.get_type::<error::InvalidCorrectionError>())?;
+    m.add("InvalidExistingError", py.get_type::<error::InvalidExistingError>())?;
+    m.add("InvalidParamError", py.get_type::<error::InvalidParamError>())?;
+    m.add("InvalidSweepError", py.get_type::<error::InvalidSweepError>())?;
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Database with the data and calculation templates (name, command)
+    fn database(data: &[&str], templates: &[(&str, &str)]) -> Database {
+        let mut db = Database::new();
+        for name in data {
+            db.template_register_dnode(name.to_string());
+        }
+        for (name, command) in templates {
+            db.template_register_cnode(name.to_string(), command.to_string()).unwrap();
+        }
+        db
+    }
+
+    fn create(db: &Database, leaf: &str, params: &[(&str, i64)]) -> Database {
+        let params = params.iter().map(|(name, value)| (name.to_string(), MetadataValue::Integer(*value))).collect();
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), leaf.to_string())]), None, None, Some(params)).unwrap()
+    }
+
+    #[test]
+    fn register_pipeline_keeps_calculations_with_other_params() {
+        let mut db = database(&["x", "y", "z"], &[("A", "cut -f param(n:int) input(x) > output(y)"), ("B", "sort input(y) > output(z)")]);
+        let first = create(&db, "x0", &[("n", 1)]);
+        db.register_pipeline(first.clone()).unwrap();
+
+        let registered = db.register_pipeline(create(&db, "x0", &[("n", 2)])).unwrap();
+        assert_eq!(db.cnodes.len(), 4);
+        assert_eq!(db.dnodes.len(), 5);
+        assert!(registered.cnodes.keys().all(|id| !first.cnodes.contains_key(id)));
+
+        // The same values are the same calculations
+        db.register_pipeline(create(&db, "x0", &[("n", 1)])).unwrap();
+        assert_eq!(db.cnodes.len(), 4);
+    }
+
+    #[test]
+    fn register_pipeline_takes_a_sweep() {
+        let db = database(&["x", "y"], &[("A", "cut -f param(n:int) input(x) > output(y)")]);
+        let params = BTreeMap::from([("n".to_string(), vec![MetadataValue::Integer(1), MetadataValue::Integer(2)])]);
+        let (sweep, _) = db.template_create_sweep(BTreeMap::from([("x".to_string(), vec!["x0".to_string()])]), Some(params), false).unwrap();
+
+        let registered = Database::new().register_pipeline(sweep).unwrap();
+        assert_eq!(registered.cnodes.len(), 2);
+        assert_eq!(registered.dnodes.len(), 3);
+    }
+
+    #[test]
+    fn register_pipeline_checks_modified_data() {
+        let mut db = database(&["x"], &[("M", "touch modify(x)")]);
+        db.template.cnodes.get_mut("M").unwrap().modified = vec!["q".to_string()];
+
+        let result = db.register_pipeline(Database::new());
+        assert!(matches!(result, Err(GraphrError::DanglingReference { node, reference }) if node == "M" && reference == "q"));
+    }
+
+    /// Calculation of the template
+    fn calculation<'a>(db: &'a Database, template: &str) -> &'a CNode {
+        db.cnodes.values().find(|c| c.template == template).unwrap()
+    }
+
+    #[test]
+    fn readers_get_the_data_before_an_independent_modification() {
+        let db = database(&["x", "y"], &[("R", "cp input(x) output(y)"), ("M", "touch modify(x)")]);
+        let created = create(&db, "x0", &[]);
+
+        let (from, to) = &calculation(&created, "M").modified[0];
+        assert_eq!(from, "x0");
+        assert_eq!(created.dnodes[to].revision, 1);
+        assert_eq!(calculation(&created, "R").incoming, ["x0"]);
+    }
+
+    #[test]
+    fn readers_downstream_of_the_modification_get_the_new_revision() {
+        let db = database(&["x", "y", "log"], &[("R", "cat input(log) input(x) > output(y)"), ("M", "touch modify(x) && date > output(log)")]);
+        let created = create(&db, "x0", &[]);
+
+        let (_, to) = &calculation(&created, "M").modified[0];
+        assert_eq!(calculation(&created, "R").incoming[1], *to);
+    }
+
+    #[test]
+    fn modifications_are_applied_in_order() {
+        let db = database(&["x", "y", "log"], &[
+            ("M1", "touch modify(x)"),
+            ("M2", "touch modify(x) && date > output(log)"),
+            ("R", "cat input(log) input(x) > output(y)"),
+        ]);
+        let created = create(&db, "x0", &[]);
+
+        // M1 and M2 are independent, so they are applied in the order of their ids
+        let (first_from, first_to) = &calculation(&created, "M1").modified[0];
+        let (second_from, second_to) = &calculation(&created, "M2").modified[0];
+        assert_eq!(first_from, "x0");
+        assert_eq!(second_from, first_to);
+        assert_eq!(created.dnodes[second_to].revision, 2);
+        // R is only downstream of M2, the last modification
+        assert_eq!(calculation(&created, "R").incoming[1], *second_to);
+    }
+}
@@ -0,0 +1,174 @@
+/*
+Moving existing calculations to a new command of their template (overwrite_calculation).
+Every calculation of the template, in any version, has to be given in the corrections, so that nothing is moved by accident.
+For every calculation the corrections say where the data of the new command comes from and goes to:
+    data template -> ("id", id of an existing data node)    connect an existing data node
+    data template -> ("name", id of a new data node)        create the data node
+Data templates without a correction keep the data node the calculation used for them before.
+Data modified in place gets a new revision, unless the calculation already wrote one of the same data.
+Parameters keep the values of the calculation, parameters the old command did not have take their default.
+The new command becomes the current version of the template. Calculations that changed lose their run, as they have
+to run again; the calculations downstream of them (before or after the change) are reported as invalidated.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use petgraph::algo::toposort;
+use crate::versions::template_name;
+use crate::{CNode, CNodeTemplate, DNode, Database, GraphrError, IdC, IdD, IdDTemplate, Node, Operation, Result};
+
+/// calculation id -> data template -> ("id" | "name", data node id)
+pub type Corrections = BTreeMap<IdC, BTreeMap<IdDTemplate, (String, String)>>;
+
+/// calculation id -> data template -> id of the revision created for the data modified in place
+pub(crate) type Revisions = BTreeMap<IdC, BTreeMap<IdDTemplate, IdD>>;
+
+fn invalid(message: String) -> GraphrError {
+    GraphrError::InvalidCorrection(message)
+}
+
+
+impl Database {
+
+    /// Overwrite the calculations of the template with a new command.
+    /// revisions - ids to use for new revisions of modified data, so that replaying the journal creates the same ones.
+    /// Returns the invalidated calculations downstream.
+    pub(crate) fn apply_overwrite(&mut self, name: &str, command: &str, corrections: &Corrections, revisions: &Revisions) -> Result<Vec<IdC>> {
+        let current = self.template.cnodes.get(name).ok_or_else(|| GraphrError::UnknownNode(name.to_string()))?;
+        // The resource hints stay with the template
+        let template = CNodeTemplate {
+            resources: current.resources.clone(),
+            ..self.template.create_calculation_node(name.to_string(), command.to_string())?
+        };
+        for d_template in template.incoming.iter().chain(&template.outcoming).chain(&template.modified) {
+            if !self.template.dnodes.contains_key(d_template) {
+                return Err(GraphrError::DanglingReference { node: name.to_string(), reference: d_template.clone() })
+            }
+        }
+
+        let targets: BTreeSet<IdC> = self.cnodes.values()
+            .filter(|c| template_name(&c.template) == name)
+            .map(|c| c.id.clone())
+            .collect();
+        let uncovered: Vec<&str> = targets.iter().filter(|id| !corrections.contains_key(*id)).map(|id| id.as_str()).collect();
+        if !uncovered.is_empty() {
+            return Err(invalid(format!("calculations of '{}' without a correction: {}", name, uncovered.join(", "))))
+        }
+        if let Some(unknown) = corrections.keys().find(|id| !targets.contains(*id)) {
+            return Err(invalid(format!("'{}' is not a calculation of '{}'", unknown, name)))
+        }
+
+        // Work on a copy, so that a failure leaves the database untouched
+        let mut db = self.clone();
+        db.install_template(template.clone());
+        let mut created = BTreeSet::new();
+        let mut used_revisions = Revisions::new();
+
+        for id in &targets {
+            let old = db.cnodes[id].clone();
+            let old_template = db.get_template_cnode(&old.template).cloned().ok_or_else(|| {
+                GraphrError::DanglingReference { node: id.clone(), reference: old.template.clone() }
+            })?;
+            // Data node the calculation used for every data template
+            let mut before: BTreeMap<&IdDTemplate, &IdD> = BTreeMap::new();
+            before.extend(old_template.incoming.iter().zip(&old.incoming));
+            before.extend(old_template.outcoming.iter().zip(&old.outcoming));
+            before.extend(old_template.modified.iter().zip(old.modified.iter().map(|(from, _)| from)));
+
+            let correction = &corrections[id];
+            let mut resolve = |db: &mut Database, d_template: &IdDTemplate| {
+                db.resolve_correction(id, d_template, correction.get(d_template), before.get(d_template).copied(), &mut created)
+            };
+            let incoming = template.incoming.iter().map(|t| resolve(&mut db, t)).collect::<Result<Vec<_>>>()?;
+            let outcoming = template.outcoming.iter().map(|t| resolve(&mut db, t)).collect::<Result<Vec<_>>>()?;
+
+            let mut modified = Vec::new();
+            for d_template in &template.modified {
+                let from = resolve(&mut db, d_template)?;
+                let written = old_template.modified.iter().zip(&old.modified)
+                    .find(|(t, (f, _))| *t == d_template && *f == from)
+                    .map(|(_, (_, to))| to.clone());
+                let to = match written {
+                    Some(to) => to,
+                    None => {
+                        let to = revisions.get(id).and_then(|r| r.get(d_template)).cloned().unwrap_or_else(Node::generate_id);
+                        let base = &db.dnodes[&from];
+                        let dnode = DNode {
+                            id: to.clone(),
+                            template: d_template.clone(),
+                            revision_of: Some(base.location().clone()),
+                            revision: base.revision + 1,
+                            ..DNode::default()
+                        };
+                        db.dnodes.insert(to.clone(), dnode);
+                        used_revisions.entry(id.clone()).or_default().insert(d_template.clone(), to.clone());
+                        to
+                    }
+                };
+                modified.push((from, to));
+            }
+
+            // Parameters keep their values, new ones get the default
+            let params = template.param_values(&old.params)?;
+
+            let changed = old_template.version() != template.version()
+                || incoming != old.incoming || outcoming != old.outcoming || modified != old.modified;
+            let run = if changed { None } else { old.run.clone() };
+            db.cnodes.insert(id.clone(), CNode { template: name.to_string(), incoming, outcoming, modified, params, run, ..old });
+        }
+
+        // Every data node has a single producer and the calculations do not form a loop
+        let mut producers: BTreeMap<&IdD, usize> = BTreeMap::new();
+        for d_id in db.cnodes.values().flat_map(|c| c.outputs()) {
+            *producers.entry(d_id).or_default() += 1;
+        }
+        if let Some((d_id, _)) = producers.iter().find(|(_, count)| **count > 1) {
+            return Err(invalid(format!("data node '{}' would be produced by several calculations", d_id)))
+        }
+        let (graph, retrieval) = db.generate_digraph()?;
+        toposort(&graph, None).map_err(|cycle| GraphrError::Cycle(retrieval[&cycle.node_id()].clone()))?;
+
+        let mut invalidated = BTreeSet::new();
+        for id in &targets {
+            for database in [&*self, &db] {
+                let future = database.select_node_future(id.clone())?;
+                invalidated.extend(future.node_weights().filter(|n| database.cnodes.contains_key(*n) && !targets.contains(*n)).cloned());
+            }
+        }
+
+        db.record(Operation::OverwriteCalculation {
+            name: name.to_string(),
+            command: command.to_string(),
+            corrections: corrections.clone(),
+            revisions: used_revisions,
+        });
+        *self = db;
+        Ok(invalidated.into_iter().collect())
+    }
+
+    /// Data node of a calculation for the data template, from the correction or the data node used before
+    fn resolve_correction(&mut self, cnode: &IdC, d_template: &IdDTemplate, correction: Option<&(String, String)>,
+                          before: Option<&IdD>, created: &mut BTreeSet<IdD>) -> Result<IdD> {
+        match correction.map(|(kind, d_id)| (kind.as_str(), d_id)) {
+            Some(("id", d_id)) => {
+                let dnode = self.dnodes.get(d_id).ok_or_else(|| GraphrError::UnknownNode(d_id.clone()))?;
+                if dnode.template != *d_template {
+                    return Err(invalid(format!("data node '{}' is a '{}', not a '{}'", d_id, dnode.template, d_template)))
+                }
+                Ok(d_id.clone())
+            }
+            Some(("name", d_id)) => {
+                match self.dnodes.get(d_id) {
+                    // Several calculations can share a data node created by the same overwrite
+                    Some(dnode) if created.contains(d_id) && dnode.template == *d_template => {}
+                    Some(_) => return Err(invalid(format!("data node '{}' already exists, connect it with (\"id\", \"{}\")", d_id, d_id))),
+                    None => {
+                        self.dnodes.insert(d_id.clone(), DNode { id: d_id.clone(), template: d_template.clone(), ..DNode::default() });
+                        created.insert(d_id.clone());
+                    }
+                }
+                Ok(d_id.clone())
+            }
+            Some((kind, _)) => Err(invalid(format!("unknown kind '{}' for '{}' of '{}', use \"id\" or \"name\"", kind, d_template, cnode))),
+            None => before.cloned().ok_or_else(|| invalid(format!("'{}' has no data node for '{}'", cnode, d_template))),
+        }
+    }
+}
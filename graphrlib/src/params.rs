@@ -0,0 +1,232 @@
+/*
+Parameters of calculation templates.
+A command marks a parameter with param(<name>), param(<name>:<type>) or param(<name>:<type>=<default>),
+the type is one of bool, int, float and str (the default). The marker becomes $p_N in the command.
+Every calculation stores the values of the parameters of its template (given to create_calculation or the defaults),
+so calculations of the same template that only differ in the values are different calculations.
+Values typed in as text (defaults and the command line) are converted to the declared type, str keeps them as written.
+Text values are quoted when they are written into a command, so that the shell passes them on as a single word.
+*/
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use pyo3::prelude::*;
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use crate::{CNode, CNodeTemplate, GraphrError, MetadataValue, Result};
+
+/// Values of parameters by their name
+pub type ParamValues = BTreeMap<String, MetadataValue>;
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamKind {
+    Bool,
+    Int,
+    Float,
+    #[default]
+    Str,
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParamKind::Bool => "bool",
+            ParamKind::Int => "int",
+            ParamKind::Float => "float",
+            ParamKind::Str => "str",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ParamKind {
+    type Err = GraphrError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "bool" => Ok(ParamKind::Bool),
+            "int" => Ok(ParamKind::Int),
+            "float" => Ok(ParamKind::Float),
+            "str" => Ok(ParamKind::Str),
+            _ => Err(GraphrError::ParseError(format!("Unknown parameter type '{}', use bool, int, float or str", name))),
+        }
+    }
+}
+
+impl ParamKind {
+    /// The value as this type. Integers are accepted for floats, text is parsed (as typed in on the command line).
+    fn convert(&self, value: &MetadataValue) -> Option<MetadataValue> {
+        match (self, value) {
+            (ParamKind::Bool, MetadataValue::Bool(_)) => Some(value.clone()),
+            (ParamKind::Int, MetadataValue::Integer(_)) => Some(value.clone()),
+            (ParamKind::Float, MetadataValue::Float(_)) => Some(value.clone()),
+            (ParamKind::Float, MetadataValue::Integer(v)) => Some(MetadataValue::Float(*v as f64)),
+            (ParamKind::Str, _) => Some(MetadataValue::Text(value.to_string())),
+            (ParamKind::Bool, MetadataValue::Text(text)) => text.parse().ok().map(MetadataValue::Bool),
+            (ParamKind::Int, MetadataValue::Text(text)) => text.parse().ok().map(MetadataValue::Integer),
+            (ParamKind::Float, MetadataValue::Text(text)) => text.parse().ok().map(MetadataValue::Float),
+            _ => None,
+        }
+    }
+}
+
+
+/// Parameter of a calculation template
+#[pyclass]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Param {
+    #[pyo3(get)]
+    pub name: String,
+    pub kind: ParamKind,
+    /// Value used when create_calculation is not given one
+    #[pyo3(get)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<MetadataValue>,
+}
+
+impl Param {
+    /// Parameter from the inside of a param(...) marker: name[:type][=default]
+    pub(crate) fn parse(spec: &str) -> Result<Param> {
+        let (declaration, default) = match spec.split_once('=') {
+            Some((declaration, default)) => (declaration, Some(default.trim())),
+            None => (spec, None),
+        };
+        let (name, kind) = match declaration.split_once(':') {
+            Some((name, kind)) => (name.trim(), kind.trim().parse()?),
+            None => (declaration.trim(), ParamKind::default()),
+        };
+        let valid = Regex::new(r"^[A-Za-z_][\w.-]*$").expect("Failed to compile parameter regex.");
+        if !valid.is_match(name) {
+            return Err(GraphrError::ParseError(format!("Invalid parameter name '{}'", name)))
+        }
+        let mut param = Param { name: name.to_string(), kind, default: None };
+        if let Some(default) = default {
+            param.default = Some(param.check(&MetadataValue::Text(default.to_string())).map_err(|_| {
+                GraphrError::ParseError(format!("Default '{}' of parameter '{}' is not a {}", default, name, kind))
+            })?);
+        }
+        Ok(param)
+    }
+
+    /// Inside of the param(...) marker, the inverse of parse
+    pub(crate) fn spec(&self) -> String {
+        let mut spec = self.name.clone();
+        if self.kind != ParamKind::Str {
+            spec.push_str(&format!(":{}", self.kind));
+        }
+        if let Some(default) = &self.default {
+            spec.push_str(&format!("={}", default));
+        }
+        spec
+    }
+
+    /// The value converted to the type of the parameter
+    fn check(&self, value: &MetadataValue) -> Result<MetadataValue> {
+        self.kind.convert(value).ok_or_else(|| {
+            GraphrError::InvalidParam(format!("'{}' is not a valid {} for '{}'", value, self.kind, self.name))
+        })
+    }
+}
+
+#[pymethods]
+impl Param {
+    fn __str__(&self) -> String {
+        format!("param({})", self.spec())
+    }
+
+    /// Type of the parameter: bool, int, float or str
+    #[getter(kind)]
+    fn get_kind(&self) -> String {
+        self.kind.to_string()
+    }
+}
+
+
+impl CNodeTemplate {
+
+    /// Values of all parameters of the template: the given ones (converted to the declared type) or the defaults.
+    /// Values of parameters the template does not have are left out.
+    pub(crate) fn param_values(&self, given: &ParamValues) -> Result<ParamValues> {
+        let mut values = ParamValues::new();
+        for param in &self.params {
+            let value = match (given.get(&param.name), &param.default) {
+                (Some(value), _) => param.check(value)?,
+                (None, Some(default)) => default.clone(),
+                (None, None) => return Err(GraphrError::InvalidParam(format!("'{}' of '{}' needs a value", param.name, self.id))),
+            };
+            values.insert(param.name.clone(), value);
+        }
+        Ok(values)
+    }
+
+    /// Replace the $p_N slots of the command.
+    /// Replaced in reverse order so that $p_1 does not clobber $p_10.
+    pub(crate) fn fill_params(&self, command: &str, value: impl Fn(usize, &Param) -> String) -> String {
+        let mut result = command.to_string();
+        for (n, param) in self.params.iter().enumerate().rev() {
+            result = result.replace(&format!("$p_{}", n), &value(n, param));
+        }
+        result
+    }
+
+    /// Copy of the template with the values of the calculation written into the command
+    pub(crate) fn with_values(&self, cnode: &CNode) -> Result<CNodeTemplate> {
+        let values = self.param_values(&cnode.params)?;
+        let command = self.fill_params(&self.command, |_, param| shell_word(&values[&param.name]));
+        Ok(CNodeTemplate { command, params: Vec::new(), ..self.clone() })
+    }
+}
+
+
+/// The value as a single word of a shell command.
+/// Text is put in single quotes, unless it only has characters the shell does not treat specially.
+pub(crate) fn shell_word(value: &MetadataValue) -> String {
+    let text = value.to_string();
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-.,/:=+@%".contains(c);
+    match value {
+        MetadataValue::Text(_) if text.is_empty() || !text.chars().all(plain) => format!("'{}'", text.replace('\'', "'\\''")),
+        _ => text,
+    }
+}
+
+
+impl CNode {
+
+    /// Parameter values as text, so that calculations can be told apart by them (the values are not Ord)
+    pub(crate) fn param_key(&self) -> String {
+        serde_json::to_string(&self.params).expect("Parameter values can always be serialized")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_typed_by_the_declaration() {
+        let text = |value: &str| MetadataValue::Text(value.to_string());
+        assert_eq!(Param::parse("tag:str=007").unwrap().default, Some(text("007")));
+        assert_eq!(Param::parse("tag=1e3").unwrap().default, Some(text("1e3")));
+        assert_eq!(Param::parse("n:float=1e3").unwrap().default, Some(MetadataValue::Float(1000.0)));
+        assert_eq!(Param::parse("n:int=007").unwrap().default, Some(MetadataValue::Integer(7)));
+        assert!(Param::parse("n:int=1.5").is_err());
+
+        let param = Param::parse("tag").unwrap();
+        assert_eq!(param.check(&text("1.50")).unwrap(), text("1.50"));
+        assert_eq!(param.check(&MetadataValue::Integer(3)).unwrap(), text("3"));
+    }
+
+    #[test]
+    fn text_is_a_single_shell_word() {
+        let text = |value: &str| shell_word(&MetadataValue::Text(value.to_string()));
+        assert_eq!(text("sample_1.txt"), "sample_1.txt");
+        assert_eq!(text("two words"), "'two words'");
+        assert_eq!(text("it's"), "'it'\\''s'");
+        assert_eq!(text("$HOME; rm"), "'$HOME; rm'");
+        assert_eq!(text(""), "''");
+        assert_eq!(shell_word(&MetadataValue::Float(0.5)), "0.5");
+    }
+}
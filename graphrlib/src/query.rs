@@ -0,0 +1,393 @@
+/*
+Query language for selecting nodes of a database.
+A query combines predicates with set operations, for example
+
+    future("f3_analysis1") & template("p2") - id("^tmp")
+
+Predicates:
+    all()             every node
+    id("regex")       nodes whose id matches the regular expression (anywhere in the id, use ^ and $ to anchor)
+    template("name")  nodes of the template (same as select_similar)
+    versions("name")  nodes of the template in any of its versions (same as select_similar_versions)
+    history("id")     the node and everything needed to produce it (same as select_history)
+    future("id")      the node and everything produced from it (same as select_future)
+    tag("name")       nodes with the tag
+    attr("key")       nodes with the metadata key
+    attr("key", "v")  nodes whose metadata value of the key is written as v (true, 3, 0.5, text)
+
+Operators, from the lowest precedence: | (union) and - (difference), then & (intersection).
+Operators of the same precedence are applied from left to right; parentheses group.
+The result only contains the selected nodes, calculations do not bring their inputs and outputs along.
+*/
+use std::collections::BTreeSet;
+use std::fmt;
+use petgraph::graph::DiGraph;
+use regex::Regex;
+use crate::{Database, GraphrError, Result};
+
+
+/// Parsed query
+#[derive(Clone, Debug)]
+pub enum Query {
+    All,
+    Id(Regex),
+    Template(String),
+    Versions(String),
+    History(String),
+    Future(String),
+    Tag(String),
+    /// Metadata key and the value as text
+    Attribute(String, Option<String>),
+    Union(Box<Query>, Box<Query>),
+    Intersection(Box<Query>, Box<Query>),
+    Difference(Box<Query>, Box<Query>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Text(String),
+    Open,
+    Close,
+    Comma,
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "'{}'", name),
+            Token::Text(value) => write!(f, "\"{}\"", value),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Union => write!(f, "'|'"),
+            Token::Intersection => write!(f, "'&'"),
+            Token::Difference => write!(f, "'-'"),
+        }
+    }
+}
+
+impl Query {
+
+    pub fn parse(text: &str) -> Result<Query> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let query = parser.expression()?;
+        if let Some((token, at)) = parser.tokens.get(parser.position) {
+            return Err(GraphrError::ParseError(format!("Unexpected {} at {} in query '{}'", token, at, text)))
+        }
+        Ok(query)
+    }
+}
+
+
+impl Database {
+
+    /// Ids of the nodes selected by the query
+    pub fn query_nodes(&self, query: &Query) -> Result<BTreeSet<String>> {
+        let ids = match query {
+            Query::All => self.node_ids(),
+            Query::Id(pattern) => {
+                self.cnodes.keys().chain(self.dnodes.keys()).filter(|id| pattern.is_match(id)).cloned().collect()
+            }
+            Query::Template(name) => {
+                let cnodes = self.cnodes.values().filter(|c| c.template == *name).map(|c| &c.id);
+                let dnodes = self.dnodes.values().filter(|d| d.template == *name).map(|d| &d.id);
+                cnodes.chain(dnodes).cloned().collect()
+            }
+            Query::Versions(name) => self.similar_versions(name).iter().map(|node| node.id()).collect(),
+            Query::History(id) => self.select_node_history(id.clone())?.node_weights().cloned().collect(),
+            Query::Future(id) => self.select_node_future(id.clone())?.node_weights().cloned().collect(),
+            Query::Tag(tag) => self.filter_by_tags(vec![tag.clone()], Vec::new()).node_ids(),
+            Query::Attribute(key, value) => {
+                self.select_annotated(|_, metadata| match (metadata.get(key), value) {
+                    (Some(found), Some(value)) => found.to_string() == *value,
+                    (found, None) => found.is_some(),
+                    (None, Some(_)) => false,
+                }).node_ids()
+            }
+            Query::Union(a, b) => &self.query_nodes(a)? | &self.query_nodes(b)?,
+            Query::Intersection(a, b) => &self.query_nodes(a)? & &self.query_nodes(b)?,
+            Query::Difference(a, b) => &self.query_nodes(a)? - &self.query_nodes(b)?,
+        };
+        Ok(ids)
+    }
+
+    fn node_ids(&self) -> BTreeSet<String> {
+        self.cnodes.keys().chain(self.dnodes.keys()).cloned().collect()
+    }
+
+    /// Sub-database of the nodes selected by the query text (see the query module)
+    pub fn select_query(&self, query: &str) -> Result<Database> {
+        let ids = self.query_nodes(&Query::parse(query)?)?;
+        let mut graph: DiGraph<String, ()> = DiGraph::new();
+        for id in ids {
+            graph.add_node(id);
+        }
+        Ok(self.digraph_to_database(&graph))
+    }
+}
+
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((at, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '|' => Token::Union,
+            '&' => Token::Intersection,
+            '-' => Token::Difference,
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => break,
+                        },
+                        Some((_, end)) if end == c => break,
+                        Some((_, other)) => value.push(other),
+                        None => return Err(GraphrError::ParseError(format!("Unterminated string starting at {} in query '{}'", at, text))),
+                    }
+                }
+                Token::Text(value)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some((_, next)) = chars.peek().filter(|(_, n)| n.is_ascii_alphanumeric() || *n == '_') {
+                    name.push(*next);
+                    chars.next();
+                }
+                Token::Name(name)
+            }
+            other => return Err(GraphrError::ParseError(format!("Unexpected '{}' at {} in query '{}'", other, at, text))),
+        };
+        tokens.push((token, at));
+    }
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.tokens.get(self.position).map(|(token, _)| token.clone());
+        self.position += 1;
+        token.ok_or_else(|| GraphrError::ParseError("Unexpected end of the query".to_string()))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let at = self.tokens.get(self.position).map(|(_, at)| *at);
+        let token = self.next()?;
+        if token != expected {
+            return Err(GraphrError::ParseError(format!("Expected {} at {}, found {}", expected, at.unwrap_or_default(), token)))
+        }
+        Ok(())
+    }
+
+    /// Union and difference
+    fn expression(&mut self) -> Result<Query> {
+        let mut query = self.intersection()?;
+        while let Some(token @ (Token::Union | Token::Difference)) = self.peek().cloned() {
+            self.position += 1;
+            let right = Box::new(self.intersection()?);
+            query = if token == Token::Union { Query::Union(Box::new(query), right) } else { Query::Difference(Box::new(query), right) };
+        }
+        Ok(query)
+    }
+
+    fn intersection(&mut self) -> Result<Query> {
+        let mut query = self.term()?;
+        while self.peek() == Some(&Token::Intersection) {
+            self.position += 1;
+            query = Query::Intersection(Box::new(query), Box::new(self.term()?));
+        }
+        Ok(query)
+    }
+
+    /// Predicate or an expression in parentheses
+    fn term(&mut self) -> Result<Query> {
+        match self.next()? {
+            Token::Open => {
+                let query = self.expression()?;
+                self.expect(Token::Close)?;
+                Ok(query)
+            }
+            Token::Name(name) => {
+                let arguments = self.arguments()?;
+                predicate(&name, arguments)
+            }
+            token => Err(GraphrError::ParseError(format!("Expected a predicate or '(', found {}", token))),
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<String>> {
+        self.expect(Token::Open)?;
+        let mut arguments = Vec::new();
+        if self.peek() == Some(&Token::Close) {
+            self.position += 1;
+            return Ok(arguments)
+        }
+        loop {
+            match self.next()? {
+                Token::Text(value) => arguments.push(value),
+                token => return Err(GraphrError::ParseError(format!("Expected a quoted string, found {}", token))),
+            }
+            match self.next()? {
+                Token::Comma => continue,
+                Token::Close => return Ok(arguments),
+                token => return Err(GraphrError::ParseError(format!("Expected ',' or ')', found {}", token))),
+            }
+        }
+    }
+}
+
+fn predicate(name: &str, arguments: Vec<String>) -> Result<Query> {
+    let counts = match name {
+        "all" => 0..=0,
+        "id" | "template" | "versions" | "history" | "future" | "tag" => 1..=1,
+        "attr" => 1..=2,
+        _ => return Err(GraphrError::ParseError(format!("Unknown predicate '{}'", name))),
+    };
+    if !counts.contains(&arguments.len()) {
+        let expected = if counts.start() == counts.end() { counts.start().to_string() } else { format!("{} to {}", counts.start(), counts.end()) };
+        return Err(GraphrError::ParseError(format!("{}() takes {} argument(s), got {}", name, expected, arguments.len())))
+    }
+
+    let mut arguments = arguments.into_iter();
+    let mut argument = || arguments.next().expect("Number of arguments was checked");
+    let query = match name {
+        "all" => Query::All,
+        "id" => {
+            let pattern = argument();
+            Query::Id(Regex::new(&pattern).map_err(|e| GraphrError::ParseError(format!("Invalid regular expression '{}': {}", pattern, e)))?)
+        }
+        "template" => Query::Template(argument()),
+        "versions" => Query::Versions(argument()),
+        "history" => Query::History(argument()),
+        "future" => Query::Future(argument()),
+        "tag" => Query::Tag(argument()),
+        _ => {
+            let key = argument();
+            Query::Attribute(key, arguments.next())
+        }
+    };
+    Ok(query)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::Node;
+
+    /// Structure of the parsed query, operators written as functions
+    fn shape(text: &str) -> String {
+        fn write(query: &Query) -> String {
+            match query {
+                Query::Union(a, b) => format!("union({}, {})", write(a), write(b)),
+                Query::Intersection(a, b) => format!("intersection({}, {})", write(a), write(b)),
+                Query::Difference(a, b) => format!("difference({}, {})", write(a), write(b)),
+                Query::Template(name) => name.clone(),
+                other => format!("{:?}", other),
+            }
+        }
+        write(&Query::parse(text).unwrap())
+    }
+
+    fn error(text: &str) -> String {
+        match Query::parse(text) {
+            Err(GraphrError::ParseError(message)) => message,
+            other => panic!("Expected a parse error for '{}', got {:?}", text, other),
+        }
+    }
+
+    /// Calculations x0 -> A -> y -> B -> z
+    fn database() -> Database {
+        let mut db = Database::new();
+        for name in ["x", "y", "z"] {
+            db.template_register_dnode(name.to_string());
+        }
+        db.template_register_cnode("A".to_string(), "cp input(x) output(y)".to_string()).unwrap();
+        db.template_register_cnode("B".to_string(), "cp input(y) output(z)".to_string()).unwrap();
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), "x0".to_string())]), None, None, None).unwrap()
+    }
+
+    /// Templates of the selected nodes
+    fn select(db: &Database, text: &str) -> Vec<String> {
+        let ids = db.query_nodes(&Query::parse(text).unwrap()).unwrap();
+        let mut templates: Vec<String> = ids.iter().map(|id| match db.get(id.clone()).unwrap() {
+            Node::Calculation(c) => c.template,
+            Node::Data(d) => d.template,
+        }).collect();
+        templates.sort();
+        templates
+    }
+
+    #[test]
+    fn intersection_binds_tighter() {
+        assert_eq!(shape(r#"template("a") | template("b") & template("c")"#), "union(a, intersection(b, c))");
+        assert_eq!(shape(r#"template("a") & template("b") - template("c")"#), "difference(intersection(a, b), c)");
+    }
+
+    #[test]
+    fn same_precedence_is_left_to_right() {
+        assert_eq!(shape(r#"template("a") | template("b") - template("c")"#), "difference(union(a, b), c)");
+        assert_eq!(shape(r#"template("a") - template("b") | template("c")"#), "union(difference(a, b), c)");
+        assert_eq!(shape(r#"template("a") & template("b") & template("c")"#), "intersection(intersection(a, b), c)");
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(shape(r#"(template("a") | template("b")) & template("c")"#), "intersection(union(a, b), c)");
+        assert_eq!(shape(r#"template("a") - (template("b") - template("c"))"#), "difference(a, difference(b, c))");
+    }
+
+    #[test]
+    fn set_operations() {
+        let db = database();
+        assert_eq!(select(&db, "all()"), ["A", "B", "x", "y", "z"]);
+        assert_eq!(select(&db, r#"template("x") | template("A")"#), ["A", "x"]);
+        assert_eq!(select(&db, r#"future("x0") & template("z")"#), ["z"]);
+        assert_eq!(select(&db, r#"all() - template("x") & template("x")"#), ["A", "B", "y", "z"]);
+        assert_eq!(select(&db, r#"template("x") | template("y") - template("y")"#), ["x"]);
+        assert_eq!(select(&db, r#"id("^x0$") | tag("none")"#), ["x"]);
+    }
+
+    #[test]
+    fn predicates_check_their_arguments() {
+        assert_eq!(error("nothing()"), "Unknown predicate 'nothing'");
+        assert_eq!(error(r#"id("a", "b")"#), "id() takes 1 argument(s), got 2");
+        assert_eq!(error(r#"attr()"#), "attr() takes 1 to 2 argument(s), got 0");
+        assert!(error(r#"id("(")"#).starts_with("Invalid regular expression '('"));
+    }
+
+    #[test]
+    fn errors_tell_the_position() {
+        assert_eq!(error(r#"all() )"#), "Unexpected ')' at 6 in query 'all() )'");
+        assert_eq!(error(r#"all() # tag("a")"#), "Unexpected '#' at 6 in query 'all() # tag(\"a\")'");
+        assert_eq!(error(r#"tag("a) | all()"#), "Unterminated string starting at 4 in query 'tag(\"a) | all()'");
+        assert_eq!(error(r#"(all() | tag("a")"#), "Unexpected end of the query");
+        assert_eq!(error(r#"(all() all()"#), "Expected ')' at 7, found 'all'");
+        assert_eq!(error(r#"all() |"#), "Unexpected end of the query");
+        assert_eq!(error(r#"all() | , "#), "Expected a predicate or '(', found ','");
+    }
+}
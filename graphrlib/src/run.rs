@@ -0,0 +1,573 @@
+/*
+Local execution of calculations.
+Calculations are ordered topologically and every command is passed to a shell.
+Commands run in the working directory of the calling process, like the commands of get_command they are:
+data is found under the data folder as given (relative to that directory), and so are scripts the templates call.
+Independent calculations run in parallel on a pool of job slots; resource hints
+of the calculation templates decide how many of them fit at the same time.
+The outcome of every calculation is stored on the calculation node (CNode::run).
+*/
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use pyo3::prelude::*;
+use serde::{Serialize, Deserialize};
+use petgraph::visit::{Topo, Walker};
+use crate::{Database, IdC, IdD, Operation, Result};
+use crate::status::hash_path;
+
+
+/// What to do when a calculation fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Do not start any new calculations
+    #[default]
+    Stop,
+    /// Keep running everything that does not depend on the failed calculation
+    Continue,
+}
+
+/// Settings for running calculations
+#[derive(Clone, Debug)]
+pub struct RunOptions {
+    /// Folder that holds the data nodes. Prepended to all inputs and outputs, a relative folder is relative to
+    /// the working directory the commands run in (that of the calling process).
+    pub data_folder: String,
+    /// Folder where stdout and stderr of every calculation are stored.
+    pub log_folder: String,
+    pub policy: FailurePolicy,
+    /// Number of job slots. A calculation takes as many slots as it has cores.
+    pub jobs: usize,
+    /// Total memory in MB available to the calculations. None means no limit.
+    pub memory: Option<u64>,
+    /// Print a progress summary to stderr
+    pub progress: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            data_folder: "data".to_string(),
+            log_folder: ".graph/logs".to_string(),
+            policy: FailurePolicy::Stop,
+            jobs: 1,
+            memory: None,
+            progress: false,
+        }
+    }
+}
+
+/// Resources a calculation needs while running
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct Resources {
+    pub cores: u32,
+    /// Memory in MB
+    pub memory: Option<u64>,
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Resources { cores: 1, memory: None }
+    }
+}
+
+/// Outcome of a single execution of a calculation
+#[pyclass]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct RunRecord {
+    /// Exit code of the command. None if the process was killed by a signal.
+    #[pyo3(get)]
+    pub exit_status: Option<i32>,
+    /// Start and end time in nanoseconds since the unix epoch
+    #[pyo3(get)]
+    pub start: u64,
+    #[pyo3(get)]
+    pub end: u64,
+    /// Paths of the log files
+    #[pyo3(get)]
+    pub stdout: String,
+    #[pyo3(get)]
+    pub stderr: String,
+    /// Hashes of the inputs the calculation was run with
+    #[pyo3(get)]
+    #[serde(default)]
+    pub inputs: BTreeMap<IdD, String>,
+    /// Command of the template the calculation was run with
+    #[pyo3(get)]
+    #[serde(default)]
+    pub command: String,
+    /// User that ran the calculation
+    #[pyo3(get)]
+    #[serde(default)]
+    pub user: Option<String>,
+}
+
+#[pymethods]
+impl RunRecord {
+    #[getter]
+    pub fn success(&self) -> bool {
+        self.exit_status == Some(0)
+    }
+
+    fn __str__(&self) -> String {
+        format!("RunRecord(exit_status={:?}, start={}, end={})", self.exit_status, self.start, self.end)
+    }
+}
+
+/// Summary of a run over the database
+#[pyclass]
+#[derive(Default, Clone, Debug)]
+pub struct RunSummary {
+    #[pyo3(get)]
+    pub succeeded: Vec<IdC>,
+    #[pyo3(get)]
+    pub failed: Vec<IdC>,
+    /// Calculations that were not started because an upstream calculation failed
+    #[pyo3(get)]
+    pub skipped: Vec<IdC>,
+}
+
+#[pymethods]
+impl RunSummary {
+    fn __str__(&self) -> String {
+        format!("RunSummary(succeeded={}, failed={}, skipped={})", self.succeeded.len(), self.failed.len(), self.skipped.len())
+    }
+}
+
+
+pub(crate) fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Failed to get current system time.")
+        .as_nanos() as u64
+}
+
+/// Name of the user running graphr
+pub(crate) fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Format nanoseconds since the unix epoch as an ISO 8601 UTC date and time (2024-01-31T12:00:00Z)
+pub fn iso_timestamp(nanos: u64) -> String {
+    let seconds = nanos / 1_000_000_000;
+    let (days, time) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Convert days since the epoch to the civil date (proleptic gregorian calendar)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Run a single command through the shell. Output is redirected to the log folder.
+/// The command runs in the working directory of this process, the paths from get_command are relative to it.
+pub(crate) fn execute(cnode_id: &str, command: &str, options: &RunOptions) -> Result<RunRecord> {
+    std::fs::create_dir_all(&options.data_folder)?;
+    std::fs::create_dir_all(&options.log_folder)?;
+
+    let stdout = Path::new(&options.log_folder).join(format!("{}.stdout", cnode_id)).to_string_lossy().to_string();
+    let stderr = Path::new(&options.log_folder).join(format!("{}.stderr", cnode_id)).to_string_lossy().to_string();
+
+    let start = now_nanos();
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(std::env::current_dir()?)
+        .stdin(Stdio::null())
+        .stdout(File::create(&stdout)?)
+        .stderr(File::create(&stderr)?)
+        .status()?;
+    let end = now_nanos();
+
+    Ok(RunRecord { exit_status: status.code(), start, end, stdout, stderr, inputs: BTreeMap::new(), command: String::new(), user: Some(current_user()) })
+}
+
+/// Execute a job and hash its data: inputs before the run and outputs after a successful run.
+fn run_job(job: &Job, options: &RunOptions) -> Result<(RunRecord, BTreeMap<IdD, String>)> {
+    let hash = |location: &IdD| hash_path(&Path::new(&options.data_folder).join(location));
+
+    let mut inputs = BTreeMap::new();
+    for (d_id, location) in &job.inputs {
+        if let Some(h) = hash(location)? {
+            inputs.insert(d_id.clone(), h);
+        }
+    }
+
+    let mut record = execute(&job.id, &job.command, options)?;
+    record.inputs = inputs;
+    record.command = job.template_command.clone();
+
+    let mut outputs = BTreeMap::new();
+    if record.success() {
+        for (d_id, location) in &job.outputs {
+            if let Some(h) = hash(location)? {
+                outputs.insert(d_id.clone(), h);
+            }
+        }
+    }
+    Ok((record, outputs))
+}
+
+
+impl Database {
+
+    /// Calculations without a successful run, in the order they have to be executed.
+    pub fn pending_calculations(&self) -> Result<Vec<IdC>> {
+        let (graph, retrieval) = self.generate_digraph()?;
+
+        Ok(Topo::new(&graph)
+            .iter(&graph)
+            .map(|node| &retrieval[&node])
+            .filter(|id| self.cnodes.get(*id).is_some_and(|c| !c.run.as_ref().is_some_and(|r| r.success())))
+            .cloned()
+            .collect())
+    }
+
+    /// Calculations needed to produce the target that are out of date, in the order they have to be executed.
+    /// A calculation is out of date if it never ran successfully, its command template changed, its inputs or outputs
+    /// differ from what was recorded at the last run, or a calculation upstream of it is out of date.
+    pub fn outdated_calculations(&self, target: &str, data_folder: &str) -> Result<Vec<IdC>> {
+        let history = self.select_node_history(target.to_string())?;
+        let mut outdated: Vec<IdC> = Vec::new();
+
+        for node in Topo::new(&history).iter(&history) {
+            let Some(cnode) = self.cnodes.get(&history[node]) else { continue };
+
+            let upstream = cnode.inputs().any(|d_id| {
+                self.cnodes.values().any(|c| c.produces(d_id) && outdated.contains(&c.id))
+            });
+            let changed = match cnode.run.as_ref().filter(|r| r.success()) {
+                None => true,
+                Some(run) => {
+                    let mut changed = self.get_template_cnode(&cnode.template).is_none_or(|t| t.command != run.command);
+                    for d_id in cnode.inputs() {
+                        changed |= self.current_hash(d_id, data_folder)?.as_ref() != run.inputs.get(d_id);
+                    }
+                    for d_id in cnode.outputs() {
+                        let current = self.current_hash(d_id, data_folder)?;
+                        changed |= current.is_none() || current != self.dnodes[d_id].hash;
+                    }
+                    changed
+                }
+            };
+
+            if upstream || changed {
+                outdated.push(cnode.id.clone());
+            }
+        }
+
+        Ok(outdated)
+    }
+
+    /// Execute all pending calculations.
+    pub fn run_calculations(&mut self, options: &RunOptions) -> Result<RunSummary> {
+        let pending = self.pending_calculations()?;
+        self.execute_calculations(pending, options)
+    }
+
+    /// Bring the target up to date, re-running only the calculations that are out of date.
+    pub fn update_target(&mut self, target: &str, options: &RunOptions) -> Result<RunSummary> {
+        let outdated = self.outdated_calculations(target, &options.data_folder)?;
+        self.execute_calculations(outdated, options)
+    }
+
+    /// Jobs for the given calculations, in the same order.
+    /// Data modified in place is overwritten, so the calculation that modifies a revision also has to wait
+    /// for the pending calculations that read it.
+    fn jobs(&self, pending: &[IdC], data_folder: &str) -> Result<Vec<Job>> {
+        let pending_set: HashSet<&IdC> = pending.iter().collect();
+        let producers: HashMap<&IdD, &IdC> = self.cnodes.values()
+            .filter(|c| pending_set.contains(&c.id))
+            .flat_map(|c| c.outputs().map(move |d_id| (d_id, &c.id)))
+            .collect();
+        let mut readers: HashMap<&IdD, Vec<&IdC>> = HashMap::new();
+        for cnode in self.cnodes.values().filter(|c| pending_set.contains(&c.id)) {
+            for d_id in &cnode.incoming {
+                readers.entry(d_id).or_default().push(&cnode.id);
+            }
+        }
+
+        let mut jobs = Vec::new();
+        for cnode_id in pending {
+            let cnode = &self.cnodes[cnode_id];
+            let mut dependencies: Vec<IdC> = cnode.inputs()
+                .filter_map(|d_id| producers.get(d_id).map(|c_id| (*c_id).clone()))
+                .collect();
+            dependencies.sort();
+            dependencies.dedup();
+            let mut after: Vec<IdC> = cnode.modified.iter()
+                .flat_map(|(from, _)| readers.get(from).into_iter().flatten())
+                .filter(|c_id| **c_id != &cnode.id)
+                .map(|c_id| (*c_id).clone())
+                .collect();
+            after.sort();
+            after.dedup();
+            jobs.push(Job {
+                id: cnode_id.clone(),
+                command: self.get_command(cnode_id.clone(), data_folder.to_string())?,
+                template_command: self.get_template_cnode(&cnode.template).map(|t| t.command.clone()).unwrap_or_default(),
+                resources: self.get_template_cnode(&cnode.template).map(|t| t.resources.clone()).unwrap_or_default(),
+                inputs: cnode.inputs().map(|d_id| (d_id.clone(), self.dnodes[d_id].location().clone())).collect(),
+                outputs: cnode.outputs().map(|d_id| (d_id.clone(), self.dnodes[d_id].location().clone())).collect(),
+                dependencies,
+                after,
+            });
+        }
+        Ok(jobs)
+    }
+
+    /// Execute the given calculations (in topological order).
+    /// A calculation is started as soon as all of its inputs are produced, the calculations reading the data it
+    /// modifies are done and there are enough free job slots and memory.
+    /// Every calculation gets its run record updated as soon as it finishes.
+    fn execute_calculations(&mut self, pending: Vec<IdC>, options: &RunOptions) -> Result<RunSummary> {
+        let mut summary = RunSummary::default();
+        let slots = options.jobs.max(1);
+
+        // Collect everything the workers need, so that the database can be updated while they run.
+        let mut waiting = self.jobs(&pending, &options.data_folder)?;
+        let total = waiting.len();
+
+        let (sender, receiver) = mpsc::channel();
+        let mut running: HashMap<IdC, Resources> = HashMap::new();
+        let mut error = None;
+        // Everything that changes in the database, for the journal
+        let mut runs = BTreeMap::new();
+        let mut hashes = BTreeMap::new();
+
+        thread::scope(|scope| {
+            loop {
+                let stopped = error.is_some() || (!summary.failed.is_empty() && options.policy == FailurePolicy::Stop);
+
+                // Waiting list is in topological order, so skipping propagates downstream in a single pass.
+                let mut i = 0;
+                while i < waiting.len() {
+                    let job = &waiting[i];
+                    if job.dependencies.iter().any(|c_id| summary.failed.contains(c_id) || summary.skipped.contains(c_id)) {
+                        summary.skipped.push(waiting.remove(i).id);
+                        continue
+                    }
+                    let ready = job.dependencies.iter().all(|c_id| summary.succeeded.contains(c_id)) &&
+                        job.after.iter().all(|c_id| !running.contains_key(c_id) && !waiting.iter().any(|j| j.id == *c_id));
+                    if !stopped && ready && fits(&job.resources, &running, slots, options.memory) {
+                        let job = waiting.remove(i);
+                        let sender = sender.clone();
+                        running.insert(job.id.clone(), job.resources.clone());
+                        scope.spawn(move || {
+                            let result = run_job(&job, options);
+                            sender.send((job.id, result)).expect("Scheduler stopped listening");
+                        });
+                        continue
+                    }
+                    i += 1;
+                }
+
+                if running.is_empty() {
+                    break
+                }
+
+                let (cnode_id, result) = receiver.recv().expect("Worker disappeared during the run");
+                running.remove(&cnode_id);
+                match result {
+                    Ok((record, outputs)) => {
+                        if record.success() {
+                            summary.succeeded.push(cnode_id.clone());
+                            // Produced data gets its hash, root inputs are registered with the content they were used with
+                            for (d_id, hash) in outputs {
+                                self.dnodes.get_mut(&d_id).expect("Data node disappeared during the run").hash = Some(hash.clone());
+                                hashes.insert(d_id, Some(hash));
+                            }
+                            for (d_id, hash) in &record.inputs {
+                                if !self.cnodes.values().any(|c| c.produces(d_id)) {
+                                    self.dnodes.get_mut(d_id).expect("Data node disappeared during the run").hash = Some(hash.clone());
+                                    hashes.insert(d_id.clone(), Some(hash.clone()));
+                                }
+                            }
+                        } else {
+                            summary.failed.push(cnode_id.clone());
+                        }
+                        self.cnodes.get_mut(&cnode_id).expect("Calculation disappeared during the run").run = Some(record.clone());
+                        runs.insert(cnode_id.clone(), Some(record));
+                    }
+                    Err(e) => {
+                        summary.failed.push(cnode_id);
+                        error.get_or_insert(e);
+                    }
+                }
+
+                if options.progress {
+                    let done = summary.succeeded.len() + summary.failed.len();
+                    eprint!("\r[{}/{}] running: {}, failed: {}", done, total, running.len(), summary.failed.len());
+                    let _ = std::io::stderr().flush();
+                }
+            }
+        });
+
+        if options.progress && total > 0 {
+            eprintln!();
+        }
+
+        // Whatever could not be started (stopped after a failure)
+        summary.skipped.extend(waiting.into_iter().map(|job| job.id));
+        if !runs.is_empty() {
+            self.record(Operation::Record { runs, hashes });
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(summary),
+        }
+    }
+}
+
+/// Calculation waiting to be executed
+struct Job {
+    id: IdC,
+    command: String,
+    template_command: String,
+    resources: Resources,
+    /// Data nodes with their location in the data folder
+    inputs: Vec<(IdD, IdD)>,
+    outputs: Vec<(IdD, IdD)>,
+    /// Pending calculations that produce the inputs of this one
+    dependencies: Vec<IdC>,
+    /// Pending calculations that read data this one modifies in place. They only have to be done, not successful.
+    after: Vec<IdC>,
+}
+
+/// Check whether a calculation can start next to the ones that are already running.
+/// A calculation that asks for more than there is in total still runs, but alone.
+fn fits(resources: &Resources, running: &HashMap<IdC, Resources>, slots: usize, memory: Option<u64>) -> bool {
+    if running.is_empty() {
+        return true
+    }
+    let cores = |r: &Resources| (r.cores.max(1) as usize).min(slots);
+    let used_cores: usize = running.values().map(cores).sum();
+    if used_cores + cores(resources) > slots {
+        return false
+    }
+    match memory {
+        Some(limit) => {
+            let used_memory: u64 = running.values().filter_map(|r| r.memory).sum();
+            used_memory + resources.memory.unwrap_or(0) <= limit
+        }
+        None => true,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Database with the calculations of the templates (name, command) for the leaf x
+    fn database(data: &[&str], templates: &[(&str, &str)]) -> Database {
+        let mut db = Database::new();
+        for name in data {
+            db.template_register_dnode(name.to_string());
+        }
+        for (name, command) in templates {
+            db.template_register_cnode(name.to_string(), command.to_string()).unwrap();
+        }
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), "x0".to_string())]), None, None, None).unwrap()
+    }
+
+    fn job<'a>(db: &Database, jobs: &'a [Job], template: &str) -> &'a Job {
+        let cnode = db.cnodes.values().find(|c| c.template == template).unwrap();
+        jobs.iter().find(|j| j.id == cnode.id).unwrap()
+    }
+
+    fn id(db: &Database, template: &str) -> IdC {
+        db.cnodes.values().find(|c| c.template == template).unwrap().id.clone()
+    }
+
+    #[test]
+    fn jobs_wait_for_the_producers_of_their_inputs() {
+        let db = database(&["x", "y", "z"], &[("A", "cp input(x) output(y)"), ("B", "cp input(y) output(z)")]);
+        let pending = db.pending_calculations().unwrap();
+        assert_eq!(pending, vec![id(&db, "A"), id(&db, "B")]);
+
+        let jobs = db.jobs(&pending, "data").unwrap();
+        assert!(job(&db, &jobs, "A").dependencies.is_empty());
+        assert_eq!(job(&db, &jobs, "B").dependencies, vec![id(&db, "A")]);
+    }
+
+    /// R reads x0 (slowly) and M, which does not depend on R, makes a new revision of it
+    fn reader_and_modifier() -> Database {
+        database(&["x", "y"], &[("R", "sleep 0.2; cp input(x) output(y)"), ("M", "echo new > modify(x)")])
+    }
+
+    #[test]
+    fn modifying_waits_for_the_readers_of_the_revision() {
+        let db = reader_and_modifier();
+        let jobs = db.jobs(&db.pending_calculations().unwrap(), "data").unwrap();
+        let reader = job(&db, &jobs, "R");
+        assert!(reader.dependencies.is_empty() && reader.after.is_empty());
+        let modifier = job(&db, &jobs, "M");
+        assert!(modifier.dependencies.is_empty());
+        assert_eq!(modifier.after, vec![id(&db, "R")]);
+    }
+
+    #[test]
+    fn finished_readers_do_not_hold_back_the_modifier() {
+        let db = reader_and_modifier();
+        let jobs = db.jobs(&[id(&db, "M")], "data").unwrap();
+        assert!(jobs[0].after.is_empty());
+    }
+
+    #[test]
+    fn parallel_run_reads_the_revision_before_it_is_modified() {
+        let folder = std::env::temp_dir().join(format!("graphr-run-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("x0"), "old\n").unwrap();
+
+        let mut db = reader_and_modifier();
+        let reader = id(&db, "R");
+        let options = RunOptions {
+            data_folder: folder.to_string_lossy().to_string(),
+            log_folder: folder.join("logs").to_string_lossy().to_string(),
+            jobs: 2,
+            ..RunOptions::default()
+        };
+        let summary = db.run_calculations(&options).unwrap();
+        let output = db.cnodes[&reader].outcoming[0].clone();
+        let read = std::fs::read_to_string(folder.join(output)).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(summary.succeeded.len(), 2);
+        assert_eq!(summary.succeeded[0], reader);
+        assert_eq!(read, "old\n");
+    }
+
+    #[test]
+    fn commands_run_where_the_relative_paths_point() {
+        let folder = format!("graphr-cwd-{}", std::process::id());
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(format!("{}/x0", folder), "data\n").unwrap();
+
+        let mut db = database(&["x", "y"], &[("P", "cat input(x) > output(y); pwd >> output(y)")]);
+        let options = RunOptions { data_folder: folder.clone(), log_folder: format!("{}/logs", folder), ..RunOptions::default() };
+        let summary = db.run_calculations(&options).unwrap();
+        let output = db.cnodes[&id(&db, "P")].outcoming[0].clone();
+        let written = std::fs::read_to_string(format!("{}/{}", folder, output)).unwrap();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(summary.succeeded.len(), 1);
+        assert_eq!(written, format!("data\n{}\n", std::env::current_dir().unwrap().display()));
+    }
+}
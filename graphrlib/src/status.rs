@@ -0,0 +1,166 @@
+/*
+Content hashes of the data nodes and the status of the database against the data folder.
+A data node hash is recorded when the node is produced by a calculation or registered by hand.
+Every run additionally records the hashes of its inputs, so that a change upstream can be detected.
+*/
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use pyo3::prelude::*;
+use sha2::{Digest, Sha256};
+use petgraph::visit::{Topo, Walker};
+use crate::{Database, GraphrError, IdD, Operation, Result};
+
+
+/// State of a node compared to the data folder
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeStatus {
+    /// Data does not exist. For calculations: never run successfully.
+    Missing,
+    UpToDate,
+    /// Data differs from the recorded hash (or no hash was recorded)
+    Modified,
+    /// Data is unchanged, but something upstream changed since it was produced
+    Stale,
+}
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NodeStatus::Missing => "missing",
+            NodeStatus::UpToDate => "up-to-date",
+            NodeStatus::Modified => "modified",
+            NodeStatus::Stale => "stale",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[pymethods]
+impl NodeStatus {
+    fn __str__(&self) -> String {
+        self.to_string()
+    }
+}
+
+
+/// Hash a file or a directory (recursively). None if the path does not exist.
+pub(crate) fn hash_path(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None)
+    }
+    let mut hasher = Sha256::new();
+    update_hash(&mut hasher, path)?;
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// Feed a path into the hasher. Directory entries are sorted, so the hash does not depend on the file system.
+fn update_hash(hasher: &mut Sha256, path: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        hasher.update(b"dir");
+        hasher.update((entries.len() as u64).to_le_bytes());
+        for entry in entries {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy().to_string();
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name.as_bytes());
+            update_hash(hasher, &entry)?;
+        }
+    } else {
+        hasher.update(b"file");
+        hasher.update(fs::metadata(path)?.len().to_le_bytes());
+        io::copy(&mut File::open(path)?, hasher)?;
+    }
+    Ok(())
+}
+
+
+impl Database {
+
+    /// A revision that was modified in place by a successful calculation is no longer on disk.
+    pub(crate) fn superseded(&self, d_id: &IdD) -> bool {
+        self.cnodes.values().any(|c| {
+            c.modified.iter().any(|(from_id, _)| from_id == d_id) && c.run.as_ref().is_some_and(|r| r.success())
+        })
+    }
+
+    /// Hash of the data node as it is now. Superseded revisions can only be known from the record.
+    pub(crate) fn current_hash(&self, d_id: &IdD, data_folder: &str) -> Result<Option<String>> {
+        let dnode = self.dnodes.get(d_id).ok_or(GraphrError::UnknownNode(d_id.clone()))?;
+        if self.superseded(d_id) {
+            return Ok(dnode.hash.clone())
+        }
+        hash_path(&Path::new(data_folder).join(dnode.location()))
+    }
+
+    /// Record the current hashes of the named data nodes (all data nodes if None).
+    /// Nodes that do not exist in the data folder and superseded revisions are left untouched.
+    pub fn record_hashes(&mut self, names: Option<Vec<String>>, data_folder: &str) -> Result<()> {
+        let names = names.unwrap_or_else(|| self.dnodes.keys().cloned().collect());
+        let mut hashes = BTreeMap::new();
+
+        for name in names {
+            if self.superseded(&name) {
+                continue
+            }
+            let hash = self.current_hash(&name, data_folder)?;
+            if let Some(hash) = hash {
+                self.dnodes.get_mut(&name).expect("Data node disappeared").hash = Some(hash.clone());
+                hashes.insert(name, Some(hash));
+            }
+        }
+        if !hashes.is_empty() {
+            self.record(Operation::Record { runs: BTreeMap::new(), hashes });
+        }
+        Ok(())
+    }
+
+    /// Classify every node of the database against the data folder.
+    pub fn node_status(&self, data_folder: &str) -> Result<BTreeMap<String, NodeStatus>> {
+        let (graph, retrieval) = self.generate_digraph()?;
+        let mut status = BTreeMap::new();
+        let producers: HashMap<&IdD, &String> = self.cnodes.values()
+            .flat_map(|c| c.outputs().map(move |d_id| (d_id, &c.id)))
+            .collect();
+
+        // Topological order guarantees that everything upstream is already classified
+        for node in Topo::new(&graph).iter(&graph) {
+            let id = &retrieval[&node];
+
+            let node_status = if let Some(dnode) = self.dnodes.get(id) {
+                let hash = self.current_hash(id, data_folder)?;
+                match (hash, &dnode.hash) {
+                    (None, _) => NodeStatus::Missing,
+                    (Some(hash), Some(recorded)) if hash == *recorded => {
+                        match producers.get(id) {
+                            Some(c_id) if status[*c_id] != NodeStatus::UpToDate => NodeStatus::Stale,
+                            _ => NodeStatus::UpToDate,
+                        }
+                    }
+                    _ => NodeStatus::Modified,
+                }
+            } else {
+                let cnode = &self.cnodes[id];
+                match cnode.run.as_ref().filter(|r| r.success()) {
+                    None => NodeStatus::Missing,
+                    Some(run) => {
+                        let changed = cnode.inputs().any(|d_id| {
+                            status[d_id] != NodeStatus::UpToDate || run.inputs.get(d_id) != self.dnodes[d_id].hash.as_ref()
+                        });
+                        if changed { NodeStatus::Stale } else { NodeStatus::UpToDate }
+                    }
+                }
+            };
+            status.insert(id.clone(), node_status);
+        }
+
+        Ok(status)
+    }
+}
@@ -0,0 +1,575 @@
+/*
+Storage backends for the database.
+JsonStorage keeps the whole database in a single json file (the original format).
+SqliteStorage keeps every node in its own row of an sqlite file, so that single nodes can be
+looked up without reading everything and a merge only writes the nodes that changed.
+The backend is picked from the file extension (see open_storage).
+
+Several processes may work on the same database. Every write remembers which version of the
+stored database it was based on; if somebody else wrote in the meantime, the operations done on the
+database are applied to the new version (see journal.rs) instead of overwriting it.
+Every write also appends the operations that were done to the journal next to the file (see journal.rs).
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
+use std::time::Duration;
+use rusqlite::{params, Connection, Params, TransactionBehavior};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use crate::journal::{append_journal, journal_path};
+use crate::versions::template_name;
+use crate::{CNode, CNodeTemplate, DNode, DNodeTemplate, Database, DatabaseTemplate, JournalEntry, Node, Operation, Result};
+
+
+/// Place where a database is kept between calls
+pub trait Storage {
+    /// Read the whole database. An empty database if nothing is stored yet.
+    fn load(&self) -> Result<Database>;
+
+    /// Replace the stored database and append the operations done on db to the journal.
+    /// If the stored database changed since db was loaded, the operations done on db are applied to it instead
+    /// and db becomes the result, so that it can be saved again. The journal is only appended once the database is written.
+    fn save(&mut self, db: &mut Database) -> Result<()>;
+
+    /// Look up a single node
+    fn get(&self, id: &str) -> Result<Option<Node>>;
+
+    /// Read the template only
+    fn template(&self) -> Result<DatabaseTemplate>;
+
+    /// The node with the data it reads and writes and the template. Enough to show the node and build its command.
+    fn neighbourhood(&self, id: &str) -> Result<Option<Database>> {
+        let Some(node) = self.get(id)? else { return Ok(None) };
+        let mut db = Database::new();
+        db.template = self.template()?;
+        match node {
+            Node::Calculation(cnode) => {
+                for d_id in cnode.inputs().chain(cnode.outputs()) {
+                    if let Some(Node::Data(dnode)) = self.get(d_id)? {
+                        db.dnodes.insert(dnode.id.clone(), dnode);
+                    }
+                }
+                db.cnodes.insert(cnode.id.clone(), cnode);
+            }
+            Node::Data(dnode) => {
+                db.dnodes.insert(dnode.id.clone(), dnode);
+            }
+        }
+        Ok(Some(db))
+    }
+
+    /// Insert the nodes of the database, replacing stored nodes with the same id
+    fn insert(&mut self, db: &Database) -> Result<()>;
+
+    /// Merge the database into the stored one (see Database::merge_into).
+    /// The given database is renamed to the ids used by the storage.
+    fn merge(&mut self, db: &mut Database) -> Result<()>;
+}
+
+/// Open the storage at the path. Files ending with .sqlite, .sqlite3 or .db use sqlite, everything else json.
+pub fn open_storage(path: &str) -> Result<Box<dyn Storage>> {
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("sqlite" | "sqlite3" | "db") => Ok(Box::new(SqliteStorage::open(path)?)),
+        _ => Ok(Box::new(JsonStorage::new(path))),
+    }
+}
+
+
+/// The whole database in a single json file.
+/// Files are replaced atomically, so reading needs no lock. Writers take an exclusive
+/// lock on <path>.lock for the whole read-modify-write cycle.
+pub struct JsonStorage {
+    path: String,
+}
+
+impl JsonStorage {
+    pub fn new(path: &str) -> Self {
+        JsonStorage { path: path.to_string() }
+    }
+
+    /// Wait for the exclusive lock. Released when the returned file is dropped.
+    fn lock(&self) -> Result<File> {
+        if let Some(parent) = Path::new(&self.path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(format!("{}.lock", self.path))?;
+        file.lock()?;
+        Ok(file)
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> Result<Database> {
+        if !Path::new(&self.path).exists() {
+            return Ok(Database::new())
+        }
+        let content = fs::read(&self.path)?;
+        let mut db: Database = serde_json::from_slice(&content)?;
+        db.loaded_version = Some(format!("{:x}", Sha256::digest(&content)));
+        Ok(db)
+    }
+
+    fn save(&mut self, db: &mut Database) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut stored = self.load()?;
+
+        if stored.loaded_version.is_some() && stored.loaded_version != db.loaded_version {
+            // Changed since db was read: keep the changes of both
+            let before = stored.clone();
+            stored.apply_changes(db)?;
+            stored.write_file(&self.path)?;
+            append_journal(&journal_path(&self.path), &before, &stored.journal)?;
+            *db = stored;
+            db.loaded_version = self.load()?.loaded_version;
+        } else {
+            db.write_file(&self.path)?;
+            append_journal(&journal_path(&self.path), &stored, &db.journal)?;
+            db.loaded_version = self.load()?.loaded_version;
+        }
+        db.journal.clear();
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Node>> {
+        Ok(self.load()?.get(id.to_string()))
+    }
+
+    fn template(&self) -> Result<DatabaseTemplate> {
+        Ok(self.load()?.template)
+    }
+
+    fn insert(&mut self, db: &Database) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut stored = self.load()?;
+        let before = stored.clone();
+        stored.insert_nodes(db);
+        stored.write_file(&self.path)?;
+        append_journal(&journal_path(&self.path), &before, &stored.journal)
+    }
+
+    fn merge(&mut self, db: &mut Database) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut stored = self.load()?;
+        let before = stored.clone();
+        db.merge_into(&mut stored)?;
+        stored.write_file(&self.path)?;
+        append_journal(&journal_path(&self.path), &before, &stored.journal)
+    }
+}
+
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS template_dnodes (id TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS template_cnodes (id TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS template_versions (id TEXT PRIMARY KEY, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS dnodes (id TEXT PRIMARY KEY, template TEXT NOT NULL, data TEXT NOT NULL);
+    CREATE TABLE IF NOT EXISTS cnodes (id TEXT PRIMARY KEY, template TEXT NOT NULL, data TEXT NOT NULL);
+    CREATE INDEX IF NOT EXISTS cnodes_template ON cnodes (template);
+    CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+";
+
+/// Every node in its own row of an sqlite file. Nodes are stored as json.
+pub struct SqliteStorage {
+    conn: Connection,
+    path: String,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        // Other writers hold the database only for the duration of a transaction
+        conn.busy_timeout(Duration::from_secs(60))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStorage { conn, path: path.to_string() })
+    }
+}
+
+/// Version of the stored database. Increased by every write.
+fn read_version(conn: &Connection) -> Result<Option<String>> {
+    Ok(read_rows::<u64>(conn, "SELECT value FROM meta WHERE key = 'version'", [])?.pop().map(|v| v.to_string()))
+}
+
+fn bump_version(conn: &Connection) -> Result<()> {
+    conn.execute("INSERT INTO meta (key, value) VALUES ('version', '1')
+                  ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + 1", [])?;
+    Ok(())
+}
+
+fn read_all(conn: &Connection) -> Result<Database> {
+    let mut db = Database::new();
+    db.template = read_template(conn)?;
+    for dnode in read_rows::<DNode>(conn, "SELECT data FROM dnodes", [])? {
+        db.dnodes.insert(dnode.id.clone(), dnode);
+    }
+    for cnode in read_rows::<CNode>(conn, "SELECT data FROM cnodes", [])? {
+        db.cnodes.insert(cnode.id.clone(), cnode);
+    }
+    db.loaded_version = read_version(conn)?;
+    Ok(db)
+}
+
+/// Read json rows returned by the query (the first column holds the data)
+fn read_rows<T: DeserializeOwned>(conn: &Connection, sql: &str, params: impl Params) -> Result<Vec<T>> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, |row| row.get::<_, String>(0))?;
+    rows.map(|data| Ok(serde_json::from_str(&data?)?)).collect()
+}
+
+fn read_template(conn: &Connection) -> Result<DatabaseTemplate> {
+    let mut template = DatabaseTemplate::new();
+    for dnode in read_rows::<DNodeTemplate>(conn, "SELECT data FROM template_dnodes", [])? {
+        template.dnodes.insert(dnode.id.clone(), dnode);
+    }
+    for cnode in read_rows::<CNodeTemplate>(conn, "SELECT data FROM template_cnodes", [])? {
+        template.cnodes.insert(cnode.id.clone(), cnode);
+    }
+    // A merge only writes rows, an earlier version that became current again is left behind
+    for cnode in read_rows::<CNodeTemplate>(conn, "SELECT data FROM template_versions", [])? {
+        if template.find_version(cnode.name(), &cnode.version()).is_none() {
+            template.versions.insert(cnode.id.clone(), cnode);
+        }
+    }
+    Ok(template)
+}
+
+/// Insert or replace all nodes of the database
+fn write_rows(conn: &Connection, db: &Database) -> Result<()> {
+    fn write<T: Serialize>(conn: &Connection, sql: &str, id: &str, template: Option<&str>, node: &T) -> Result<()> {
+        let data = serde_json::to_string(node)?;
+        match template {
+            Some(template) => conn.prepare_cached(sql)?.execute(params![id, template, data])?,
+            None => conn.prepare_cached(sql)?.execute(params![id, data])?,
+        };
+        Ok(())
+    }
+
+    for (id, node) in &db.template.dnodes {
+        write(conn, "INSERT OR REPLACE INTO template_dnodes (id, data) VALUES (?1, ?2)", id, None, node)?;
+    }
+    for (id, node) in &db.template.cnodes {
+        write(conn, "INSERT OR REPLACE INTO template_cnodes (id, data) VALUES (?1, ?2)", id, None, node)?;
+    }
+    for (id, node) in &db.template.versions {
+        write(conn, "INSERT OR REPLACE INTO template_versions (id, data) VALUES (?1, ?2)", id, None, node)?;
+    }
+    for (id, node) in &db.dnodes {
+        write(conn, "INSERT OR REPLACE INTO dnodes (id, template, data) VALUES (?1, ?2, ?3)", id, Some(&node.template), node)?;
+    }
+    for (id, node) in &db.cnodes {
+        write(conn, "INSERT OR REPLACE INTO cnodes (id, template, data) VALUES (?1, ?2, ?3)", id, Some(&node.template), node)?;
+    }
+    Ok(())
+}
+
+/// Delete the rows of the nodes that are in before, but no longer in the database
+fn delete_rows(conn: &Connection, db: &Database, before: &Database) -> Result<()> {
+    fn delete<T>(conn: &Connection, table: &str, now: &BTreeMap<String, T>, before: &BTreeMap<String, T>) -> Result<()> {
+        let mut stmt = conn.prepare_cached(&format!("DELETE FROM {} WHERE id = ?1", table))?;
+        for id in before.keys().filter(|id| !now.contains_key(*id)) {
+            stmt.execute([id])?;
+        }
+        Ok(())
+    }
+
+    delete(conn, "template_dnodes", &db.template.dnodes, &before.template.dnodes)?;
+    delete(conn, "template_cnodes", &db.template.cnodes, &before.template.cnodes)?;
+    delete(conn, "template_versions", &db.template.versions, &before.template.versions)?;
+    delete(conn, "dnodes", &db.dnodes, &before.dnodes)?;
+    delete(conn, "cnodes", &db.cnodes, &before.cnodes)
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> Result<Database> {
+        // A single transaction, so that the version matches what was read
+        let tx = self.conn.unchecked_transaction()?;
+        let db = read_all(&tx)?;
+        tx.commit()?;
+        Ok(db)
+    }
+
+    fn save(&mut self, db: &mut Database) -> Result<()> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version = read_version(&tx)?;
+        let journal = journal_path(&self.path);
+        let conflict = version.is_some() && version != db.loaded_version;
+        // Only the rows that differ from the stored database are written
+        let before = read_all(&tx)?;
+
+        let mut stored = before.clone();
+        let written = if conflict {
+            // Changed since db was read: keep the changes of both
+            stored.apply_changes(db)?;
+            &stored
+        } else {
+            &*db
+        };
+        write_rows(&tx, &written.changed_since(&before)?)?;
+        delete_rows(&tx, written, &before)?;
+        bump_version(&tx)?;
+        let version = read_version(&tx)?;
+        tx.commit()?;
+        // Only journal what was committed
+        append_journal(&journal, &before, &written.journal)?;
+
+        if conflict {
+            *db = stored;
+        }
+        db.loaded_version = version;
+        db.journal.clear();
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Node>> {
+        if let Some(cnode) = read_rows(&self.conn, "SELECT data FROM cnodes WHERE id = ?1", [id])?.pop() {
+            return Ok(Some(Node::Calculation(cnode)))
+        }
+        Ok(read_rows(&self.conn, "SELECT data FROM dnodes WHERE id = ?1", [id])?.pop().map(Node::Data))
+    }
+
+    fn template(&self) -> Result<DatabaseTemplate> {
+        read_template(&self.conn)
+    }
+
+    fn insert(&mut self, db: &Database) -> Result<()> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let journal = journal_path(&self.path);
+        let before = if Path::new(&journal).exists() { Database::new() } else { read_all(&tx)? };
+        write_rows(&tx, db)?;
+        bump_version(&tx)?;
+        tx.commit()?;
+        append_journal(&journal, &before, &[JournalEntry::new(Operation::Insert { database: db.clone() })])
+    }
+
+    fn merge(&mut self, db: &mut Database) -> Result<()> {
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Only load the part of the stored database the merge can match against:
+        // calculations of the same templates (in any version, a new version moves them) and the data nodes that are referred to.
+        let mut stored = Database::new();
+        stored.template = read_template(&tx)?;
+        let mut templates: BTreeSet<&str> = db.cnodes.values().map(|c| template_name(&c.template)).collect();
+        templates.extend(db.template.cnodes.values()
+            .filter(|c| stored.template.find_version(c.name(), &c.version()).as_deref() != Some(c.name()))
+            .map(|c| c.name()));
+        for template in templates {
+            let sql = "SELECT data FROM cnodes WHERE template = ?1 OR substr(template, 1, length(?1) + 1) = ?1 || '@'";
+            for cnode in read_rows::<CNode>(&tx, sql, [template])? {
+                stored.cnodes.insert(cnode.id.clone(), cnode);
+            }
+        }
+        let mut d_ids: BTreeSet<&String> = db.dnodes.keys().collect();
+        d_ids.extend(stored.cnodes.values().flat_map(|c| c.inputs().chain(c.outputs())));
+        let mut dnodes = BTreeMap::new();
+        for d_id in d_ids {
+            for dnode in read_rows::<DNode>(&tx, "SELECT data FROM dnodes WHERE id = ?1", [d_id])? {
+                dnodes.insert(dnode.id.clone(), dnode);
+            }
+        }
+        stored.dnodes = dnodes;
+
+        let journal = journal_path(&self.path);
+        let snapshot = if Path::new(&journal).exists() { Database::new() } else { read_all(&tx)? };
+        let before = stored.clone();
+        db.merge_into(&mut stored)?;
+        write_rows(&tx, &stored.changed_since(&before)?)?;
+        bump_version(&tx)?;
+        tx.commit()?;
+        append_journal(&journal, &snapshot, &stored.journal)
+    }
+}
+
+
+impl Database {
+
+    /// Bring the changes done on db into this newer version of the stored database.
+    /// A database that was read from the storage has its operations applied again, so that deleting and replacing
+    /// work as well as adding. A database that was never stored is merged, as there is nothing it could replace.
+    fn apply_changes(&mut self, db: &Database) -> Result<()> {
+        if db.loaded_version.is_some() {
+            return self.apply_journal(&db.journal)
+        }
+        let mut incoming = db.clone();
+        incoming.journal.clear();
+        incoming.merge_into(self)
+    }
+
+    /// Copy the template and the nodes of the other database into this one (overwriting nodes with the same id)
+    pub(crate) fn insert_nodes(&mut self, other: &Database) {
+        self.template.dnodes.extend(other.template.dnodes.clone());
+        self.template.cnodes.extend(other.template.cnodes.clone());
+        self.template.versions.extend(other.template.versions.clone());
+        self.dnodes.extend(other.dnodes.clone());
+        self.cnodes.extend(other.cnodes.clone());
+        self.record(Operation::Insert { database: other.clone() });
+    }
+
+    /// Nodes that are new or differ from the earlier state of the database
+    fn changed_since(&self, before: &Database) -> Result<Database> {
+        fn changed<T: Serialize + Clone>(now: &BTreeMap<String, T>, before: &BTreeMap<String, T>) -> Result<BTreeMap<String, T>> {
+            let mut result = BTreeMap::new();
+            for (id, node) in now {
+                let differs = match before.get(id) {
+                    Some(old) => serde_json::to_string(old)? != serde_json::to_string(node)?,
+                    None => true,
+                };
+                if differs {
+                    result.insert(id.clone(), node.clone());
+                }
+            }
+            Ok(result)
+        }
+
+        let mut db = Database::new();
+        db.template.dnodes = changed(&self.template.dnodes, &before.template.dnodes)?;
+        db.template.cnodes = changed(&self.template.cnodes, &before.template.cnodes)?;
+        db.template.versions = changed(&self.template.versions, &before.template.versions)?;
+        db.dnodes = changed(&self.dnodes, &before.dnodes)?;
+        db.cnodes = changed(&self.cnodes, &before.cnodes)?;
+        Ok(db)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::read_journal;
+    use crate::GraphrError;
+
+    /// Fresh path for a database file in the temp folder
+    fn path(name: &str) -> String {
+        let folder = std::env::temp_dir().join(format!("graphr-storage-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        folder.join(name).to_string_lossy().to_string()
+    }
+
+    /// Stored database with the calculation x0 -> y of template R
+    fn stored(path: &str) -> Box<dyn Storage> {
+        let mut db = Database::new();
+        db.template_register_dnode("x".to_string());
+        db.template_register_dnode("y".to_string());
+        db.template_register_cnode("R".to_string(), "cp input(x) output(y)".to_string()).unwrap();
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), "x0".to_string())]), None, None, None).unwrap()
+            .merge_into(&mut db).unwrap();
+        let mut storage = open_storage(path).unwrap();
+        storage.save(&mut db).unwrap();
+        storage
+    }
+
+    fn concurrent_changes_are_kept(path: &str) {
+        let mut storage = stored(path);
+        let mut first = storage.load().unwrap();
+        let mut second = storage.load().unwrap();
+        let calculation = first.cnodes.keys().next().unwrap().clone();
+
+        first.delete(vec![calculation.clone()]);
+        first.template_register_cnode("R".to_string(), "cp -v input(x) output(y)".to_string()).unwrap();
+        storage.save(&mut first).unwrap();
+        second.add_tags(vec!["checked".to_string()], Some(vec!["x0".to_string()])).unwrap();
+        storage.save(&mut second).unwrap();
+
+        let result = storage.load().unwrap();
+        assert!(!result.cnodes.contains_key(&calculation));
+        assert!(result.dnodes["x0"].tags.contains("checked"));
+        assert_eq!(result.template.cnodes["R"].command, first.template.cnodes["R"].command);
+        // The second writer goes on with the stored database, saving again is no conflict
+        assert_eq!(second.loaded_version, result.loaded_version);
+        assert_eq!(serde_json::to_string(&second).unwrap(), serde_json::to_string(&result).unwrap());
+        // The journal holds both writers and rebuilds the same database
+        let replayed = Database::replay_journal(&read_journal(&journal_path(path)).unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&replayed).unwrap(), serde_json::to_string(&result).unwrap());
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    fn undo_of_a_changed_database_fails(path: &str) {
+        let mut storage = stored(path);
+        let mut first = storage.load().unwrap();
+        let mut second = storage.load().unwrap();
+
+        first.add_tags(vec!["checked".to_string()], Some(vec!["x0".to_string()])).unwrap();
+        storage.save(&mut first).unwrap();
+        second.journal.push(JournalEntry::new(Operation::Undo));
+        assert!(matches!(storage.save(&mut second), Err(GraphrError::Journal(_))));
+        assert!(storage.load().unwrap().dnodes["x0"].tags.contains("checked"));
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn json_keeps_concurrent_changes() {
+        concurrent_changes_are_kept(&path("db.json"));
+    }
+
+    #[test]
+    fn sqlite_keeps_concurrent_changes() {
+        concurrent_changes_are_kept(&path("db.sqlite"));
+    }
+
+    #[test]
+    fn json_refuses_undo_of_a_changed_database() {
+        undo_of_a_changed_database_fails(&path("undo.json"));
+    }
+
+    #[test]
+    fn sqlite_refuses_undo_of_a_changed_database() {
+        undo_of_a_changed_database_fails(&path("undo.sqlite"));
+    }
+
+    #[test]
+    fn sqlite_only_writes_changed_rows() {
+        let path = path("changed.sqlite");
+        drop(stored(&path));
+        let mut storage = SqliteStorage::open(&path).unwrap();
+
+        let mut db = storage.load().unwrap();
+        db.add_tags(vec!["checked".to_string()], Some(vec!["x0".to_string()])).unwrap();
+        let changes = storage.conn.total_changes();
+        storage.save(&mut db).unwrap();
+        // The data node and the version
+        assert_eq!(storage.conn.total_changes() - changes, 2);
+
+        let calculation = db.cnodes.keys().next().unwrap().clone();
+        db.delete(vec![calculation.clone()]);
+        storage.save(&mut db).unwrap();
+        let stored = storage.load().unwrap();
+        assert!(stored.cnodes.is_empty());
+        assert_eq!(stored.dnodes.keys().collect::<Vec<_>>(), ["x0"]);
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn sqlite_journals_only_committed_changes() {
+        let path = path("failed.sqlite");
+        drop(stored(&path));
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let journal = read_journal(&journal_path(&path)).unwrap();
+        // Let the transaction fail after the rows are written
+        storage.conn.execute_batch("CREATE TRIGGER fail BEFORE UPDATE ON meta BEGIN SELECT RAISE(ABORT, 'read only'); END").unwrap();
+
+        let mut db = storage.load().unwrap();
+        db.add_tags(vec!["checked".to_string()], Some(vec!["x0".to_string()])).unwrap();
+        assert!(storage.save(&mut db).is_err());
+        assert!(storage.load().unwrap().dnodes["x0"].tags.is_empty());
+        assert_eq!(read_journal(&journal_path(&path)).unwrap().len(), journal.len());
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn neighbourhood_has_what_the_command_needs() {
+        let path = path("neighbourhood.sqlite");
+        let storage = stored(&path);
+        let db = storage.load().unwrap();
+        let calculation = db.cnodes.keys().next().unwrap().clone();
+
+        let neighbourhood = storage.neighbourhood(&calculation).unwrap().unwrap();
+        assert_eq!(neighbourhood.dnodes.len(), 2);
+        assert_eq!(neighbourhood.get_command(calculation.clone(), "data".to_string()).unwrap(),
+                   db.get_command(calculation, "data".to_string()).unwrap());
+        assert!(storage.neighbourhood("missing").unwrap().is_none());
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+}
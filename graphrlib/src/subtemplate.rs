@@ -0,0 +1,151 @@
+/*
+Parts of a template.
+The history of data templates is everything needed to produce them: the calculation that writes every needed data
+template, the calculations that modify it in place and, going upstream, everything these calculations read.
+A sub-template is the history of the given data templates, and creating a calculation from it only creates that part
+of the workflow.
+Data that exists already can be given to create_calculation instead of a leaf name. Its history is not calculated again,
+the template is cut there and only the calculations downstream of the existing data (and anything else the results
+need) are created. Calculations that modify the data in place are downstream of it, they make new revisions of the
+existing data node.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use crate::{CNodeTemplate, DNode, DatabaseTemplate, GraphrError, IdDTemplate, Result};
+
+
+impl DatabaseTemplate {
+
+    /// Template with the calculations needed for the data templates.
+    /// The templates in stop are taken as given: they are not produced again, but still modified in place.
+    fn history(&self, targets: &BTreeSet<&IdDTemplate>, stop: &BTreeSet<&IdDTemplate>) -> DatabaseTemplate {
+        let mut needed = BTreeSet::new();
+        let mut queue: Vec<&IdDTemplate> = targets.iter().copied().collect();
+        let mut cnodes = BTreeMap::new();
+        while let Some(d_template) = queue.pop() {
+            if !needed.insert(d_template) {
+                continue
+            }
+            let produced = !stop.contains(d_template);
+            let writers = self.cnodes.values()
+                .filter(|c| (produced && c.outcoming.contains(d_template)) || c.modified.contains(d_template));
+            for cnode in writers {
+                if cnodes.insert(cnode.id.clone(), cnode.clone()).is_none() {
+                    queue.extend(cnode.incoming.iter().chain(&cnode.modified));
+                }
+            }
+        }
+
+        let outputs: BTreeSet<&IdDTemplate> = cnodes.values().flat_map(|c| &c.outcoming).collect();
+        let dnodes = self.dnodes.iter()
+            .filter(|(id, _)| needed.contains(id) || outputs.contains(id))
+            .map(|(id, dnode)| (id.clone(), dnode.clone()))
+            .collect();
+        DatabaseTemplate { cnodes, dnodes, versions: BTreeMap::new() }
+    }
+
+    /// Only the part of the template needed to produce the data templates
+    pub(crate) fn history_of(&self, targets: &[IdDTemplate]) -> Result<DatabaseTemplate> {
+        if let Some(unknown) = targets.iter().find(|t| !self.dnodes.contains_key(*t)) {
+            return Err(GraphrError::UnknownNode(unknown.clone()))
+        }
+        Ok(self.history(&targets.iter().collect(), &BTreeSet::new()))
+    }
+
+    /// Part of the template that is left to calculate, when the data of the existing data templates is there.
+    /// These are the calculations that lead to the results of the template (data nothing reads) and the ones that
+    /// modify the existing data, except for the results that were produced on the way to the existing data.
+    pub(crate) fn cut(&self, existing: &BTreeMap<IdDTemplate, DNode>) -> Result<DatabaseTemplate> {
+        for (d_template, dnode) in existing {
+            if !self.dnodes.contains_key(d_template) {
+                return Err(GraphrError::UnknownNode(d_template.clone()))
+            }
+            if dnode.template != *d_template {
+                return Err(GraphrError::InvalidExisting(format!("data node '{}' is a '{}', not a '{}'", dnode.id, dnode.template, d_template)))
+            }
+        }
+        let stop: BTreeSet<&IdDTemplate> = existing.keys().collect();
+        // What led to the existing data: the calculations producing it and their history (modifications come after it)
+        let producers: Vec<&CNodeTemplate> = self.cnodes.values()
+            .filter(|c| c.outcoming.iter().any(|d| stop.contains(d)))
+            .collect();
+        let mut upstream = self.history(&producers.iter().flat_map(|c| c.incoming.iter().chain(&c.modified)).collect(), &BTreeSet::new());
+        upstream.cnodes.extend(producers.into_iter().map(|c| (c.id.clone(), c.clone())));
+
+        let read: BTreeSet<&IdDTemplate> = self.cnodes.values().flat_map(|c| &c.incoming).collect();
+        let results: BTreeSet<&IdDTemplate> = self.dnodes.keys()
+            .filter(|d| !read.contains(d) && !stop.contains(d))
+            .filter(|d| !upstream.cnodes.values().any(|c| c.outcoming.contains(*d) || c.modified.contains(*d)))
+            .collect();
+        let modified: BTreeSet<&IdDTemplate> = self.cnodes.values().flat_map(|c| &c.modified).filter(|d| stop.contains(d)).collect();
+        let cut = self.history(&(&results | &modified), &stop);
+
+        // The existing data would be produced again
+        for cnode in cut.cnodes.values() {
+            if let Some(d_template) = cnode.outcoming.iter().find(|d| stop.contains(d)) {
+                return Err(GraphrError::InvalidExisting(format!("'{}' is written by '{}', which is needed for other data", d_template, cnode.id)))
+            }
+        }
+        Ok(cut)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{CNode, Database, GraphrError, Result};
+    use std::collections::BTreeMap;
+
+    /// Database with the data and calculation templates (name, command)
+    fn database(data: &[&str], templates: &[(&str, &str)]) -> Database {
+        let mut db = Database::new();
+        for name in data {
+            db.template_register_dnode(name.to_string());
+        }
+        for (name, command) in templates {
+            db.template_register_cnode(name.to_string(), command.to_string()).unwrap();
+        }
+        db
+    }
+
+    fn create(db: &Database, existing: &[(&str, &str)]) -> Result<Database> {
+        let existing = existing.iter().map(|(template, id)| (template.to_string(), id.to_string())).collect();
+        db.template_create_calculation(BTreeMap::new(), Some(existing), None, None)
+    }
+
+    fn calculation<'a>(db: &'a Database, template: &str) -> &'a CNode {
+        db.cnodes.values().find(|c| c.template == template).unwrap()
+    }
+
+    #[test]
+    fn history_of_existing_data_is_left_out() {
+        let mut db = database(&["x", "y", "z"], &[("A", "cp input(x) output(y)"), ("B", "cp input(y) output(z)")]);
+        db.register_dnode("y".to_string(), Some("y0".to_string())).unwrap();
+
+        let created = create(&db, &[("y", "y0")]).unwrap();
+        assert_eq!(created.cnodes.len(), 1);
+        assert_eq!(calculation(&created, "B").incoming, ["y0"]);
+    }
+
+    #[test]
+    fn existing_data_is_still_modified() {
+        let mut db = database(&["x", "y"], &[("R", "cp input(x) output(y)"), ("M", "touch modify(x)")]);
+        db.register_dnode("x".to_string(), Some("x0".to_string())).unwrap();
+
+        let created = create(&db, &[("x", "x0")]).unwrap();
+        assert_eq!(created.cnodes.len(), 2);
+        let (from, to) = &calculation(&created, "M").modified[0];
+        assert_eq!(from, "x0");
+        assert_eq!(created.dnodes[to].revision_of.as_deref(), Some("x0"));
+        assert_eq!(created.dnodes[to].revision, 1);
+        // R does not depend on M, it reads the data before the modification
+        assert_eq!(calculation(&created, "R").incoming, ["x0"]);
+    }
+
+    #[test]
+    fn existing_data_is_not_produced_again() {
+        let mut db = database(&["x", "y", "z", "w"], &[("P", "split input(x) output(y) output(z)"), ("C", "cp input(z) output(w)")]);
+        db.register_dnode("y".to_string(), Some("y0".to_string())).unwrap();
+
+        assert!(matches!(create(&db, &[("y", "y0")]), Err(GraphrError::InvalidExisting(_))));
+    }
+}
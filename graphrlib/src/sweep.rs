@@ -0,0 +1,195 @@
+/*
+Sweeps: the template implemented for many leaf names and parameter values at once.
+Every leaf and every parameter gets a list of values. The combinations are the cartesian product of the lists
+(the last list changes fastest) or, zipped, the lists side by side. Every combination is created with create_calculation
+and merged into a single database, so calculations that several combinations share (the same template, inputs and
+parameter values) are only there once.
+The table of the sweep tells for every combination which data nodes it produced.
+*/
+use std::collections::BTreeMap;
+use pyo3::prelude::*;
+use crate::{Database, DatabaseTemplate, GraphrError, IdD, IdDTemplate, MetadataValue, ParamValues, Result};
+
+
+/// Row of the table of a sweep
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct Combination {
+    /// Names of the root data nodes
+    #[pyo3(get)]
+    pub leafs: BTreeMap<IdDTemplate, String>,
+    /// Values of the swept parameters (the others have their default)
+    #[pyo3(get)]
+    pub params: ParamValues,
+    /// Data produced for the combination, by data template (the last revision of data modified in place)
+    #[pyo3(get)]
+    pub outputs: BTreeMap<IdDTemplate, IdD>,
+}
+
+#[pymethods]
+impl Combination {
+    fn __str__(&self) -> String {
+        format!("Combination(leafs={:?}, params={:?}, outputs={:?})", self.leafs, self.params, self.outputs)
+    }
+}
+
+
+/// Index into every list for every combination
+fn combinations(lengths: &[usize], zip: bool) -> Result<Vec<Vec<usize>>> {
+    if zip {
+        let length = lengths.first().copied().unwrap_or(0);
+        if lengths.iter().any(|l| *l != length) {
+            return Err(GraphrError::InvalidSweep(format!("zipped lists need the same length, got {:?}", lengths)))
+        }
+        return Ok((0..length).map(|i| vec![i; lengths.len()]).collect())
+    }
+    let count: usize = lengths.iter().product();
+    Ok((0..count).map(|mut n| {
+        let mut indices = vec![0; lengths.len()];
+        for (index, length) in indices.iter_mut().zip(lengths).rev() {
+            *index = n % length;
+            n /= length;
+        }
+        indices
+    }).collect())
+}
+
+
+impl DatabaseTemplate {
+
+    /// Create the calculations of all combinations of the leaf names and parameter values as one database
+    pub(crate) fn sweep(&self, leafs: &BTreeMap<IdDTemplate, Vec<String>>, params: &BTreeMap<String, Vec<MetadataValue>>,
+                        zip: bool) -> Result<(Database, Vec<Combination>)> {
+        let lengths: Vec<usize> = leafs.values().map(|v| v.len()).chain(params.values().map(|v| v.len())).collect();
+        let mut database = Database { template: self.clone(), ..Database::default() };
+        let mut table = Vec::new();
+
+        for indices in combinations(&lengths, zip)? {
+            let (leaf_indices, param_indices) = indices.split_at(leafs.len());
+            let combination_leafs: BTreeMap<IdDTemplate, String> = leafs.iter().zip(leaf_indices)
+                .map(|((template, names), i)| (template.clone(), names[*i].clone()))
+                .collect();
+            let combination_params: ParamValues = params.iter().zip(param_indices)
+                .map(|((name, values), i)| (name.clone(), values[*i].clone()))
+                .collect();
+
+            let mut db = self.instantiate(combination_leafs.clone(), BTreeMap::new(), &combination_params)?;
+            // Merging renames the nodes to the ones already in the sweep
+            db.merge_into(&mut database)?;
+
+            let mut outputs: BTreeMap<IdDTemplate, (u32, IdD)> = BTreeMap::new();
+            for d_id in db.cnodes.values().flat_map(|c| c.outputs()) {
+                let dnode = &database.dnodes[d_id];
+                if outputs.get(&dnode.template).is_none_or(|(revision, _)| *revision < dnode.revision) {
+                    outputs.insert(dnode.template.clone(), (dnode.revision, d_id.clone()));
+                }
+            }
+            table.push(Combination {
+                leafs: combination_leafs,
+                params: combination_params,
+                outputs: outputs.into_iter().map(|(template, (_, d_id))| (template, d_id)).collect(),
+            });
+        }
+        // The sweep is a new database, the merges are not part of its history
+        database.journal.clear();
+        Ok((database, table))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::*;
+
+    /// Database with the templates (name, command) on the data templates
+    fn database(data: &[&str], templates: &[(&str, &str)]) -> Database {
+        let mut db = Database::new();
+        for name in data {
+            db.template_register_dnode(name.to_string());
+        }
+        for (name, command) in templates {
+            db.template_register_cnode(name.to_string(), command.to_string()).unwrap();
+        }
+        db
+    }
+
+    fn sweep(db: &Database, names: &[&str], values: &[i64], zip: bool) -> Result<(Database, Vec<Combination>)> {
+        let leafs = BTreeMap::from([("x".to_string(), names.iter().map(|n| n.to_string()).collect())]);
+        let params = BTreeMap::from([("n".to_string(), values.iter().map(|v| MetadataValue::Integer(*v)).collect())]);
+        db.template_create_sweep(leafs, Some(params), zip)
+    }
+
+    /// Leaf name and value of n of every combination
+    fn rows(table: &[Combination]) -> Vec<(String, MetadataValue)> {
+        table.iter().map(|c| (c.leafs["x"].clone(), c.params["n"].clone())).collect()
+    }
+
+    fn cut() -> Database {
+        database(&["x", "y"], &[("A", "cut -f param(n:int) input(x) > output(y)")])
+    }
+
+    #[test]
+    fn cartesian_product_changes_the_last_list_fastest() {
+        assert_eq!(combinations(&[2, 3], false).unwrap(), vec![vec![0, 0], vec![0, 1], vec![0, 2], vec![1, 0], vec![1, 1], vec![1, 2]]);
+
+        let (db, table) = sweep(&cut(), &["a", "b"], &[1, 2], false).unwrap();
+        let n = MetadataValue::Integer;
+        assert_eq!(rows(&table), vec![("a".to_string(), n(1)), ("a".to_string(), n(2)), ("b".to_string(), n(1)), ("b".to_string(), n(2))]);
+        assert_eq!(db.cnodes.len(), 4);
+    }
+
+    #[test]
+    fn zipped_lists_go_side_by_side() {
+        let (db, table) = sweep(&cut(), &["a", "b"], &[1, 2], true).unwrap();
+        assert_eq!(rows(&table), vec![("a".to_string(), MetadataValue::Integer(1)), ("b".to_string(), MetadataValue::Integer(2))]);
+        assert_eq!(db.cnodes.len(), 2);
+    }
+
+    #[test]
+    fn zipped_lists_need_the_same_length() {
+        assert!(matches!(sweep(&cut(), &["a", "b"], &[1], true), Err(GraphrError::InvalidSweep(_))));
+        // The cartesian product takes any lengths
+        assert_eq!(sweep(&cut(), &["a", "b"], &[1], false).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn shared_calculations_are_there_once() {
+        let db = database(&["x", "y", "z"], &[("A", "sort input(x) > output(y)"), ("B", "head -n param(n:int) input(y) > output(z)")]);
+        let (sweep, table) = sweep(&db, &["a"], &[1, 2, 3], false).unwrap();
+
+        assert_eq!(sweep.cnodes.values().filter(|c| c.template == "A").count(), 1);
+        assert_eq!(sweep.cnodes.values().filter(|c| c.template == "B").count(), 3);
+        // Every combination refers to the shared output, and to its own
+        assert!(table.iter().all(|c| c.outputs["y"] == table[0].outputs["y"]));
+        let own: BTreeSet<&IdD> = table.iter().map(|c| &c.outputs["z"]).collect();
+        assert_eq!(own.len(), 3);
+        assert!(sweep.journal.is_empty());
+    }
+
+    #[test]
+    fn outputs_are_the_last_revision() {
+        let db = database(&["x", "y"], &[("A", "sort input(x) > output(y)"), ("M", "echo param(n:int) >> modify(y)")]);
+        let (sweep, table) = sweep(&db, &["a"], &[1, 2], false).unwrap();
+
+        for combination in &table {
+            let output = &sweep.dnodes[&combination.outputs["y"]];
+            assert_eq!(output.revision, 1);
+            let modifier = sweep.cnodes.values().find(|c| c.modified.iter().any(|(_, to)| *to == output.id)).unwrap();
+            assert_eq!(modifier.params["n"], combination.params["n"]);
+        }
+    }
+
+    #[test]
+    fn sweep_is_registered_next_to_existing_calculations() {
+        let mut db = database(&["x", "y", "z"], &[("A", "sort input(x) > output(y)"), ("B", "head -n param(n:int) input(y) > output(z)")]);
+        let (first, _) = sweep(&db, &["a"], &[1], false).unwrap();
+        db.register_pipeline(first).unwrap();
+
+        let (second, _) = sweep(&db, &["a"], &[1, 2], false).unwrap();
+        let registered = db.register_pipeline(second).unwrap();
+        assert_eq!(db.cnodes.len(), 3);
+        assert_eq!(db.dnodes.len(), 4);
+        assert!(registered.cnodes.keys().all(|id| db.cnodes.contains_key(id)));
+    }
+}
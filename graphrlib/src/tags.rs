@@ -0,0 +1,229 @@
+/*
+Tags and metadata of data and calculation nodes.
+Tags are free-form labels (for example the experiment a calculation belongs to), metadata is a map of typed values.
+Merging a database unites both: a node gets the tags of both databases and the metadata keys of both.
+For a key that both have, the value of the incoming database wins, as it is the more recent one.
+Changing tags or metadata of a stored database has to be done on the stored database itself (see the CLI),
+merging can only add.
+*/
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use pyo3::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::{CNode, DNode, Database, GraphrError, Result};
+
+
+/// Value of a metadata entry. Converts to and from bool, int, float and str in python.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, FromPyObject, IntoPyObject)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataValue::Bool(value) => write!(f, "{}", value),
+            MetadataValue::Integer(value) => write!(f, "{}", value),
+            MetadataValue::Float(value) => write!(f, "{}", value),
+            MetadataValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl MetadataValue {
+    /// Value typed in on the command line: true/false, integers and floats are recognised, everything else is text
+    pub fn parse(text: &str) -> Self {
+        if let Ok(value) = text.parse::<bool>() {
+            MetadataValue::Bool(value)
+        } else if let Ok(value) = text.parse::<i64>() {
+            MetadataValue::Integer(value)
+        } else if let Ok(value) = text.parse::<f64>() {
+            MetadataValue::Float(value)
+        } else {
+            MetadataValue::Text(text.to_string())
+        }
+    }
+}
+
+pub type Metadata = BTreeMap<String, MetadataValue>;
+
+
+/// Nodes that carry tags and metadata
+pub(crate) trait Annotated {
+    fn annotations(&self) -> (&BTreeSet<String>, &Metadata);
+    fn annotations_mut(&mut self) -> (&mut BTreeSet<String>, &mut Metadata);
+
+    /// Union of the tags and metadata of both nodes, the values of other win
+    fn merge_annotations(&mut self, other: &Self) {
+        let (tags, metadata) = other.annotations();
+        let (own_tags, own_metadata) = self.annotations_mut();
+        own_tags.extend(tags.iter().cloned());
+        own_metadata.extend(metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+}
+
+impl Annotated for DNode {
+    fn annotations(&self) -> (&BTreeSet<String>, &Metadata) {
+        (&self.tags, &self.metadata)
+    }
+    fn annotations_mut(&mut self) -> (&mut BTreeSet<String>, &mut Metadata) {
+        (&mut self.tags, &mut self.metadata)
+    }
+}
+
+impl Annotated for CNode {
+    fn annotations(&self) -> (&BTreeSet<String>, &Metadata) {
+        (&self.tags, &self.metadata)
+    }
+    fn annotations_mut(&mut self) -> (&mut BTreeSet<String>, &mut Metadata) {
+        (&mut self.tags, &mut self.metadata)
+    }
+}
+
+
+impl Database {
+
+    /// Change the tags and metadata of the named nodes (all nodes if None).
+    /// Returns the names of the nodes, so that the change can be recorded.
+    pub(crate) fn annotate(&mut self, names: Option<Vec<String>>, change: impl Fn(&mut BTreeSet<String>, &mut Metadata)) -> Result<Vec<String>> {
+        let names = names.unwrap_or_else(|| self.cnodes.keys().chain(self.dnodes.keys()).cloned().collect());
+        if let Some(unknown) = names.iter().find(|n| !self.cnodes.contains_key(*n) && !self.dnodes.contains_key(*n)) {
+            return Err(GraphrError::UnknownNode(unknown.clone()))
+        }
+
+        for name in &names {
+            let (tags, metadata) = match self.cnodes.get_mut(name) {
+                Some(cnode) => cnode.annotations_mut(),
+                None => self.dnodes.get_mut(name).expect("Node was checked").annotations_mut(),
+            };
+            change(tags, metadata);
+        }
+        Ok(names)
+    }
+
+    /// Sub-database of the nodes whose tags and metadata are accepted
+    pub(crate) fn select_annotated(&self, accept: impl Fn(&BTreeSet<String>, &Metadata) -> bool) -> Database {
+        let mut db = self.generate_empty();
+        for (id, cnode) in &self.cnodes {
+            let (tags, metadata) = cnode.annotations();
+            if accept(tags, metadata) {
+                db.cnodes.insert(id.clone(), cnode.clone());
+            }
+        }
+        for (id, dnode) in &self.dnodes {
+            let (tags, metadata) = dnode.annotations();
+            if accept(tags, metadata) {
+                db.dnodes.insert(id.clone(), dnode.clone());
+            }
+        }
+        db
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Database with the calculation x0 -> y of template R
+    fn database() -> Database {
+        let mut db = Database::new();
+        db.template_register_dnode("x".to_string());
+        db.template_register_dnode("y".to_string());
+        db.template_register_cnode("R".to_string(), "cp input(x) output(y)".to_string()).unwrap();
+        db.template_create_calculation(BTreeMap::from([("x".to_string(), "x0".to_string())]), None, None, None).unwrap()
+            .merge_into(&mut db).unwrap();
+        db
+    }
+
+    fn tags(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn names(values: &[&str]) -> Option<Vec<String>> {
+        Some(values.iter().map(|n| n.to_string()).collect())
+    }
+
+    #[test]
+    fn tags_are_added_set_and_removed() {
+        let mut db = database();
+        let calculation = db.cnodes.keys().next().unwrap().clone();
+
+        db.add_tags(vec!["a".to_string(), "b".to_string()], names(&["x0"])).unwrap();
+        db.add_tags(vec!["c".to_string()], None).unwrap();
+        assert_eq!(db.dnodes["x0"].tags, tags(&["a", "b", "c"]));
+        assert_eq!(db.cnodes[&calculation].tags, tags(&["c"]));
+
+        db.set_tags(vec!["d".to_string()], names(&["x0"])).unwrap();
+        db.remove_tags(vec!["c".to_string(), "d".to_string()], None).unwrap();
+        assert!(db.dnodes.values().all(|d| d.tags.is_empty()));
+        assert!(db.cnodes[&calculation].tags.is_empty());
+    }
+
+    #[test]
+    fn metadata_is_set_and_removed() {
+        let mut db = database();
+        db.set_metadata("n".to_string(), MetadataValue::Integer(3), None).unwrap();
+        db.set_metadata("n".to_string(), MetadataValue::parse("three"), names(&["x0"])).unwrap();
+        assert_eq!(db.dnodes["x0"].metadata["n"], MetadataValue::Text("three".to_string()));
+        assert_eq!(db.select_metadata("n".to_string(), Some(MetadataValue::Integer(3))).dnodes.len(), db.dnodes.len() - 1);
+
+        db.remove_metadata("n".to_string(), names(&["x0"])).unwrap();
+        assert!(db.dnodes["x0"].metadata.is_empty());
+        assert_eq!(db.select_metadata("n".to_string(), None).cnodes.len(), 1);
+    }
+
+    #[test]
+    fn unknown_nodes_change_nothing() {
+        let mut db = database();
+        let journal = db.journal.len();
+        assert!(matches!(db.add_tags(vec!["a".to_string()], names(&["x0", "missing"])), Err(GraphrError::UnknownNode(n)) if n == "missing"));
+        assert!(matches!(db.set_metadata("n".to_string(), MetadataValue::Bool(true), names(&["missing"])), Err(GraphrError::UnknownNode(_))));
+        assert!(db.dnodes["x0"].tags.is_empty());
+        assert_eq!(db.journal.len(), journal);
+    }
+
+    #[test]
+    fn merging_unites_the_annotations() {
+        let mut db = database();
+        let mut other = db.clone();
+        db.add_tags(vec!["a".to_string()], names(&["x0"])).unwrap();
+        db.set_metadata("n".to_string(), MetadataValue::Integer(1), names(&["x0"])).unwrap();
+        other.add_tags(vec!["b".to_string()], names(&["x0"])).unwrap();
+        other.set_metadata("n".to_string(), MetadataValue::Integer(2), names(&["x0"])).unwrap();
+
+        other.merge_into(&mut db).unwrap();
+        assert_eq!(db.dnodes["x0"].tags, tags(&["a", "b"]));
+        assert_eq!(db.dnodes["x0"].metadata["n"], MetadataValue::Integer(2));
+    }
+
+    #[test]
+    fn annotations_are_replayed_from_the_journal() {
+        let mut db = database();
+        let stored = db.clone();
+        let start = db.journal.len();
+        db.add_tags(vec!["a".to_string(), "b".to_string()], None).unwrap();
+        db.set_tags(vec!["c".to_string()], names(&["x0"])).unwrap();
+        db.remove_tags(vec!["a".to_string()], None).unwrap();
+        db.set_metadata("n".to_string(), MetadataValue::Float(0.5), None).unwrap();
+        db.remove_metadata("n".to_string(), names(&["x0"])).unwrap();
+
+        let expected = {
+            let mut db = db.clone();
+            db.journal.clear();
+            serde_json::to_string(&db).unwrap()
+        };
+        let replayed = Database::replay_journal(&db.journal).unwrap();
+        assert_eq!(serde_json::to_string(&replayed).unwrap(), expected);
+
+        // The same operations on another copy of the database
+        let mut copy = stored;
+        copy.apply_journal(&db.journal[start..]).unwrap();
+        copy.journal.clear();
+        assert_eq!(serde_json::to_string(&copy).unwrap(), expected);
+    }
+}
@@ -0,0 +1,175 @@
+/*
+Versions of calculation templates.
+A calculation template is identified by its name and its version, a hash of the command with its input, output and modify
+markers (so the data it reads and writes is part of the version).
+The current version of a name is stored under the name itself. That is the version create_calculation implements and
+the template graph is made of. Earlier versions are kept as name@version, and the calculations made with them point there,
+so that every calculation keeps the command it was made with.
+Registering a different command under an existing name, or merging a database that has one, makes it the current version.
+*/
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use crate::{CNode, CNodeTemplate, Database, DatabaseTemplate, GraphrError, IdC, IdCTemplate, Node, Result};
+
+/// Separates the name and the version in the id of an earlier version
+const VERSION_SEPARATOR: char = '@';
+
+
+/// Name of a calculation template id (the id without the version)
+pub(crate) fn template_name(id: &str) -> &str {
+    id.split_once(VERSION_SEPARATOR).map_or(id, |(name, _)| name)
+}
+
+/// Id under which an earlier version of a template is kept
+fn versioned_id(name: &str, version: &str) -> IdCTemplate {
+    format!("{}{}{}", name, VERSION_SEPARATOR, version)
+}
+
+/// Names of templates can not contain the version separator
+pub(crate) fn check_template_name(name: &str) -> Result<()> {
+    if name.contains(VERSION_SEPARATOR) {
+        return Err(GraphrError::ParseError(format!("Template name '{}' can not contain '{}'", name, VERSION_SEPARATOR)))
+    }
+    Ok(())
+}
+
+/// Point the calculations at the renamed templates
+fn repoint(cnodes: &mut BTreeMap<IdC, CNode>, renames: &BTreeMap<IdCTemplate, IdCTemplate>) {
+    for cnode in cnodes.values_mut() {
+        if let Some(id) = renames.get(&cnode.template) {
+            cnode.template = id.clone();
+        }
+    }
+}
+
+
+impl CNodeTemplate {
+
+    /// Name shared by all versions of the template
+    pub fn name(&self) -> &str {
+        template_name(&self.id)
+    }
+
+    /// Content hash of the template (first 8 hex digits)
+    pub fn version(&self) -> String {
+        format!("{:x}", Sha256::digest(self.marked_command().as_bytes()))[..8].to_string()
+    }
+}
+
+
+impl DatabaseTemplate {
+
+    /// Calculation template with the id, the current version or an earlier one
+    pub fn get_version(&self, id: &str) -> Option<&CNodeTemplate> {
+        self.cnodes.get(id).or_else(|| self.versions.get(id))
+    }
+
+    /// All versions of the named calculation template, the current one first
+    pub fn template_versions(&self, name: &str) -> Vec<&CNodeTemplate> {
+        let earlier = self.versions.values().filter(|c| c.name() == name);
+        self.cnodes.get(name).into_iter().chain(earlier).collect()
+    }
+
+    /// Id under which the version of the named template is stored
+    pub(crate) fn find_version(&self, name: &str, version: &str) -> Option<IdCTemplate> {
+        if self.cnodes.get(name).is_some_and(|c| c.version() == version) {
+            return Some(name.to_string())
+        }
+        let id = versioned_id(name, version);
+        self.versions.contains_key(&id).then_some(id)
+    }
+
+    /// Make the calculation template the current version of its name, the replaced version is kept as name@version.
+    /// Returns the template ids that changed, so that the calculations can follow.
+    pub(crate) fn install(&mut self, cnode: CNodeTemplate) -> BTreeMap<IdCTemplate, IdCTemplate> {
+        let name = cnode.name().to_string();
+        let version = cnode.version();
+        let mut renames = BTreeMap::new();
+        if let Some(current) = self.cnodes.get(&name)
+            && current.version() != version {
+            renames.insert(name.clone(), versioned_id(&name, &current.version()));
+        }
+        let earlier = versioned_id(&name, &version);
+        if self.versions.contains_key(&earlier) {
+            renames.insert(earlier, name.clone());
+        }
+        self.rekey(&renames);
+        self.cnodes.insert(name.clone(), CNodeTemplate { id: name, ..cnode });
+        renames
+    }
+
+    /// Change the ids of calculation templates. Ids with a version are earlier versions, the others current ones.
+    fn rekey(&mut self, renames: &BTreeMap<IdCTemplate, IdCTemplate>) {
+        if renames.iter().all(|(from, to)| from == to) {
+            return
+        }
+        let cnodes = std::mem::take(&mut self.cnodes);
+        let versions = std::mem::take(&mut self.versions);
+        for mut cnode in cnodes.into_values().chain(versions.into_values()) {
+            if let Some(id) = renames.get(&cnode.id) {
+                cnode.id = id.clone();
+            }
+            let target = if cnode.id.contains(VERSION_SEPARATOR) { &mut self.versions } else { &mut self.cnodes };
+            target.insert(cnode.id.clone(), cnode);
+        }
+    }
+}
+
+
+impl Database {
+
+    /// Make the calculation template the current version of its name.
+    /// Calculations of the replaced version keep pointing at it.
+    pub(crate) fn install_template(&mut self, cnode: CNodeTemplate) {
+        let renames = self.template.install(cnode);
+        repoint(&mut self.cnodes, &renames);
+    }
+
+    /// Change the ids of calculation templates and point the calculations at the new ids
+    pub(crate) fn rename_templates(&mut self, renames: &BTreeMap<IdCTemplate, IdCTemplate>) {
+        self.template.rekey(renames);
+        repoint(&mut self.cnodes, renames);
+    }
+
+    /// Add the calculation templates of another database. Versions that are not known yet are kept,
+    /// and the current versions of the other database become the current ones (the incoming templates win).
+    /// Returns the ids the templates of the other database have in this one.
+    pub(crate) fn merge_templates(&mut self, other: &DatabaseTemplate) -> BTreeMap<IdCTemplate, IdCTemplate> {
+        for earlier in other.versions.values() {
+            if self.template.find_version(earlier.name(), &earlier.version()).is_none() {
+                self.template.versions.insert(earlier.id.clone(), earlier.clone());
+            }
+        }
+        for current in other.cnodes.values() {
+            self.install_template(current.clone());
+        }
+        other.cnodes.values().chain(other.versions.values())
+            .map(|c| (c.id.clone(), self.template.find_version(c.name(), &c.version()).expect("All versions were added")))
+            .collect()
+    }
+
+    /// Ids of the calculation templates of this database in the global one.
+    /// A version the global database does not know keeps its id, unless the global database uses the id for another version.
+    pub(crate) fn match_templates(&self, global_db: &Database) -> BTreeMap<IdCTemplate, IdCTemplate> {
+        let mut ids = BTreeMap::new();
+        for cnode in self.template.cnodes.values().chain(self.template.versions.values()) {
+            let id = match global_db.template.find_version(cnode.name(), &cnode.version()) {
+                Some(id) => id,
+                None if global_db.template.get_version(&cnode.id).is_some() => versioned_id(cnode.name(), &cnode.version()),
+                None => {
+                    eprintln!("Warning: Template '{}' not found in global database", cnode.id);
+                    cnode.id.clone()
+                }
+            };
+            ids.insert(cnode.id.clone(), id);
+        }
+        ids
+    }
+
+    /// Select all nodes of the named template, whatever version of the template they were made with
+    pub(crate) fn similar_versions(&self, name: &str) -> Vec<Node> {
+        let cnodes = self.cnodes.values().filter(|c| template_name(&c.template) == name).map(|c| Node::Calculation(c.clone()));
+        let dnodes = self.dnodes.values().filter(|d| d.template == name).map(|d| Node::Data(d.clone()));
+        cnodes.chain(dnodes).collect()
+    }
+}